@@ -0,0 +1,63 @@
+//! Blocking adapter for the code that isn't written for async
+//!
+//! Some libraries accept a resolve function in the spirit of
+//! `ToSocketAddrs`, i.e. a function that blocks until name is resolved.
+//! This module wraps the router into such a function.
+//!
+//! **Warning:** functions here block current thread until name is
+//! resolved, and resolution happens on the thread running the reactor of
+//! the router. So never call them on the reactor thread, as this will
+//! deadlock.
+//!
+//! This module is only available with the `blocking` feature enabled.
+use std::io;
+use std::net::SocketAddr;
+
+use abstract_ns::Error;
+use futures::Future;
+
+use router::Router;
+
+
+/// A type of function returned by `resolve_fn`
+pub type ResolveFn =
+    Box<Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync>;
+
+/// Resolve a name blocking current thread
+///
+/// Name is parsed as in `Router::resolve_auto`. Addresses are returned
+/// in the order of priority.
+///
+/// **Warning:** must not be called on the reactor thread, see module
+/// description.
+pub fn resolve(router: &Router, name: &str, default_port: u16)
+    -> io::Result<Vec<SocketAddr>>
+{
+    let addr = router.resolve_auto(name, default_port).wait()
+        .map_err(convert_error)?;
+    let mut result = Vec::new();
+    for set in addr.iter() {
+        result.extend(set.addresses());
+    }
+    Ok(result)
+}
+
+/// Wrap router into a blocking resolve function
+///
+/// See `resolve` for the description of the function.
+///
+/// **Warning:** resulting function must not be called on the reactor
+/// thread, see module description.
+pub fn resolve_fn(router: &Router, default_port: u16) -> ResolveFn {
+    let router = router.clone();
+    Box::new(move |name| resolve(&router, name, default_port))
+}
+
+fn convert_error(e: Error) -> io::Error {
+    let kind = match e {
+        Error::InvalidName(..) => io::ErrorKind::InvalidInput,
+        Error::NameNotFound => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e.to_string())
+}
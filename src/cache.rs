@@ -0,0 +1,161 @@
+//! Cache of resolved values which also coalesces identical requests
+//!
+//! Enabled by `Config::positive_cache_ttl`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use abstract_ns::{Name, Error};
+use futures::{Future, Async};
+use futures::sync::oneshot;
+use void::Void;
+
+use coroutine::FutureResult;
+use internal::{reply, fail, clone_error};
+
+
+type ReplyTx<T> = oneshot::Sender<Result<T, Error>>;
+type Waiters<T> = Rc<RefCell<Vec<ReplyTx<T>>>>;
+
+enum Entry<T> {
+    InFlight(Waiters<T>),
+    Ready { value: T, expire: Instant },
+}
+
+pub(crate) struct Cache<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    /// Incremented on each `clear`, so results of requests started
+    /// before that are not stored
+    generation: u64,
+}
+
+pub(crate) type SharedCache<K, T> = Rc<RefCell<Cache<K, T>>>;
+
+/// Waits for the reply and sends it to all requests of the same key
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Coalesce<K, T> {
+    name: Name,
+    key: K,
+    ttl: Duration,
+    generation: u64,
+    cache: SharedCache<K, T>,
+    waiters: Waiters<T>,
+    rx: oneshot::Receiver<Result<T, Error>>,
+}
+
+impl<K: Hash + Eq, T> Default for Cache<K, T> {
+    fn default() -> Cache<K, T> {
+        Cache {
+            entries: HashMap::new(),
+            generation: 0,
+        }
+    }
+}
+
+impl<K: fmt::Debug, T> fmt::Debug for Cache<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cache")
+        .field("keys", &self.entries.keys().collect::<Vec<_>>())
+        .field("generation", &self.generation)
+        .finish()
+    }
+}
+
+impl<K: Hash + Eq, T> Cache<K, T> {
+    /// Drops all cached values
+    ///
+    /// Requests in flight still reply to their waiters, but their results
+    /// are not cached.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.generation += 1;
+    }
+}
+
+/// Replies from the cache or registers a request in flight
+///
+/// Returns `None` if request is replied or attached to the request in
+/// flight. Otherwise the request must be sent to the resolver using the
+/// returned sender, and the returned future must be spawned.
+pub(crate) fn lookup<K, T>(cache: &SharedCache<K, T>,
+    name: &Name, key: K, ttl: Duration,
+    tx: ReplyTx<T>)
+    -> Option<(ReplyTx<T>, Coalesce<K, T>)>
+    where K: Hash + Eq + Clone,
+          T: Clone + Send + fmt::Debug + 'static,
+{
+    let mut cache_ref = cache.borrow_mut();
+    let expired = match cache_ref.entries.get(&key) {
+        Some(&Entry::Ready { ref value, expire }) => {
+            if expire > Instant::now() {
+                reply(name, tx, value.clone());
+                return None;
+            }
+            true
+        }
+        Some(Entry::InFlight(waiters)) => {
+            waiters.borrow_mut().push(tx);
+            return None;
+        }
+        None => false,
+    };
+    if expired {
+        cache_ref.entries.remove(&key);
+    }
+    let waiters = Rc::new(RefCell::new(vec![tx]));
+    cache_ref.entries.insert(key.clone(), Entry::InFlight(waiters.clone()));
+    let (itx, irx) = oneshot::channel();
+    let future = Coalesce {
+        name: name.clone(),
+        key, ttl, waiters,
+        generation: cache_ref.generation,
+        cache: cache.clone(),
+        rx: irx,
+    };
+    Some((itx, future))
+}
+
+impl<K, T> Future for Coalesce<K, T>
+    where K: Hash + Eq + Clone,
+          T: Clone + Send + fmt::Debug + 'static,
+{
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        {
+            let mut cache = self.cache.borrow_mut();
+            if cache.generation == self.generation {
+                match result {
+                    Ok(ref value) if self.ttl > Duration::new(0, 0) => {
+                        let entry = Entry::Ready {
+                            value: value.clone(),
+                            expire: Instant::now() + self.ttl,
+                        };
+                        cache.entries.insert(self.key.clone(), entry);
+                    }
+                    // errors are not cached, zero ttl only coalesces
+                    _ => {
+                        cache.entries.remove(&self.key);
+                    }
+                }
+            }
+        }
+        let waiters = mem::take(&mut *self.waiters.borrow_mut());
+        for tx in waiters {
+            match result {
+                Ok(ref value) => reply(&self.name, tx, value.clone()),
+                Err(ref e) => fail(&self.name, tx, clone_error(e)),
+            }
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
@@ -0,0 +1,126 @@
+//! Explicit cancellation of subscriptions
+//!
+//! See `Router::subscribe_cancelable`.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use abstract_ns::Name;
+use futures::{Future, Async};
+use futures::sync::mpsc::UnboundedSender;
+use futures::unsync::oneshot;
+use void::Void;
+
+use config::Config;
+use coroutine::{ResolverFuture, FutureResult, Continuation};
+use internal::Request;
+
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies tasks that belong to a single cancelable subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct SubscriptionId(usize);
+
+/// A guard which cancels the subscription when dropped
+///
+/// Returned from `Router::subscribe_cancelable` and
+/// `Router::subscribe_many_cancelable`. When the subscription is
+/// canceled, the router drops all its tasks (and so streams of the
+/// underlying resolvers) on the next iteration, and the subscription
+/// stream is finished.
+#[derive(Debug)]
+pub struct Subscription {
+    id: SubscriptionId,
+    requests: UnboundedSender<Request>,
+}
+
+/// Drops the subscription task when the subscription is canceled
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Cancelable<F> {
+    id: SubscriptionId,
+    future: F,
+    canceled: oneshot::Receiver<()>,
+}
+
+/// Restarts the task in the scope of its subscription
+///
+/// So tasks spawned on restart can be canceled too.
+#[derive(Debug)]
+struct Scoped {
+    id: SubscriptionId,
+    task: Box<Continuation>,
+}
+
+impl SubscriptionId {
+    pub fn new() -> SubscriptionId {
+        SubscriptionId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Subscription {
+    pub(crate) fn new(id: SubscriptionId, requests: &UnboundedSender<Request>)
+        -> Subscription
+    {
+        Subscription { id, requests: requests.clone() }
+    }
+    /// Cancels the subscription
+    ///
+    /// This is the same as dropping the guard, but makes intention clear.
+    pub fn cancel(self) {}
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // nothing to cancel if router is already shut down
+        self.requests.unbounded_send(Request::Cancel(self.id)).ok();
+    }
+}
+
+impl<F> Cancelable<F> {
+    pub fn new(id: SubscriptionId, future: F,
+        canceled: oneshot::Receiver<()>)
+        -> Cancelable<F>
+    {
+        Cancelable { id, future, canceled }
+    }
+}
+
+impl<F> Future for Cancelable<F>
+    where F: Future<Item=FutureResult, Error=Void>,
+{
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        use coroutine::FutureResult::*;
+        match self.canceled.poll() {
+            Ok(Async::NotReady) => {}
+            // sender is dropped when subscription is canceled, dropping
+            // the future closes the underlying stream
+            _ => return Ok(Async::Ready(Done)),
+        }
+        let result = match self.future.poll()? {
+            Async::Ready(result) => result,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        let result = match result {
+            Restart { task } => Restart {
+                task: Box::new(Scoped { id: self.id, task }),
+            },
+            DelayRestart { task } => DelayRestart {
+                task: Box::new(Scoped { id: self.id, task }),
+            },
+            other => other,
+        };
+        Ok(Async::Ready(result))
+    }
+}
+
+impl Continuation for Scoped {
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let task = &mut self.task;
+        res.in_scope(self.id, |res| task.restart(res, cfg));
+    }
+    fn name(&self) -> Option<&Name> {
+        self.task.name()
+    }
+}
@@ -0,0 +1,24 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use config::Config;
+
+
+/// Holds the config currently used by the router
+///
+/// It's updated by the `ResolverFuture` and read by `UpdateSink`, so that
+/// the config can be inspected without sending a request to the router.
+/// Config isn't `Send` (resolvers may be bound to the thread), so the cell
+/// is only shared with things living on the router's thread.
+#[derive(Debug, Default)]
+pub(crate) struct ConfigCell(RefCell<Option<Arc<Config>>>);
+
+impl ConfigCell {
+    pub fn put(&self, cfg: &Arc<Config>) {
+        *self.0.borrow_mut() = Some(cfg.clone());
+    }
+    /// Returns `None` until the first config is received
+    pub fn get(&self) -> Option<Arc<Config>> {
+        self.0.borrow().clone()
+    }
+}
@@ -0,0 +1,297 @@
+use std::fmt;
+use std::sync::Arc;
+
+use abstract_ns::{Name, Address, IpList, Error};
+use slot;
+use futures::{Future, Stream, Async};
+use futures::sync::oneshot;
+use void::Void;
+
+use config::Config;
+use coroutine::{ResolverFuture, FutureResult, Continuation, get_suffix};
+use internal::{reply, fail};
+use internal_traits::Resolver;
+use subscr::{Task, TaskResult, SubscrFuture};
+
+
+/// Fallthrough resolver built by `Config::push_fallthrough`
+///
+/// Resolvers are tried in order, the next one is used only if previous
+/// one returns `Error::NameNotFound`.
+#[derive(Debug)]
+pub(crate) struct ChainResolver {
+    resolvers: Vec<Arc<Resolver>>,
+}
+
+/// Waits for a resolver in chain and advances to the next on
+/// `NameNotFound`
+#[must_use = "futures do nothing unless polled"]
+struct ChainStep<T, F> {
+    rx: oneshot::Receiver<Result<T, Error>>,
+    next: Option<Next<T, F>>,
+}
+
+struct Next<T, F> {
+    resolvers: Vec<Arc<Resolver>>,
+    name: Name,
+    tx: oneshot::Sender<Result<T, Error>>,
+    func: F,
+}
+
+/// Subscription which follows the first resolver in chain that has
+/// non-empty address
+pub(crate) struct ChainSubscr<T> {
+    name: Name,
+    chain: Arc<Resolver>,
+    sources: Vec<Option<slot::Receiver<T>>>,
+    values: Vec<Option<T>>,
+    last: Option<T>,
+    tx: slot::Sender<T>,
+}
+
+/// Value which can be received from a chained subscription
+pub(crate) trait ChainValue: Clone + PartialEq + Sized + 'static {
+    fn is_empty(&self) -> bool;
+    fn subscribe(resolver: &Arc<Resolver>, res: &mut ResolverFuture,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<Self>);
+}
+
+impl ChainValue for Address {
+    fn is_empty(&self) -> bool {
+        self.iter().all(|set| set.addresses().next().is_none())
+    }
+    fn subscribe(resolver: &Arc<Resolver>, res: &mut ResolverFuture,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<Address>)
+    {
+        resolver.subscribe(res, sub, cfg, name, tx)
+    }
+}
+
+impl ChainValue for IpList {
+    fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+    fn subscribe(resolver: &Arc<Resolver>, res: &mut ResolverFuture,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<IpList>)
+    {
+        resolver.host_subscribe(res, sub, cfg, name, tx)
+    }
+}
+
+impl ChainResolver {
+    pub fn new(resolvers: Vec<Arc<Resolver>>) -> ChainResolver {
+        ChainResolver { resolvers }
+    }
+    fn start<T, F>(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<T, Error>>, func: F)
+        where T: Send + fmt::Debug + 'static,
+              F: Fn(&Arc<Resolver>, &mut ResolverFuture, &Arc<Config>,
+                    Name, oneshot::Sender<Result<T, Error>>),
+              F: Clone + 'static,
+    {
+        try_next(res, cfg, Next {
+            resolvers: self.resolvers.clone(),
+            name, tx, func,
+        })
+    }
+    fn subscribe_all<T: ChainValue>(&self, res: &mut ResolverFuture,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<T>)
+    {
+        let mut sources = Vec::with_capacity(self.resolvers.len());
+        for resolver in &self.resolvers {
+            let (itx, irx) = slot::channel();
+            // resolvers in chain are subscribed on behalf of the chain, so
+            // they follow config updates the same way the chain does
+            T::subscribe(resolver, res, sub, cfg, name.clone(), itx);
+            sources.push(Some(irx));
+        }
+        SubscrFuture::spawn_in(res, ChainSubscr {
+            name, tx,
+            chain: sub.clone(),
+            values: sources.iter().map(|_| None).collect(),
+            sources,
+            last: None,
+        });
+    }
+}
+
+fn try_next<T, F>(res: &mut ResolverFuture, cfg: &Arc<Config>,
+    mut next: Next<T, F>)
+    where T: Send + fmt::Debug + 'static,
+          F: Fn(&Arc<Resolver>, &mut ResolverFuture, &Arc<Config>,
+                Name, oneshot::Sender<Result<T, Error>>),
+          F: Clone + 'static,
+{
+    let resolver = next.resolvers.remove(0);
+    if next.resolvers.is_empty() {
+        (next.func)(&resolver, res, cfg, next.name, next.tx);
+        return;
+    }
+    let (itx, irx) = oneshot::channel();
+    (next.func)(&resolver, res, cfg, next.name.clone(), itx);
+    res.spawn(ChainStep { rx: irx, next: Some(next) });
+}
+
+impl Resolver for ChainResolver {
+    fn describe(&self) -> Option<String> {
+        let items = self.resolvers.iter()
+            .filter_map(|r| r.describe())
+            .collect::<Vec<_>>();
+        Some(format!("Chain({})", items.join(", ")))
+    }
+    fn warmup(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
+        suffix: &str)
+    {
+        for resolver in &self.resolvers {
+            resolver.warmup(res, cfg, suffix);
+        }
+    }
+    fn resolve_host(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<IpList, Error>>)
+    {
+        self.start(res, cfg, name, tx, |r, res, cfg, name, tx| {
+            r.resolve_host(res, cfg, name, tx)
+        })
+    }
+    fn resolve_host_port(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
+        name: Name, port: u16, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        self.start(res, cfg, name, tx, move |r, res, cfg, name, tx| {
+            r.resolve_host_port(res, cfg, name, port, tx)
+        })
+    }
+    fn resolve(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        self.start(res, cfg, name, tx, |r, res, cfg, name, tx| {
+            r.resolve(res, cfg, name, tx)
+        })
+    }
+    fn host_subscribe(&self, res: &mut ResolverFuture,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<IpList>)
+    {
+        self.subscribe_all(res, sub, cfg, name, tx)
+    }
+    fn subscribe(&self, res: &mut ResolverFuture,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<Address>)
+    {
+        self.subscribe_all(res, sub, cfg, name, tx)
+    }
+}
+
+impl<T, F> fmt::Debug for Next<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Next")
+        .field("name", &self.name)
+        .field("resolvers", &self.resolvers)
+        .finish()
+    }
+}
+
+impl<T, F> Continuation for Option<Next<T, F>>
+    where T: Send + fmt::Debug + 'static,
+          F: Fn(&Arc<Resolver>, &mut ResolverFuture, &Arc<Config>,
+                Name, oneshot::Sender<Result<T, Error>>),
+          F: Clone + 'static,
+{
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        try_next(res, cfg, self.take().expect("continuation called twice"))
+    }
+    fn name(&self) -> Option<&Name> {
+        self.as_ref().map(|next| &next.name)
+    }
+}
+
+impl<T, F> Future for ChainStep<T, F>
+    where T: Send + fmt::Debug + 'static,
+          F: Fn(&Arc<Resolver>, &mut ResolverFuture, &Arc<Config>,
+                Name, oneshot::Sender<Result<T, Error>>),
+          F: Clone + 'static,
+{
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        let next = self.next.take().expect("future polled twice");
+        match result {
+            Ok(value) => reply(&next.name, next.tx, value),
+            Err(Error::NameNotFound) => {
+                debug!("Name {:?} is not found, trying next resolver",
+                    next.name);
+                return Ok(Async::Ready(FutureResult::Restart {
+                    task: Box::new(Some(next)),
+                }));
+            }
+            Err(e) => fail(&next.name, next.tx, e),
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
+
+impl<T: ChainValue> ChainSubscr<T> {
+    fn current(&self) -> Option<&T> {
+        self.values.iter()
+            .filter_map(|v| v.as_ref())
+            .find(|v| !v.is_empty())
+            .or_else(|| self.values.iter().filter_map(|v| v.as_ref()).next())
+    }
+}
+
+impl<T: ChainValue> Task for ChainSubscr<T> {
+    fn restart(self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let nsub = get_suffix(cfg, &self.name);
+        if !Arc::ptr_eq(nsub, &self.chain) {
+            // subscriptions of the old chain are dropped with `self`
+            T::subscribe(nsub, res, nsub, cfg, self.name, self.tx);
+        } else {
+            SubscrFuture::spawn_in(res, self)
+        }
+    }
+    fn poll(&mut self) -> TaskResult {
+        for (source, value) in
+            self.sources.iter_mut().zip(self.values.iter_mut())
+        {
+            let done = match *source {
+                Some(ref mut source) => loop {
+                    match source.poll() {
+                        Ok(Async::Ready(Some(x))) => *value = Some(x),
+                        Ok(Async::Ready(None)) | Err(()) => break true,
+                        Ok(Async::NotReady) => break false,
+                    }
+                },
+                None => false,
+            };
+            if done {
+                *source = None;
+                *value = None;
+            }
+        }
+        let current = self.current().cloned();
+        if let Some(ref value) = current {
+            if current != self.last {
+                self.last = current.clone();
+                if self.tx.swap(value.clone()).is_err() {
+                    return TaskResult::Stop;
+                }
+            }
+        }
+        match self.tx.poll_cancel() {
+            Ok(Async::NotReady) => {}
+            _ => return TaskResult::Stop,
+        }
+        TaskResult::Continue
+    }
+    fn name(&self) -> Option<&Name> {
+        Some(&self.name)
+    }
+}
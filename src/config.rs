@@ -1,11 +1,18 @@
-use std::collections::HashMap;
-use std::fmt::Debug;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use abstract_ns::{Name, Address, IpList};
+use abstract_ns::addr::Builder;
 use abstract_ns::{HostResolve, Resolve, HostSubscribe, Subscribe};
+use chain::ChainResolver;
 use internal_traits::{Resolver, Wrapper, NullResolver};
+use eq::AddressEqMode;
+use metrics::Metrics;
+use middleware::ResolveMiddleware;
+use timer::Timer;
 
 
 /// Configuration of the router
@@ -16,32 +23,276 @@ use internal_traits::{Resolver, Wrapper, NullResolver};
 pub struct Config {
     pub(crate) restart_delay: Duration,
     pub(crate) convergence_delay: Duration,
+    /// Whether delays were set explicitly (used by `merge`)
+    restart_delay_set: bool,
+    convergence_delay_set: bool,
+    pub(crate) seed_grace_period: Duration,
+    pub(crate) emit_empty_for_empty_list: bool,
+    pub(crate) address_eq: AddressEqMode,
+    pub(crate) normalize_mapped_v4: bool,
     pub(crate) hosts: HashMap<Name, IpList>,
     pub(crate) services: HashMap<Name, Address>,
-    pub(crate) suffixes: HashMap<String, Arc<Resolver>>,
+    weights: HashMap<Name, HashMap<SocketAddr, u16>>,
+    pub(crate) suffixes: HashMap<String, Suffix>,
+    pub(crate) prefixes: HashMap<String, Suffix>,
+    pub(crate) names: HashMap<Name, Suffix>,
+    pub(crate) disabled_suffixes: HashSet<String>,
     pub(crate) root: Arc<Resolver>,
+    root_chain: Vec<Arc<Resolver>>,
+    pub(crate) root_opts: SuffixOpts,
+    pub(crate) label: Option<String>,
+    pub(crate) fallthrough_on_temporary: bool,
+    pub(crate) middleware: Vec<Arc<ResolveMiddleware>>,
+    pub(crate) track_latency: bool,
+    pub(crate) warmup_on_start: bool,
+    pub(crate) skip_equal_updates: bool,
+    pub(crate) poll_fairness: Option<usize>,
+    pub(crate) max_resolution_depth: usize,
+    pub(crate) ports: HashMap<String, u16>,
+    pub(crate) idna_names: bool,
+    pub(crate) on_apply: Option<ApplyCallback>,
+    pub(crate) rewriter: Option<AddressRewriter>,
+    pub(crate) timer: Option<Arc<Timer>>,
+    pub(crate) metrics: Option<Arc<Metrics>>,
+    pub(crate) negative_cache_ttl: Option<Duration>,
+    pub(crate) positive_cache_ttl: Option<Duration>,
+}
+
+/// Configs are equal if they produce the same routing
+///
+/// Resolvers, middleware and callbacks are compared by identity (i.e.
+/// `Arc::ptr_eq`), so configs are only equal if resolvers are shared
+/// between them, for example when one config is made from the other by
+/// `Config::builder_from` or `clone`. All other settings are compared by
+/// value.
+impl PartialEq for Config {
+    fn eq(&self, other: &Config) -> bool {
+        self.restart_delay == other.restart_delay &&
+        self.convergence_delay == other.convergence_delay &&
+        self.seed_grace_period == other.seed_grace_period &&
+        self.emit_empty_for_empty_list == other.emit_empty_for_empty_list &&
+        self.address_eq == other.address_eq &&
+        self.normalize_mapped_v4 == other.normalize_mapped_v4 &&
+        self.hosts == other.hosts &&
+        self.services == other.services &&
+        self.weights == other.weights &&
+        self.suffixes == other.suffixes &&
+        self.prefixes == other.prefixes &&
+        self.names == other.names &&
+        self.disabled_suffixes == other.disabled_suffixes &&
+        Arc::ptr_eq(&self.root, &other.root) &&
+        self.root_opts == other.root_opts &&
+        self.label == other.label &&
+        self.fallthrough_on_temporary == other.fallthrough_on_temporary &&
+        self.middleware.len() == other.middleware.len() &&
+        self.middleware.iter().zip(&other.middleware)
+            .all(|(a, b)| Arc::ptr_eq(a, b)) &&
+        self.track_latency == other.track_latency &&
+        self.warmup_on_start == other.warmup_on_start &&
+        self.skip_equal_updates == other.skip_equal_updates &&
+        self.poll_fairness == other.poll_fairness &&
+        self.max_resolution_depth == other.max_resolution_depth &&
+        self.ports == other.ports &&
+        self.idna_names == other.idna_names &&
+        ApplyCallback::same(&self.on_apply, &other.on_apply) &&
+        AddressRewriter::same(&self.rewriter, &other.rewriter) &&
+        match (self.timer.as_ref(), other.timer.as_ref()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        } &&
+        match (self.metrics.as_ref(), other.metrics.as_ref()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        } &&
+        self.negative_cache_ttl == other.negative_cache_ttl &&
+        self.positive_cache_ttl == other.positive_cache_ttl
+    }
+}
+
+/// Overrides of the timing parameters for a single suffix
+///
+/// Use it with `Config::add_suffix_with_opts` and
+/// `Config::set_fallthrough_with_opts`. Values which are `None` are taken
+/// from the `Config`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SuffixOpts {
+    /// Overrides `Config::restart_delay` for the names of the suffix
+    pub restart_delay: Option<Duration>,
+    /// Overrides `Config::convergence_delay` for the names of the suffix
+    ///
+    /// When names of multiple suffixes are subscribed by a single
+    /// `subscribe_many` the largest delay among them is used.
+    pub convergence_delay: Option<Duration>,
+}
+
+/// A resolver for the suffix (or prefix) and its options
+#[derive(Debug, Clone)]
+pub(crate) struct Suffix {
+    pub resolver: Arc<Resolver>,
+    pub opts: SuffixOpts,
+}
+
+type ApplyFn = Fn(&Arc<Config>) + Send + Sync;
+
+/// A callback set by `Config::on_apply`
+#[derive(Clone)]
+pub(crate) struct ApplyCallback(pub Arc<ApplyFn>);
+
+/// A function set by `Config::set_address_rewriter`
+#[derive(Clone)]
+pub(crate) struct AddressRewriter(
+    pub Arc<Fn(Address) -> Address + Send + Sync>);
+
+impl Debug for ApplyCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ApplyCallback")
+    }
+}
+
+impl Debug for AddressRewriter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AddressRewriter")
+    }
+}
+
+impl PartialEq for Suffix {
+    fn eq(&self, other: &Suffix) -> bool {
+        Arc::ptr_eq(&self.resolver, &other.resolver) &&
+        self.opts == other.opts
+    }
+}
+
+impl ApplyCallback {
+    pub fn same(a: &Option<ApplyCallback>, b: &Option<ApplyCallback>)
+        -> bool
+    {
+        match (a.as_ref(), b.as_ref()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a.0, &b.0),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl AddressRewriter {
+    pub fn rewrite(&self, addr: Address) -> Address {
+        (self.0)(addr)
+    }
+    pub fn same(a: &Option<AddressRewriter>, b: &Option<AddressRewriter>)
+        -> bool
+    {
+        match (a.as_ref(), b.as_ref()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a.0, &b.0),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Timing parameters of the router
+///
+/// These can be updated independently of the config using
+/// `Router::from_config_and_timing`. Unlike updating the config, updating
+/// timing doesn't restart subscriptions, new values are picked up next
+/// time they are used (i.e. when subscription restarts or when a new
+/// list of names arrives to `subscribe_many`).
+///
+/// See the respective methods of `Config` for the description of fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    /// Overrides `Config::restart_delay`
+    pub restart_delay: Duration,
+    /// Overrides `Config::convergence_delay`
+    pub convergence_delay: Duration,
+    /// Overrides `Config::seed_grace_period`
+    pub seed_grace_period: Duration,
+}
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing {
+            restart_delay: Duration::from_millis(100),
+            convergence_delay: Duration::from_millis(100),
+            seed_grace_period: Duration::from_secs(10),
+        }
+    }
 }
 
 impl Config {
 
     /// Create a new, empty config
     pub fn new() -> Config {
+        let timing = Timing::default();
         Config {
-            restart_delay: Duration::from_millis(100),
-            convergence_delay: Duration::from_millis(100),
+            restart_delay: timing.restart_delay,
+            convergence_delay: timing.convergence_delay,
+            restart_delay_set: false,
+            convergence_delay_set: false,
+            seed_grace_period: timing.seed_grace_period,
+            emit_empty_for_empty_list: true,
+            address_eq: AddressEqMode::Exact,
+            normalize_mapped_v4: false,
             hosts: HashMap::new(),
             services: HashMap::new(),
+            weights: HashMap::new(),
             suffixes: HashMap::new(),
+            prefixes: HashMap::new(),
+            names: HashMap::new(),
+            disabled_suffixes: HashSet::new(),
             root: Arc::new(NullResolver),
+            root_chain: Vec::new(),
+            root_opts: SuffixOpts::default(),
+            label: None,
+            fallthrough_on_temporary: false,
+            middleware: Vec::new(),
+            track_latency: false,
+            warmup_on_start: false,
+            skip_equal_updates: false,
+            poll_fairness: None,
+            max_resolution_depth: 8,
+            ports: HashMap::new(),
+            idna_names: false,
+            on_apply: None,
+            rewriter: None,
+            timer: None,
+            metrics: None,
+            negative_cache_ttl: None,
+            positive_cache_ttl: None,
         }
     }
 
+    /// Create a new config with the specified fallthrough resolver
+    ///
+    /// This is a shortcut for `Config::new().set_fallthrough(resolver)`,
+    /// i.e. names that don't match any suffix are resolved by `resolver`
+    /// instead of failing with `NameNotFound`.
+    pub fn new_with_root<R>(resolver: R) -> Config
+        where R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
+    {
+        let mut cfg = Config::new();
+        cfg.set_fallthrough(resolver);
+        cfg
+    }
+
+    /// Create a config builder initialized from an existing config
+    ///
+    /// This is useful to change a few settings of the config that is
+    /// currently in use and pass the result to `UpdateSink::update`.
+    /// Changing the returned builder never affects the original config.
+    pub fn builder_from(cfg: &Arc<Config>) -> Config {
+        (**cfg).clone()
+    }
+
     /// Sets delay after which router will restart any subscription stream
     ///
     /// This works both when stream yields end-of-stream and when stream
     /// has returned error. Default value is 100 milliseconds.
     pub fn restart_delay(&mut self, delay: Duration) -> &mut Self {
         self.restart_delay = delay;
+        self.restart_delay_set = true;
         self
     }
 
@@ -75,16 +326,79 @@ impl Config {
     /// [`subscribe_many`]: struct.Router.html#tymethod.subscribe_many
     pub fn convergence_delay(&mut self, delay: Duration) -> &mut Self {
         self.convergence_delay = delay;
+        self.convergence_delay_set = true;
+        self
+    }
+
+    /// Emit an empty address immediately for an empty list of names
+    ///
+    /// By default, when [`subscribe_many`] family of functions receives
+    /// an empty list of names, empty address is sent to the application
+    /// immediately, so the application knows that there are no addresses
+    /// rather than waiting for them. When this option is disabled, empty
+    /// address is sent only when `convergence_delay` expires.
+    ///
+    /// [`subscribe_many`]: struct.Router.html#method.subscribe_many
+    pub fn emit_empty_for_empty_list(&mut self, value: bool) -> &mut Self {
+        self.emit_empty_for_empty_list = value;
+        self
+    }
+
+    /// Sets how addresses are compared to detect changes
+    ///
+    /// See [`AddressEqMode`] for the description of the modes. Default is
+    /// `AddressEqMode::Exact`. Static hosts and services are always
+    /// compared exactly.
+    ///
+    /// [`AddressEqMode`]: enum.AddressEqMode.html
+    pub fn address_eq(&mut self, mode: AddressEqMode) -> &mut Self {
+        self.address_eq = mode;
+        self
+    }
+
+    /// Convert IPv4-mapped IPv6 addresses to plain IPv4 ones
+    ///
+    /// When enabled, addresses like `::ffff:127.0.0.1` returned by
+    /// resolvers are converted to `127.0.0.1` before they are delivered
+    /// to the application, both for one-off resolutions and for
+    /// subscriptions. Since address weights can't be inspected, a set
+    /// containing a mapped address gets equal weights after conversion.
+    /// Static hosts and services aren't converted. Default is `false`.
+    pub fn normalize_mapped_v4(&mut self, value: bool) -> &mut Self {
+        self.normalize_mapped_v4 = value;
+        self
+    }
+
+    /// Sets for how long seed is kept by [`subscribe_seeded`]
+    ///
+    /// The period is started when the first live value for the name is
+    /// received. While it's active the application receives union of the
+    /// seed and the live addresses, so connections to seeded addresses
+    /// aren't dropped abruptly. Default value is 10 seconds.
+    ///
+    /// [`subscribe_seeded`]: struct.Router.html#method.subscribe_seeded
+    pub fn seed_grace_period(&mut self, period: Duration) -> &mut Self {
+        self.seed_grace_period = period;
         self
     }
 
     /// Add a host that will be resolved to list of addreses
     ///
     /// Hosts added by this host method overrides any other resolvers.
+    ///
+    /// Note: hosts and services are independent, if the same name is
+    /// added both as a host and as a service, host is used for
+    /// `resolve_host` and host-port names, and service is used for
+    /// `resolve` and service names. Warning is logged in this case as it's
+    /// probably a mistake.
     pub fn add_host<A>(&mut self, name: &Name, addr: A)
         -> &mut Self
         where A: Into<IpList>
     {
+        if self.services.contains_key(name) {
+            warn!("Name {:?} is added both as a host and as a service",
+                name);
+        }
         self.hosts.insert(name.clone(), addr.into());
         self
     }
@@ -92,11 +406,69 @@ impl Config {
     /// Add a service that will be resolved to an Address object
     ///
     /// Service names added by this host method overrides any other resolvers.
+    ///
+    /// See `add_host` for the case when the same name is also added as
+    /// a host.
     pub fn add_service(&mut self, name: &Name, addr: Address) -> &mut Self {
+        if self.hosts.contains_key(name) {
+            warn!("Name {:?} is added both as a host and as a service",
+                name);
+        }
         self.services.insert(name.clone(), addr);
         self
     }
 
+    /// Overrides weight of the address of the static service
+    ///
+    /// Override is applied whenever address of the `name` added by
+    /// `add_service` is returned, both for one-off resolutions and for
+    /// subscriptions, so it can be set either before or after the service
+    /// itself.
+    ///
+    /// Weights can't be read back from an `Address`, so once any override
+    /// is set for the `name`, addresses without an override get weight `1`
+    /// and their original weights are lost. Priorities are kept.
+    ///
+    /// Address with weight `0` is kept in the set, but is moved to
+    /// a separate set with the lowest priority, so it's only used if
+    /// there are no other addresses.
+    pub fn set_address_weight(&mut self, name: &Name, addr: SocketAddr,
+        weight: u16)
+        -> &mut Self
+    {
+        self.weights.entry(name.clone()).or_default()
+            .insert(addr, weight);
+        self
+    }
+
+    /// Removes already configured static host
+    pub fn remove_host(&mut self, name: &Name) -> &mut Self {
+        self.hosts.remove(name);
+        self
+    }
+
+    /// Removes already configured static service
+    pub fn remove_service(&mut self, name: &Name) -> &mut Self {
+        self.services.remove(name);
+        self
+    }
+
+    /// Removes all static hosts and services and all routing rules
+    ///
+    /// This resets hosts, services, suffixes (including disabled ones),
+    /// prefixes and exact names. Fallthrough resolver, timing and all
+    /// other options are kept.
+    pub fn clear(&mut self) -> &mut Self {
+        self.hosts.clear();
+        self.services.clear();
+        self.weights.clear();
+        self.suffixes.clear();
+        self.disabled_suffixes.clear();
+        self.prefixes.clear();
+        self.names.clear();
+        self
+    }
+
     /// Add a resolver for suffix
     ///
     /// Note: you must supply a full resolver here,
@@ -104,6 +476,16 @@ impl Config {
     /// [`interval_subscribe`] or `frozen_subscriber`
     /// and other combinators to fullfill needed type.
     ///
+    /// Suffix is matched case-insensitively (only ASCII letters are
+    /// folded), like the rest of DNS names.
+    ///
+    /// Suffixes may be nested (i.e. `consul` and `svc.consul`), the
+    /// longest suffix matching the name wins, regardless of the order
+    /// suffixes are added in. Disabled suffixes (see
+    /// `Router::disable_suffix`) are skipped, so the next longest one is
+    /// used. A suffix matches whole labels only: `example.com` matches
+    /// `example.com` and `x.example.com`, but not `xexample.com`.
+    ///
     /// [`interval_subscribe`]: trait.SubscribeExt.html#tymethod.interval_subscribe
     pub fn add_suffix<S, R>(&mut self, suffix: S, resolver: R)
         -> &mut Self
@@ -111,8 +493,90 @@ impl Config {
               R: Resolve + HostResolve + Subscribe + HostSubscribe,
               R: Debug + 'static,
     {
-        self.suffixes.insert(suffix.into(),
-            Arc::new(Wrapper::new(resolver)));
+        self.add_suffix_with_opts(suffix, resolver, SuffixOpts::default())
+    }
+
+    /// Add a resolver for suffix with overriden timing parameters
+    ///
+    /// This works the same as `add_suffix` but allows to set restart and
+    /// convergence delays for the names of this suffix, for example to
+    /// use shorter restart delay for local resolver and longer one for
+    /// slow and flaky remote one.
+    pub fn add_suffix_with_opts<S, R>(&mut self, suffix: S, resolver: R,
+        opts: SuffixOpts)
+        -> &mut Self
+        where S: Into<String>,
+              R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
+    {
+        // `Name` is always lowercase, so only the suffix needs folding
+        let suffix = suffix.into().to_ascii_lowercase();
+        self.suffixes.insert(suffix, Suffix {
+            resolver: Arc::new(Wrapper::new(resolver)),
+            opts,
+        });
+        self
+    }
+
+    /// Add a resolver for exactly this name
+    ///
+    /// Unlike `add_host` and `add_service` the name is still resolved
+    /// (and subscribed to) live, but using this resolver instead of the one
+    /// matched by suffix or prefix. This is useful when a single service
+    /// must go through a different backend than its suffix siblings.
+    ///
+    /// Note: static hosts and services take precedence over this.
+    pub fn add_name<R>(&mut self, name: &Name, resolver: R) -> &mut Self
+        where R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
+    {
+        self.names.insert(name.clone(), Suffix {
+            resolver: Arc::new(Wrapper::new(resolver)),
+            opts: SuffixOpts::default(),
+        });
+        self
+    }
+
+    /// Removes resolver added by `add_name`
+    pub fn remove_name(&mut self, name: &Name) -> &mut Self {
+        self.names.remove(name);
+        self
+    }
+
+    /// Add a resolver for names starting with the prefix
+    ///
+    /// Prefix is matched against the whole name as a string, i.e.
+    /// `db-` prefix matches both `db-primary.internal` and
+    /// `db-replica.internal`. Prefixes take precedence over suffixes, and
+    /// if multiple prefixes match, the longest one is used. Resolvers added
+    /// by `add_name` take precedence over prefixes.
+    ///
+    /// Empty prefix would match every name and shadow all suffixes, so it's
+    /// ignored (use `set_fallthrough` instead).
+    ///
+    /// Note: static hosts and services still take precedence over
+    /// prefixes, and `Router::matched_suffix` only reports suffixes.
+    pub fn add_prefix<S, R>(&mut self, prefix: S, resolver: R)
+        -> &mut Self
+        where S: Into<String>,
+              R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
+    {
+        let prefix = prefix.into();
+        if prefix.is_empty() {
+            warn!("Empty prefix is ignored, use set_fallthrough instead");
+            return self;
+        }
+        self.prefixes.insert(prefix, Suffix {
+            resolver: Arc::new(Wrapper::new(resolver)),
+            opts: SuffixOpts::default(),
+        });
+        self
+    }
+
+    /// Removes already configured prefix
+    pub fn remove_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.prefixes.remove(prefix);
         self
     }
 
@@ -120,7 +584,7 @@ impl Config {
     pub fn remove_suffix<S>(&mut self, suffix: &str)
         -> &mut Self
     {
-        self.suffixes.remove(suffix);
+        self.suffixes.remove(&suffix.to_ascii_lowercase());
         self
     }
 
@@ -129,13 +593,522 @@ impl Config {
         -> &mut Self
         where R: Resolve + HostResolve + Subscribe + HostSubscribe,
               R: Debug + 'static,
+    {
+        self.set_fallthrough_with_opts(resolver, SuffixOpts::default())
+    }
+
+    /// Adds a fallthrough resolver with overriden timing parameters
+    ///
+    /// See `add_suffix_with_opts` for more info.
+    pub fn set_fallthrough_with_opts<R>(&mut self, resolver: R,
+        opts: SuffixOpts)
+        -> &mut Self
+        where R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
     {
         self.root = Arc::new(Wrapper::new(resolver));
+        self.root_chain = vec![self.root.clone()];
+        self.root_opts = opts;
         self
     }
 
+    /// Appends a resolver to the chain of fallthrough resolvers
+    ///
+    /// Resolvers in chain are tried in order they are pushed (a resolver
+    /// set by `set_fallthrough` is the first one). Next resolver is
+    /// tried only if previous one returns `Error::NameNotFound`, other
+    /// errors are returned to the application as is.
+    ///
+    /// Subscriptions are made to all resolvers in chain, and the
+    /// application receives value of the first resolver that has
+    /// a non-empty address.
+    // resolvers in config aren't `Send`, same as the config itself
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn push_fallthrough<R>(&mut self, resolver: R)
+        -> &mut Self
+        where R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
+    {
+        self.root_chain.push(Arc::new(Wrapper::new(resolver)));
+        self.root = if self.root_chain.len() == 1 {
+            self.root_chain[0].clone()
+        } else {
+            Arc::new(ChainResolver::new(self.root_chain.clone()))
+        };
+        self
+    }
+
+    /// Sets a label for the router using this config
+    ///
+    /// The label is included in every log line emitted by the router, so
+    /// when you run multiple routers in a single process (for example one
+    /// per tenant) you can tell their messages apart. Default is `router`.
+    ///
+    /// Note: label is picked up when config is applied, so if config
+    /// without a label is pushed after a labelled one, the previous label
+    /// is kept.
+    pub fn set_label<S: Into<String>>(&mut self, label: S) -> &mut Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Retry resolving in fallthrough resolver on temporary errors
+    ///
+    /// When enabled and a suffix resolver fails with
+    /// `Error::TemporaryError`, the request is immediately sent to the
+    /// fallthrough resolver (see `set_fallthrough`) instead of returning
+    /// the error to the caller.
+    ///
+    /// Note: `NameNotFound` is an authoritative answer of the suffix
+    /// resolver and is never retried in fallthrough, regardless of this
+    /// setting. Also this works only for one-shot resolve requests,
+    /// subscriptions are restarted by the usual means (see
+    /// `restart_delay`).
+    ///
+    /// Default is `false`.
+    pub fn retry_fallthrough_on_temporary(&mut self, value: bool)
+        -> &mut Self
+    {
+        self.fallthrough_on_temporary = value;
+        self
+    }
+
+    /// Adds a middleware that wraps resolution of every name
+    ///
+    /// Middlewares are composed in the order they are added, i.e. the
+    /// first one added is the outermost one: it receives the future
+    /// returned by the second one and so on.
+    ///
+    /// See [`ResolveMiddleware`] for more info.
+    ///
+    /// [`ResolveMiddleware`]: middleware/trait.ResolveMiddleware.html
+    pub fn add_middleware(&mut self, middleware: Arc<ResolveMiddleware>)
+        -> &mut Self
+    {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Enables tracking of resolution latencies
+    ///
+    /// When enabled, time from receiving a `resolve_host`,
+    /// `resolve_host_port` or `resolve` request by the router until the
+    /// reply is sent is recorded into a histogram. Histograms can be
+    /// fetched by `Router::latency_stats`. Default is `false`.
+    pub fn track_latency(&mut self, value: bool) -> &mut Self {
+        self.track_latency = value;
+        self
+    }
+
+    /// Warm up suffix resolvers when router starts
+    ///
+    /// Some resolvers (for example ones talking to consul) connect to their
+    /// backend lazily, on the first query. When this option is enabled,
+    /// router issues a host resolution of each suffix itself (e.g. of
+    /// `consul` for the `consul` suffix) when the first config is applied,
+    /// so connection is established before names are requested. Results
+    /// are ignored. The fallthrough resolver is not warmed up.
+    ///
+    /// Default is `false`.
+    pub fn warmup_on_start(&mut self, value: bool) -> &mut Self {
+        self.warmup_on_start = value;
+        self
+    }
+
+    /// Don't restart subscriptions when an equal config is pushed
+    ///
+    /// By default every config received by the router (i.e. via
+    /// `UpdateSink::update` or a config stream) restarts all
+    /// subscriptions. When this option is enabled in the new config, and
+    /// it's equal to the current one, the update is ignored (including the
+    /// `on_apply` callback). See `PartialEq` implementation for `Config`
+    /// for the description of when configs are equal.
+    ///
+    /// Default is `false`.
+    pub fn skip_equal_updates(&mut self, value: bool) -> &mut Self {
+        self.skip_equal_updates = value;
+        self
+    }
+
+    /// Limits number of internal futures processed in a single poll
+    ///
+    /// Router processes requests and completion of internal futures
+    /// (resolutions, subscription restarts) in a single task. When lots of
+    /// futures become ready at once (i.e. when config update restarts
+    /// thousands of subscriptions) processing them all in one go delays
+    /// new requests. With this option router yields to the event loop
+    /// after processing `limit` futures, and resumes on the next
+    /// iteration, picking up new requests first.
+    ///
+    /// By default there is no limit.
+    pub fn poll_fairness(&mut self, limit: usize) -> &mut Self {
+        assert!(limit > 0, "poll fairness limit must be positive");
+        self.poll_fairness = Some(limit);
+        self
+    }
+
+    /// Limits how deep resolvers may delegate to a router
+    ///
+    /// When a suffix resolver is itself a `Router` (or uses one), a name
+    /// may be routed back to the same suffix, and resolution would loop
+    /// forever. To prevent that, every request sent to a router by a
+    /// resolver while resolving another request is one level deeper than
+    /// the original request. When depth exceeds `depth`, resolution fails
+    /// with `TemporaryError` and subscription never gets a value.
+    ///
+    /// Note: depth is only tracked for requests that a resolver sends
+    /// synchronously, i.e. from within its `resolve` or `subscribe` call
+    /// (or while its subscription is restarted). A request sent later,
+    /// for example from `poll` of a future the resolver returned, is
+    /// treated as a new request at depth zero, so such a loop isn't
+    /// detected.
+    ///
+    /// Default is `8`.
+    pub fn max_resolution_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_resolution_depth = depth;
+        self
+    }
+
+    /// Registers a symbolic port name
+    ///
+    /// Names like `example.org:admin` passed to `Router::resolve_auto`,
+    /// `Router::subscribe_many` and similar methods will use `port` for
+    /// the `admin` suffix. Registered names override built-in ones
+    /// (`http`, `https`, `ssh`, `smtp`).
+    ///
+    /// Note: names are parsed when they are passed to the router, so for
+    /// a router created by `Router::from_stream`, names passed before the
+    /// first config is received only know built-in ports.
+    pub fn register_port<S: Into<String>>(&mut self, name: S, port: u16)
+        -> &mut Self
+    {
+        self.ports.insert(name.into(), port);
+        self
+    }
+
+    /// Accept unicode host names in `resolve_auto`, `subscribe_many`, etc.
+    ///
+    /// When enabled, non-ASCII labels of the names are encoded into
+    /// punycode (`münchen.example` → `xn--mnchen-3ya.example`). Names
+    /// passed as `Name` are never converted. Default is `false`.
+    ///
+    /// The same note about names parsed before config is applied as for
+    /// `register_port` applies here.
+    ///
+    /// Only available with the `idna` feature.
+    #[cfg(feature="idna")]
+    pub fn idna_names(&mut self, value: bool) -> &mut Self {
+        self.idna_names = value;
+        self
+    }
+
+    /// Sets a callback invoked each time router applies a new config
+    ///
+    /// Callback is called on the reactor thread after the config is
+    /// installed, i.e. requests issued from the callback are already
+    /// resolved using the new config. This is useful for side effects
+    /// tied to config change, like flushing caches.
+    ///
+    /// Note: callback is a part of the config, so it's replaced (or
+    /// removed) by the next config pushed to the router. Also callback is
+    /// invoked for configs derived by the router itself, like when a name
+    /// is pinned by `Router::pin_static` or suffix is disabled by
+    /// `Router::disable_suffix`.
+    pub fn on_apply(&mut self, callback: Arc<ApplyFn>)
+        -> &mut Self
+    {
+        self.on_apply = Some(ApplyCallback(callback));
+        self
+    }
+
+    /// Sets a function that rewrites every resolved address
+    ///
+    /// Rewriter is applied to the results of `resolve`, `resolve_auto`
+    /// (and other methods returning `Address`), `subscribe` and
+    /// `subscribe_many` family of methods, after middleware, right before
+    /// address is delivered to the application. Static services are
+    /// rewritten too. This is useful for service mesh integration, i.e.
+    /// to redirect all connections to a sidecar proxy.
+    ///
+    /// Rewriter isn't applied to host resolution (`resolve_host`,
+    /// `subscribe_host`), to `resolve_bypass_static`, to subscriptions
+    /// made by `subscribe_with` and to IP addresses passed to
+    /// `resolve_auto` (those are returned without contacting router).
+    pub fn set_address_rewriter(&mut self,
+        rewriter: Arc<Fn(Address) -> Address + Send + Sync>)
+        -> &mut Self
+    {
+        self.rewriter = Some(AddressRewriter(rewriter));
+        self
+    }
+
+    /// Sets a source of timers for the router
+    ///
+    /// Timer is used for `restart_delay`, `convergence_delay` and
+    /// `seed_grace_period`. By default timers of the runtime running the
+    /// router are used. Use `ManualTimer` to test code that depends on
+    /// these delays deterministically.
+    ///
+    /// Timeouts passed to the methods of the router directly (i.e.
+    /// `Router::subscribe_with_init_timeout`) don't use this timer.
+    pub fn timer<T: Timer>(&mut self, timer: T) -> &mut Self {
+        self.timer = Some(Arc::new(timer));
+        self
+    }
+
+    /// Sets hooks which receive events of the router
+    ///
+    /// Router reports resolutions started and finished, subscriptions
+    /// created and closed, and configs applied. See `Metrics` for the
+    /// details. By default no metrics are collected.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) -> &mut Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Caches `NameNotFound` results for the specified time
+    ///
+    /// While name is in the cache, `resolve`, `resolve_host` and similar
+    /// methods (including `resolve_auto`) fail with `NameNotFound` right
+    /// away, without contacting the resolver. Host and service lookups are
+    /// cached separately. Cache is cleared when a new config is applied.
+    ///
+    /// Subscriptions, `resolve_traced` and `resolve_bypass_static` don't
+    /// use the cache. By default nothing is cached.
+    pub fn negative_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.negative_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Caches successful resolutions for the specified time
+    ///
+    /// This also coalesces identical requests: while a name is being
+    /// resolved, `resolve`, `resolve_host` and `resolve_auto` for the same
+    /// name wait for the request in flight instead of contacting the
+    /// resolver again. Zero `ttl` enables only the latter.
+    ///
+    /// Cache is cleared when a new config is applied. Requests in flight
+    /// at that moment are still replied, but their results aren't cached.
+    ///
+    /// Subscriptions, `resolve_traced`, `resolve_bypass_static` and
+    /// `resolve_host_ports` don't use the cache. By default nothing is
+    /// cached.
+    pub fn positive_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.positive_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Merges routing rules of another config into this one
+    ///
+    /// This is useful when config is assembled from independent parts,
+    /// for example one module configures `.consul` suffix and another one
+    /// adds static hosts. The following is copied from `other`:
+    ///
+    /// * static hosts and services, and address weight overrides
+    /// * suffixes, prefixes and exact names along with their options
+    /// * fallthrough resolver (or a chain of them), if set in `other`
+    /// * symbolic port names
+    /// * `restart_delay` and `convergence_delay`, if set in `other`
+    ///   explicitly
+    ///
+    /// On conflict the value from `other` wins, i.e. configs merged later
+    /// take precedence. Other settings of `self` are kept intact.
+    pub fn merge(&mut self, other: &Config) -> &mut Self {
+        self.hosts.extend(other.hosts.iter()
+            .map(|(k, v)| (k.clone(), v.clone())));
+        self.services.extend(other.services.iter()
+            .map(|(k, v)| (k.clone(), v.clone())));
+        for (name, weights) in &other.weights {
+            self.weights.entry(name.clone()).or_default()
+                .extend(weights.iter().map(|(a, w)| (*a, *w)));
+        }
+        self.suffixes.extend(other.suffixes.iter()
+            .map(|(k, v)| (k.clone(), v.clone())));
+        self.prefixes.extend(other.prefixes.iter()
+            .map(|(k, v)| (k.clone(), v.clone())));
+        self.names.extend(other.names.iter()
+            .map(|(k, v)| (k.clone(), v.clone())));
+        self.ports.extend(other.ports.iter()
+            .map(|(k, v)| (k.clone(), *v)));
+        if !other.root_chain.is_empty() {
+            self.root = other.root.clone();
+            self.root_chain = other.root_chain.clone();
+            self.root_opts = other.root_opts.clone();
+        }
+        if other.restart_delay_set {
+            self.restart_delay(other.restart_delay);
+        }
+        if other.convergence_delay_set {
+            self.convergence_delay(other.convergence_delay);
+        }
+        self
+    }
+
+    /// Returns configured suffixes (including disabled ones)
+    pub fn suffixes<'x>(&'x self) -> impl Iterator<Item=&'x str> + 'x {
+        self.suffixes.keys().map(|s| &s[..])
+    }
+
+    /// Returns `Debug` representation of the resolver for the suffix
+    pub fn suffix_resolver(&self, suffix: &str) -> Option<String> {
+        self.suffixes.get(&suffix.to_ascii_lowercase())
+            .and_then(|s| s.resolver.describe())
+    }
+
+    /// Returns `Debug` representation of the fallthrough resolver
+    ///
+    /// Returns `None` if fallthrough resolver is not set.
+    pub fn fallthrough_resolver(&self) -> Option<String> {
+        self.root.describe()
+    }
+
+    /// Returns static hosts
+    pub fn hosts<'x>(&'x self)
+        -> impl Iterator<Item=(&'x Name, &'x IpList)> + 'x
+    {
+        self.hosts.iter()
+    }
+
+    /// Returns static services
+    pub fn services<'x>(&'x self)
+        -> impl Iterator<Item=(&'x Name, &'x Address)> + 'x
+    {
+        self.services.iter()
+    }
+
     /// A convenience method that returns Arc'd config
     pub fn done(&self) -> Arc<Config> {
         Arc::new(self.clone())
     }
+
+    /// Returns static service with weight overrides applied
+    pub(crate) fn service(&self, name: &Name) -> Option<Address> {
+        let addr = self.services.get(name)?;
+        match self.weights.get(name) {
+            Some(weights) => Some(reweight(addr, weights)),
+            None => Some(addr.clone()),
+        }
+    }
+
+    pub(crate) fn set_timing(&mut self, timing: &Timing) {
+        self.restart_delay = timing.restart_delay;
+        self.convergence_delay = timing.convergence_delay;
+        self.seed_grace_period = timing.seed_grace_period;
+    }
+}
+
+fn reweight(addr: &Address, weights: &HashMap<SocketAddr, u16>)
+    -> Address
+{
+    // original weights are not accessible, so default weight is used
+    let mut builder = Builder::new();
+    let mut zero = Vec::new();
+    for set in addr.iter() {
+        let mut items = Vec::new();
+        for a in set.addresses() {
+            match weights.get(&a).map(|&w| w as u64).unwrap_or(1) {
+                0 => zero.push((1, a)),
+                w => items.push((w, a)),
+            }
+        }
+        if !items.is_empty() {
+            builder.add_addresses(&items);
+        }
+    }
+    if !zero.is_empty() {
+        builder.add_addresses(&zero);
+    }
+    builder.into_address()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use abstract_ns::{Name, Address, IpList, Error};
+    use abstract_ns::{HostResolve, Resolve};
+    use futures::future::{FutureResult, ok};
+    use super::Config;
+
+    #[derive(Debug)]
+    struct Mock(u8);
+
+    impl HostResolve for Mock {
+        type HostFuture = FutureResult<IpList, Error>;
+        fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+            ok(vec![[127, 0, 0, self.0].into()].into())
+        }
+    }
+
+    impl Resolve for Mock {
+        type Future = FutureResult<Address, Error>;
+        fn resolve(&self, _name: &Name) -> Self::Future {
+            ok(Address::from(&[][..]))
+        }
+    }
+
+    #[test]
+    fn builder_from_keeps_original() {
+        let a: Name = "a.example.org".parse().unwrap();
+        let b: Name = "b.example.org".parse().unwrap();
+        let orig = Config::new()
+            .add_host(&a, vec!["127.0.0.1".parse().unwrap()])
+            .done();
+        let updated = Config::builder_from(&orig)
+            .add_host(&b, vec!["127.0.0.2".parse().unwrap()])
+            .restart_delay(::std::time::Duration::from_secs(1))
+            .done();
+        assert!(orig.hosts.contains_key(&a));
+        assert!(!orig.hosts.contains_key(&b));
+        assert!(updated.hosts.contains_key(&a));
+        assert!(updated.hosts.contains_key(&b));
+        assert_eq!(orig.restart_delay.as_secs(), 0);
+    }
+
+    #[test]
+    fn merge_later_wins() {
+        let a: Name = "a.example.org".parse().unwrap();
+        let mut first = Config::new();
+        first
+            .add_suffix("consul", Mock(1).frozen_subscriber())
+            .add_suffix("local", Mock(1).frozen_subscriber())
+            .add_host(&a, vec!["127.0.0.1".parse().unwrap()])
+            .convergence_delay(Duration::from_secs(5));
+        let mut second = Config::new();
+        second
+            .add_suffix("consul", Mock(2).frozen_subscriber())
+            .add_host(&a, vec!["127.0.0.2".parse().unwrap()])
+            .restart_delay(Duration::from_secs(1));
+
+        let cfg = first.merge(&second).done();
+        let describe = |suffix: &str| {
+            cfg.suffixes[suffix].resolver.describe().unwrap()
+        };
+        assert!(describe("consul").contains("Mock(2)"));
+        assert!(describe("local").contains("Mock(1)"));
+        assert_eq!(cfg.hosts[&a],
+                   vec!["127.0.0.2".parse().unwrap()].into());
+        assert_eq!(cfg.restart_delay, Duration::from_secs(1));
+        // default value in `second` doesn't override the explicit one
+        assert_eq!(cfg.convergence_delay, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn accessors() {
+        let a: Name = "a.example.org".parse().unwrap();
+        let s: Name = "_http._tcp.example.org".parse().unwrap();
+        let cfg = Config::new()
+            .add_suffix("consul", Mock(1).frozen_subscriber())
+            .add_host(&a, vec!["127.0.0.1".parse().unwrap()])
+            .add_service(&s, Address::from(&[][..]))
+            .done();
+        assert_eq!(cfg.suffixes().collect::<Vec<_>>(), vec!["consul"]);
+        assert!(cfg.suffix_resolver("consul").unwrap().contains("Mock(1)"));
+        assert_eq!(cfg.suffix_resolver("local"), None);
+        assert_eq!(cfg.fallthrough_resolver(), None);
+        assert_eq!(cfg.hosts().map(|(n, _)| n).collect::<Vec<_>>(),
+                   vec![&a]);
+        assert_eq!(cfg.services().map(|(n, _)| n).collect::<Vec<_>>(),
+                   vec![&s]);
+    }
 }
@@ -1,35 +1,93 @@
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::{Arc};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
 use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use std::net::{IpAddr, SocketAddr};
 
 use abstract_ns::{Address, IpList, Name, Error};
-use async_slot as slot;
+use slot;
 use futures::future::Shared;
 use futures::stream::{FuturesUnordered, Fuse};
 use futures::sync::mpsc::{UnboundedReceiver};
 use futures::sync::oneshot;
 use futures::{Stream, Future, Async};
-use tokio_core::reactor::{Handle, Timeout};
+use futures::task;
+use futures::unsync;
 use void::{Void, unreachable};
 
-use config::Config;
-use internal_traits::Resolver;
-use internal::{Request, reply};
+use cache::{self, SharedCache};
+use cancel::{Cancelable, SubscriptionId};
+use cell::ConfigCell;
+use config::{Config, Timing, Suffix, SuffixOpts};
+use fallback::RootFallback;
+use future::ResolveFuture;
+use internal_traits::{Resolver, SendResult};
+use metrics::{Labeled, Metered, SharedMetrics};
+use middleware::AddressFuture;
+use internal::{Request, ReplyMap, AtDepth, reply, fail, set_depth};
+use latency::{LatencyStats, Timed, Kind};
+use name::{ParseOptions, SharedParseOptions};
+use negative::{Lookup, Remember, SharedNegativeCache};
+use pin::PinStatic;
 use subscr::{SubscrFuture, HostNoOpSubscr, NoOpSubscr};
+use subscr::RewriteSubscr;
+use stats::{Counted, RouterStats};
+use table;
+use timer::{Sleep, Timer};
+use trace::{Trace, Route};
 
 
 #[must_use = "futures do nothing unless polled"]
 pub struct ResolverFuture {
-    update_tx: oneshot::Sender<()>,
-    update_rx: Shared<oneshot::Receiver<()>>,
+    update_tx: oneshot::Sender<Update>,
+    update_rx: Shared<oneshot::Receiver<Update>>,
     requests: Fuse<UnboundedReceiver<Request>>,
     futures: FuturesUnordered<Box<Future<Item=FutureResult, Error=Void>>>,
     current_config: Option<Arc<Config>>,
-    handle: Handle,
+    timing: Option<Timing>,
+    label: String,
+    latency: Arc<Mutex<LatencyStats>>,
+    shutdown_watchers: Vec<oneshot::Sender<()>>,
+    cell: Rc<ConfigCell>,
+    parse_opts: SharedParseOptions,
+    metrics: SharedMetrics,
+    negative: SharedNegativeCache,
+    host_cache: SharedCache<Name, IpList>,
+    host_port_cache: SharedCache<(Name, u16), Address>,
+    service_cache: SharedCache<Name, Address>,
+    resolves: Rc<Cell<usize>>,
+    subscriptions: Rc<Cell<usize>>,
+    /// Cancelable subscription that the spawned tasks belong to
+    scope: Option<SubscriptionId>,
+    /// Senders which cancel tasks of a subscription when dropped
+    cancels: HashMap<SubscriptionId, Vec<unsync::oneshot::Sender<()>>>,
+    /// Number of futures following config and timing streams, they are
+    /// not waited for on shutdown
+    followers: usize,
+    shutting_down: bool,
+    timer: Arc<Timer>,
+}
+
+/// The reason subscriptions are restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Update {
+    /// New config is applied, resubscribe if routing is changed
+    Config,
+    /// Network is changed, resubscribe everything that isn't static
+    Refresh,
+    /// Router is shutting down, subscriptions must stop
+    Shutdown,
 }
 
 pub(crate) trait Continuation: fmt::Debug {
     fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>);
+    /// Name which is used to find per-suffix options (i.e. restart delay)
+    fn name(&self) -> Option<&Name> { None }
 }
 
 pub(crate) enum FutureResult {
@@ -39,6 +97,11 @@ pub(crate) enum FutureResult {
         cfg: Arc<Config>,
         next: Box<Future<Item=FutureResult, Error=Void>>,
     },
+    UpdateTiming {
+        timing: Timing,
+        next: Box<Future<Item=FutureResult, Error=Void>>,
+    },
+    TimingClosed,
     Restart {
         task: Box<Continuation>,
     },
@@ -61,9 +124,24 @@ fn mapper<S>(res: Result<(Option<Arc<Config>>, S), (Void, S)>)
     }
 }
 
+fn timing_mapper<S>(res: Result<(Option<Timing>, S), (Void, S)>)
+    -> Result<FutureResult, Void>
+    where S: Stream<Item=Timing, Error=Void> + 'static
+{
+    match res {
+        // router works with last timing values when stream is closed
+        Ok((None, _)) => Ok(FutureResult::TimingClosed),
+        Ok((Some(timing), stream)) => Ok(FutureResult::UpdateTiming {
+            timing,
+            next: Box::new(stream.into_future().then(timing_mapper)),
+        }),
+        Err((e, _)) => unreachable(e),
+    }
+}
+
 impl ResolverFuture {
     pub(crate) fn new<S>(config: S, requests: UnboundedReceiver<Request>,
-        handle: &Handle)
+        latency: &Arc<Mutex<LatencyStats>>, timer: Arc<Timer>)
         -> ResolverFuture
         where S: Stream<Item=Arc<Config>, Error=Void> + 'static
     {
@@ -77,37 +155,456 @@ impl ResolverFuture {
             update_tx: tx,
             update_rx: rx.shared(),
             futures: futures,
-            handle: handle.clone(),
+            timer,
             current_config: None,
+            timing: None,
+            label: String::from("router"),
+            latency: latency.clone(),
+            shutdown_watchers: Vec::new(),
+            cell: Rc::new(ConfigCell::default()),
+            parse_opts: SharedParseOptions::default(),
+            metrics: SharedMetrics::default(),
+            negative: SharedNegativeCache::default(),
+            host_cache: SharedCache::default(),
+            host_port_cache: SharedCache::default(),
+            service_cache: SharedCache::default(),
+            resolves: Rc::new(Cell::new(0)),
+            subscriptions: Rc::new(Cell::new(0)),
+            scope: None,
+            cancels: HashMap::new(),
+            followers: 1,
+            shutting_down: false,
         }
     }
-    pub fn update_rx(&self) -> Shared<oneshot::Receiver<()>> {
+    /// Returns a cell which holds the config currently in use
+    pub(crate) fn config_cell(&self) -> Rc<ConfigCell> {
+        self.cell.clone()
+    }
+    /// Returns name parsing options of the config currently in use
+    pub(crate) fn parse_options(&self) -> SharedParseOptions {
+        self.parse_opts.clone()
+    }
+    /// Returns metrics of the config currently in use
+    pub(crate) fn metrics(&self) -> SharedMetrics {
+        self.metrics.clone()
+    }
+    pub(crate) fn follow_timing<S>(&mut self, timing: S)
+        where S: Stream<Item=Timing, Error=Void> + 'static
+    {
+        self.followers += 1;
+        self.futures.push(Box::new(timing.into_future().then(timing_mapper))
+            as Box<Future<Item=FutureResult, Error=Void>>);
+    }
+    pub fn update_rx(&self) -> Shared<oneshot::Receiver<Update>> {
         self.update_rx.clone()
     }
-    pub fn handle(&self) -> &Handle {
-        &self.handle
+    /// Returns a future which resolves after `duration`
+    ///
+    /// Uses the timer of the config if set, otherwise the runtime's one.
+    pub(crate) fn sleep(&self, cfg: &Config, duration: Duration) -> Sleep {
+        match cfg.timer {
+            Some(ref timer) => timer.sleep(duration),
+            None => self.timer.sleep(duration),
+        }
+    }
+    fn notify_shutdown(&mut self) {
+        // requests still queued (i.e. sent before first config) are dropped
+        // with the router, but watchers must know it was a real shutdown
+        while let Ok(Async::Ready(Some(req))) = self.requests.poll() {
+            if let Request::WatchShutdown(tx) = req {
+                self.shutdown_watchers.push(tx);
+            }
+        }
+        for tx in self.shutdown_watchers.drain(..) {
+            tx.send(()).ok();
+        }
+    }
+    fn update_config(&mut self, mut cfg: Arc<Config>) -> Arc<Config> {
+        if let Some(ref label) = cfg.label {
+            self.label = label.clone();
+        }
+        if let Some(ref timing) = self.timing {
+            Arc::make_mut(&mut cfg).set_timing(timing);
+        }
+        if let Some(ref current) = self.current_config {
+            if cfg.skip_equal_updates && **current == *cfg {
+                debug!("{}: config is unchanged, skipping", self.label);
+                return current.clone();
+            }
+        }
+        debug!("{}: config updated", self.label);
+        self.negative.borrow_mut().clear();
+        self.host_cache.borrow_mut().clear();
+        self.host_port_cache.borrow_mut().clear();
+        self.service_cache.borrow_mut().clear();
+        let first = self.current_config.is_none();
+        self.current_config = Some(cfg.clone());
+        self.cell.put(&cfg);
+        *self.parse_opts.write().expect("options lock is not poisoned") =
+            ParseOptions::from_config(&cfg);
+        let metrics = cfg.metrics.as_ref()
+            .map(|m| Labeled::new(&self.label, m));
+        if let Some(ref metrics) = metrics {
+            metrics.config_updated();
+        }
+        *self.metrics.write().expect("metrics lock is not poisoned") =
+            metrics;
+        self.notify(Update::Config);
+        if first && cfg.warmup_on_start {
+            self.warmup(&cfg);
+        }
+        if let Some(ref callback) = cfg.on_apply {
+            (callback.0)(&cfg);
+        }
+        cfg
+    }
+    fn warmup(&mut self, cfg: &Arc<Config>) {
+        for (suffix, suf) in &cfg.suffixes {
+            if cfg.disabled_suffixes.contains(suffix) {
+                continue;
+            }
+            debug!("{}: warming up {:?}", self.label, suffix);
+            suf.resolver.warmup(self, cfg, suffix);
+        }
+    }
+    /// Applies timing without restarting subscriptions
+    fn update_timing(&mut self, timing: Timing) {
+        debug!("{}: timing updated: {:?}", self.label, timing);
+        if let Some(ref mut cfg) = self.current_config {
+            Arc::make_mut(cfg).set_timing(&timing);
+            self.cell.put(cfg);
+        }
+        self.timing = Some(timing);
+    }
+    fn set_suffix_enabled(&mut self, cfg: &Arc<Config>,
+        suffix: String, enabled: bool)
+        -> Arc<Config>
+    {
+        if cfg.disabled_suffixes.contains(&suffix) != enabled {
+            // nothing changed
+            return cfg.clone();
+        }
+        let mut new_cfg = cfg.clone();
+        {
+            let disabled = &mut Arc::make_mut(&mut new_cfg).disabled_suffixes;
+            if enabled {
+                info!("{}: suffix {:?} enabled", self.label, suffix);
+                disabled.remove(&suffix);
+            } else {
+                info!("{}: suffix {:?} disabled", self.label, suffix);
+                disabled.insert(suffix);
+            }
+        }
+        self.update_config(new_cfg)
+    }
+    fn notify(&mut self, kind: Update) {
+        let (tx, rx) = oneshot::channel();
+        let tx = mem::replace(&mut self.update_tx, tx);
+        self.update_rx = rx.shared();
+        tx.send(kind).ok();
+    }
+    fn shutdown(&mut self) {
+        info!("{}: shutting down", self.label);
+        self.shutting_down = true;
+        self.notify_shutdown();
+        self.notify(Update::Shutdown);
+        // subscriptions spawned while in-flight requests are drained
+        // must stop too, so the next update is sent right away
+        let (tx, _) = oneshot::channel();
+        mem::replace(&mut self.update_tx, tx).send(Update::Shutdown).ok();
+    }
+}
+
+/// Returns the value of the host that is known without resolving it
+///
+/// This is either a static host of the config or a literal IP address.
+pub(crate) fn static_host(cfg: &Config, name: &Name) -> Option<IpList> {
+    cfg.hosts.get(name).cloned()
+        // literal IP never changes, so it's emitted once just like
+        // a static host, rather than passed to a resolver
+        .or_else(|| name.as_ref().parse::<IpAddr>().ok()
+                        .map(|ip| vec![ip].into()))
+}
+
+pub(crate) fn get_suffix<'x>(cfg: &'x Arc<Config>, name: &Name)
+    -> &'x Arc<Resolver>
+{
+    if let Some(exact) = cfg.names.get(name) {
+        return &exact.resolver;
+    }
+    let name = name.as_ref();
+    if let Some((_, pre)) = match_prefix(cfg, name) {
+        return &pre.resolver;
+    }
+    match match_suffix(cfg, name) {
+        Some((_, suf)) => &suf.resolver,
+        None => &cfg.root,
+    }
+}
+
+pub(crate) fn get_opts<'x>(cfg: &'x Arc<Config>, name: &Name)
+    -> &'x SuffixOpts
+{
+    if let Some(exact) = cfg.names.get(name) {
+        return &exact.opts;
+    }
+    let name = name.as_ref();
+    if let Some((_, pre)) = match_prefix(cfg, name) {
+        return &pre.opts;
+    }
+    match match_suffix(cfg, name) {
+        Some((_, suf)) => &suf.opts,
+        None => &cfg.root_opts,
     }
 }
 
-pub(crate) fn get_suffix<'x>(cfg: &'x Arc<Config>, name: &str) -> &'x Arc<Resolver> {
-    if let Some(ref suf) = cfg.suffixes.get(name) {
-        return suf;
+/// Finds the routing rule and the resolver for the name
+fn trace(cfg: &Arc<Config>, name: &Name) -> Trace {
+    if cfg.services.contains_key(name) {
+        return Trace { route: Route::Static, resolver: None };
+    }
+    let route = if cfg.names.contains_key(name) {
+        Route::Name
+    } else if let Some((prefix, _)) = match_prefix(cfg, name.as_ref()) {
+        Route::Prefix(prefix.to_string())
+    } else if let Some((suffix, _)) = match_suffix(cfg, name.as_ref()) {
+        Route::Suffix(suffix.to_string())
+    } else {
+        Route::Fallthrough
+    };
+    Trace {
+        route,
+        resolver: get_suffix(cfg, name).describe(),
     }
-    for (idx, _) in name.match_indices('.') {
-        if let Some(suf) = cfg.suffixes.get(&name[idx+1..]) {
-            return suf;
+}
+
+/// Returns the longest prefix matching the name
+fn match_prefix<'x>(cfg: &'x Arc<Config>, name: &str)
+    -> Option<(&'x str, &'x Suffix)>
+{
+    let mut best: Option<(&String, &Suffix)> = None;
+    for (prefix, pre) in &cfg.prefixes {
+        if !name.starts_with(&prefix[..]) {
+            continue;
+        }
+        match best {
+            Some((best_prefix, _)) if best_prefix.len() >= prefix.len() => {}
+            _ => best = Some((prefix, pre)),
+        }
+    }
+    best.map(|(prefix, pre)| (&prefix[..], pre))
+}
+
+/// Finds the longest enabled suffix of the name
+///
+/// Suffixes are stored in lowercase, and `Name` is always lowercase, so
+/// they can be compared directly.
+pub(crate) fn match_suffix<'x, 'n>(cfg: &'x Arc<Config>, name: &'n str)
+    -> Option<(&'n str, &'x Suffix)>
+{
+    let candidates = Some(0).into_iter()
+        .chain(name.match_indices('.').map(|(idx, _)| idx+1));
+    for idx in candidates {
+        let suffix = &name[idx..];
+        if cfg.disabled_suffixes.contains(suffix) {
+            continue;
+        }
+        if let Some(suf) = cfg.suffixes.get(suffix) {
+            return Some((suffix, suf));
         }
     }
-    return &cfg.root;
+    None
+}
+
+fn with_ports(list: &IpList, ports: &[u16]) -> Address {
+    let mut result = Vec::with_capacity(list.iter().count()*ports.len());
+    for &port in ports {
+        for ip in list.iter() {
+            result.push(SocketAddr::new(*ip, port));
+        }
+    }
+    Address::from(&result[..])
 }
 
 impl ResolverFuture {
+    /// Spawns a one-shot future (i.e. a resolution)
     pub(crate) fn spawn<F>(&mut self, future: F)
         where F: Future<Item=FutureResult, Error=Void> + 'static,
     {
+        let future = Counted::new(future, &self.resolves);
+        self.futures.push(Box::new(future)
+            as Box<Future<Item=FutureResult, Error=Void>>)
+    }
+    /// Spawns a subscription task
+    ///
+    /// When called in scope of a cancelable subscription, the task is
+    /// dropped when the subscription is canceled.
+    pub(crate) fn spawn_subscription<F>(&mut self, future: F)
+        where F: Future<Item=FutureResult, Error=Void> + 'static,
+    {
+        let future = match self.scope {
+            Some(id) => {
+                let senders = match self.cancels.get_mut(&id) {
+                    Some(senders) => senders,
+                    // subscription is canceled while task was restarting
+                    None => return,
+                };
+                // forget tasks that are already finished
+                senders.retain(|tx| !tx.is_canceled());
+                let (tx, rx) = unsync::oneshot::channel();
+                senders.push(tx);
+                Box::new(Cancelable::new(id, future, rx))
+                    as Box<Future<Item=FutureResult, Error=Void>>
+            }
+            None => Box::new(future),
+        };
+        let future = match AtDepth::wrap(future) {
+            Ok(future) => Box::new(future)
+                as Box<Future<Item=FutureResult, Error=Void>>,
+            Err(future) => future,
+        };
+        let future = Counted::new(future, &self.subscriptions);
         self.futures.push(Box::new(future)
             as Box<Future<Item=FutureResult, Error=Void>>)
     }
+    /// Fails the request exceeding `Config::max_resolution_depth`
+    fn resolution_loop(&mut self, request: Request) {
+        use internal::Request::*;
+        let error = || Error::TemporaryError("resolution loop".into());
+        match request {
+            ResolveHost(name, tx) => {
+                warn!("{}: resolution loop detected for {:?}",
+                      self.label, name);
+                fail(&name, tx, error());
+            }
+            Resolve(name, tx) => {
+                warn!("{}: resolution loop detected for {:?}",
+                      self.label, name);
+                fail(&name, tx, error());
+            }
+            // there is no way to report an error to the subscriber, and
+            // closing the stream would make the outer subscription restart
+            // the loop, so the subscription just never gets a value
+            HostSubscribe(name, tx) => {
+                warn!("{}: resolution loop detected for {:?}",
+                      self.label, name);
+                SubscrFuture::spawn_in(self,
+                    HostNoOpSubscr { name, tx, value: None });
+            }
+            Subscribe(name, tx) => {
+                warn!("{}: resolution loop detected for {:?}",
+                      self.label, name);
+                SubscrFuture::spawn_in(self,
+                    NoOpSubscr { name, tx, value: None });
+            }
+            _ => unreachable!("only requests of resolver traits are nested"),
+        }
+    }
+    /// Runs `f` so that subscription tasks it spawns belong to `id`
+    pub(crate) fn in_scope<F>(&mut self, id: SubscriptionId, f: F)
+        where F: FnOnce(&mut ResolverFuture),
+    {
+        let prev = self.scope.replace(id);
+        f(self);
+        self.scope = prev;
+    }
+    fn timed<T>(&mut self, cfg: &Arc<Config>, kind: Kind, name: &Name,
+        tx: oneshot::Sender<Result<T, Error>>)
+        -> oneshot::Sender<Result<T, Error>>
+        where T: Send + fmt::Debug + 'static,
+    {
+        if !cfg.track_latency {
+            return tx;
+        }
+        let (itx, irx) = oneshot::channel();
+        let timed = Timed::new(name.clone(), kind, &self.latency, irx, tx);
+        self.spawn(timed);
+        itx
+    }
+    fn metered<T>(&mut self, cfg: &Arc<Config>, name: &Name,
+        tx: oneshot::Sender<Result<T, Error>>)
+        -> oneshot::Sender<Result<T, Error>>
+        where T: Send + fmt::Debug + 'static,
+    {
+        let metrics = match cfg.metrics {
+            Some(ref metrics) => Labeled::new(&self.label, metrics),
+            None => return tx,
+        };
+        let (itx, irx) = oneshot::channel();
+        self.spawn(Metered::new(name.clone(), metrics, irx, tx));
+        itx
+    }
+    /// Replies with `NameNotFound` if the name is in the negative cache
+    ///
+    /// Returns `None` if request is replied, otherwise sender which
+    /// remembers names that are not found.
+    fn negative<T>(&mut self, cfg: &Arc<Config>, lookup: Lookup,
+        name: &Name, tx: oneshot::Sender<Result<T, Error>>)
+        -> Option<oneshot::Sender<Result<T, Error>>>
+        where T: Send + fmt::Debug + 'static,
+    {
+        let ttl = match cfg.negative_cache_ttl {
+            Some(ttl) => ttl,
+            None => return Some(tx),
+        };
+        if self.negative.borrow_mut().contains(name, lookup) {
+            debug!("{}: {:?} is not found (cached)", self.label, name);
+            fail(name, tx, Error::NameNotFound);
+            return None;
+        }
+        let (itx, irx) = oneshot::channel();
+        self.spawn(Remember::new(name.clone(), lookup, ttl,
+            &self.negative, irx, tx));
+        Some(itx)
+    }
+    /// Replies from the positive cache or joins the request in flight
+    ///
+    /// Returns `None` if request is replied (or will be replied by the
+    /// request in flight), otherwise sender to pass to the resolver.
+    fn cached<K, T>(&mut self, cfg: &Arc<Config>, cache: SharedCache<K, T>,
+        name: &Name, key: K, tx: oneshot::Sender<Result<T, Error>>)
+        -> Option<oneshot::Sender<Result<T, Error>>>
+        where K: Hash + Eq + Clone + 'static,
+              T: Clone + Send + fmt::Debug + 'static,
+    {
+        let ttl = match cfg.positive_cache_ttl {
+            Some(ttl) => ttl,
+            None => return Some(tx),
+        };
+        let (itx, future) = cache::lookup(&cache, name, key, ttl, tx)?;
+        self.spawn(future);
+        Some(itx)
+    }
+    fn rewritten(&mut self, cfg: &Arc<Config>, name: &Name,
+        tx: oneshot::Sender<Result<Address, Error>>)
+        -> oneshot::Sender<Result<Address, Error>>
+    {
+        let rewriter = match cfg.rewriter {
+            Some(ref rewriter) => rewriter.clone(),
+            None => return tx,
+        };
+        let (itx, irx) = oneshot::channel();
+        self.spawn(ReplyMap::new(name.clone(), irx, tx,
+            move |addr| rewriter.rewrite(addr)));
+        itx
+    }
+    fn delay_restart(&mut self, cfg: &Arc<Config>, task: Box<Continuation>) {
+        let delay = task.name()
+            .and_then(|name| get_opts(cfg, name).restart_delay)
+            .unwrap_or(cfg.restart_delay);
+        // if timer fails, this usually means reactor is shutting down, so
+        // there is nothing better than dropping the task
+        let timeo = self.sleep(cfg, delay);
+        let label = self.label.clone();
+        self.spawn_subscription(timeo.then(move |res| match res {
+            Ok(()) => Ok(FutureResult::Restart { task }),
+            Err(e) => {
+                error!("{}: restart timer for {:?} failed: {}, \
+                    dropping task", label, task, e);
+                Ok(FutureResult::Done)
+            }
+        }));
+    }
     fn resolve_host(&mut self, cfg: &Arc<Config>,
         name: Name, tx: oneshot::Sender<Result<IpList, Error>>)
     {
@@ -117,10 +614,31 @@ impl ResolverFuture {
             reply(&name, tx, value.clone());
             return;
         }
-        get_suffix(cfg, name.as_ref()).resolve_host(self, cfg, name, tx);
+        let suffix = get_suffix(cfg, &name);
+        if cfg.fallthrough_on_temporary && !Arc::ptr_eq(suffix, &cfg.root) {
+            let (itx, irx) = oneshot::channel();
+            suffix.resolve_host(self, cfg, name.clone(), itx);
+            self.spawn(RootFallback::new(name, irx, tx,
+                |res, cfg, name, tx| {
+                    cfg.root.resolve_host(res, cfg, name, tx)
+                }));
+            return;
+        }
+        suffix.resolve_host(self, cfg, name, tx);
     }
     fn resolve_host_port(&mut self, cfg: &Arc<Config>,
         name: Name, port: u16, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        if cfg.middleware.is_empty() {
+            self.resolve_host_port_direct(cfg, name, port, tx);
+        } else {
+            let (itx, irx) = oneshot::channel();
+            self.resolve_host_port_direct(cfg, name.clone(), port, itx);
+            self.wrap_middleware(cfg, name, irx, tx);
+        }
+    }
+    fn resolve_host_port_direct(&mut self, cfg: &Arc<Config>,
+        name: Name, port: u16, tx: oneshot::Sender<Result<Address, Error>>)
     {
         // need to retry resolving static host because the config might just
         // arrived right now
@@ -128,44 +646,91 @@ impl ResolverFuture {
             reply(&name, tx, value.with_port(port));
             return;
         }
-        get_suffix(cfg, name.as_ref())
-            .resolve_host_port(self, cfg, name, port, tx);
+        let suffix = get_suffix(cfg, &name);
+        if cfg.fallthrough_on_temporary && !Arc::ptr_eq(suffix, &cfg.root) {
+            let (itx, irx) = oneshot::channel();
+            suffix.resolve_host_port(self, cfg, name.clone(), port, itx);
+            self.spawn(RootFallback::new(name, irx, tx,
+                move |res, cfg, name, tx| {
+                    cfg.root.resolve_host_port(res, cfg, name, port, tx)
+                }));
+            return;
+        }
+        suffix.resolve_host_port(self, cfg, name, port, tx);
     }
     fn resolve(&mut self, cfg: &Arc<Config>,
         name: Name, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        if cfg.middleware.is_empty() {
+            self.resolve_direct(cfg, name, tx);
+        } else {
+            let (itx, irx) = oneshot::channel();
+            self.resolve_direct(cfg, name.clone(), itx);
+            self.wrap_middleware(cfg, name, irx, tx);
+        }
+    }
+    fn wrap_middleware(&mut self, cfg: &Arc<Config>, name: Name,
+        rx: oneshot::Receiver<Result<Address, Error>>,
+        tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        let mut future: AddressFuture = Box::new(ResolveFuture(rx));
+        for middleware in cfg.middleware.iter().rev() {
+            future = middleware.around_resolve(&name, future);
+        }
+        self.spawn(SendResult(name, future, Some(tx)));
+    }
+    fn resolve_direct(&mut self, cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<Address, Error>>)
     {
         // need to retry resolving static host because the config might just
         // arrived right now
-        if let Some(value) = cfg.services.get(&name) {
-            reply(&name, tx, value.clone());
+        if let Some(value) = cfg.service(&name) {
+            reply(&name, tx, value);
+            return;
+        }
+        self.resolve_dynamic(cfg, name, tx);
+    }
+    fn resolve_dynamic(&mut self, cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        let suffix = get_suffix(cfg, &name);
+        if cfg.fallthrough_on_temporary && !Arc::ptr_eq(suffix, &cfg.root) {
+            let (itx, irx) = oneshot::channel();
+            suffix.resolve(self, cfg, name.clone(), itx);
+            self.spawn(RootFallback::new(name, irx, tx,
+                |res, cfg, name, tx| cfg.root.resolve(res, cfg, name, tx)));
             return;
         }
-        get_suffix(cfg, name.as_ref()).resolve(self, cfg, name, tx);
+        suffix.resolve(self, cfg, name, tx);
     }
     pub fn host_subscribe(&mut self, cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<IpList>)
     {
-        if let Some(value) = cfg.hosts.get(&name) {
+        if let Some(value) = static_host(cfg, &name) {
             let ok = tx.swap(value.clone()).is_ok();
             if ok {
-                SubscrFuture::spawn_in(self, HostNoOpSubscr { name, tx });
+                SubscrFuture::spawn_in(self, HostNoOpSubscr {
+                    name, tx, value: Some(value),
+                });
             }
             return;
         }
-        let sub = get_suffix(cfg, name.as_ref());
+        let sub = get_suffix(cfg, &name);
         sub.host_subscribe(self, sub, cfg, name, tx);
     }
     pub fn subscribe(&mut self, cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<Address>)
     {
-        if let Some(value) = cfg.services.get(&name) {
+        if let Some(value) = cfg.service(&name) {
             let ok = tx.swap(value.clone()).is_ok();
             if ok {
-                SubscrFuture::spawn_in(self, NoOpSubscr { name, tx });
+                SubscrFuture::spawn_in(self, NoOpSubscr {
+                    name, tx, value: Some(value),
+                });
             }
             return;
         }
-        let sub = get_suffix(cfg, name.as_ref());
+        let sub = get_suffix(cfg, &name);
         sub.subscribe(self, sub, cfg, name, tx);
     }
 }
@@ -176,28 +741,195 @@ impl Future for ResolverFuture {
     fn poll(&mut self) -> Result<Async<()>, ()> {
         use internal::Request::*;
         if let Some(mut cfg) = self.current_config.clone() {
-            loop {
+            while !self.shutting_down {
+                self.scope = None;
+                // requests sent from outside of the router are at depth zero
+                set_depth(Some(0));
                 let inp = self.requests.poll()
-                    .map_err(|_| error!("Router input stream is failed"))?;
+                    .map_err(|_| error!("{}: input stream is failed",
+                                        self.label))?;
+                let inp = match inp {
+                    Async::Ready(Some(Cancelable(id, request))) => {
+                        self.cancels.entry(id).or_default();
+                        self.scope = Some(id);
+                        Async::Ready(Some(*request))
+                    }
+                    Async::Ready(Some(Nested(depth, request))) => {
+                        set_depth(Some(depth));
+                        if depth > cfg.max_resolution_depth {
+                            self.resolution_loop(*request);
+                            continue;
+                        }
+                        Async::Ready(Some(*request))
+                    }
+                    inp => inp,
+                };
                 match inp {
                     Async::Ready(Some(ResolveHost(n, tx))) => {
+                        let tx = self.metered(&cfg, &n, tx);
+                        let tx = self.timed(&cfg, Kind::Host, &n, tx);
+                        let tx = match self.negative(&cfg, Lookup::Host,
+                                                     &n, tx)
+                        {
+                            Some(tx) => tx,
+                            None => continue,
+                        };
+                        let cache = self.host_cache.clone();
+                        let tx = match self.cached(&cfg, cache,
+                                                   &n, n.clone(), tx)
+                        {
+                            Some(tx) => tx,
+                            None => continue,
+                        };
                         self.resolve_host(&cfg, n, tx);
                     }
                     Async::Ready(Some(ResolveHostPort(n, p, tx))) => {
+                        let tx = self.metered(&cfg, &n, tx);
+                        let tx = self.rewritten(&cfg, &n, tx);
+                        let tx = self.timed(&cfg, Kind::HostPort, &n, tx);
+                        let tx = match self.negative(&cfg, Lookup::Host,
+                                                     &n, tx)
+                        {
+                            Some(tx) => tx,
+                            None => continue,
+                        };
+                        let cache = self.host_port_cache.clone();
+                        let tx = match self.cached(&cfg, cache,
+                                                   &n, (n.clone(), p), tx)
+                        {
+                            Some(tx) => tx,
+                            None => continue,
+                        };
                         self.resolve_host_port(&cfg, n, p, tx);
                     }
+                    Async::Ready(Some(ResolveHostPorts(n, ports, tx))) => {
+                        let tx = self.metered(&cfg, &n, tx);
+                        let (itx, irx) = oneshot::channel();
+                        let itx = match self.negative(&cfg, Lookup::Host,
+                                                      &n, itx)
+                        {
+                            Some(itx) => itx,
+                            None => {
+                                fail(&n, tx, Error::NameNotFound);
+                                continue;
+                            }
+                        };
+                        self.resolve_host(&cfg, n.clone(), itx);
+                        let rewriter = cfg.rewriter.clone();
+                        self.spawn(ReplyMap::new(n, irx, tx, move |list| {
+                            let addr = with_ports(&list, &ports);
+                            match rewriter {
+                                Some(ref rw) => rw.rewrite(addr),
+                                None => addr,
+                            }
+                        }));
+                    }
                     Async::Ready(Some(Resolve(n, tx))) => {
+                        let tx = self.metered(&cfg, &n, tx);
+                        let tx = self.rewritten(&cfg, &n, tx);
+                        let tx = self.timed(&cfg, Kind::Service, &n, tx);
+                        let tx = match self.negative(&cfg, Lookup::Service,
+                                                     &n, tx)
+                        {
+                            Some(tx) => tx,
+                            None => continue,
+                        };
+                        let cache = self.service_cache.clone();
+                        let tx = match self.cached(&cfg, cache,
+                                                   &n, n.clone(), tx)
+                        {
+                            Some(tx) => tx,
+                            None => continue,
+                        };
                         self.resolve(&cfg, n, tx);
                     }
+                    Async::Ready(Some(ResolveBypassStatic(n, tx))) => {
+                        let tx = self.metered(&cfg, &n, tx);
+                        self.resolve_dynamic(&cfg, n, tx);
+                    }
+                    Async::Ready(Some(MatchedSuffix(n, tx))) => {
+                        let suffix = match_suffix(&cfg, n.as_ref())
+                            .map(|(suffix, _)| suffix.to_string());
+                        reply(&n, tx, suffix);
+                    }
+                    Async::Ready(Some(ResolveTraced(n, tx))) => {
+                        let tx = self.metered(&cfg, &n, tx);
+                        let trace = trace(&cfg, &n);
+                        let (itx, irx) = oneshot::channel();
+                        let itx = self.rewritten(&cfg, &n, itx);
+                        self.resolve(&cfg, n.clone(), itx);
+                        self.spawn(ReplyMap::new(n, irx, tx,
+                            move |addr| (addr, trace)));
+                    }
+                    Async::Ready(Some(Stats(tx))) => {
+                        tx.send(Ok(RouterStats {
+                            resolves: self.resolves.get(),
+                            subscriptions: self.subscriptions.get(),
+                        })).ok();
+                    }
+                    Async::Ready(Some(RoutingTable(tx))) => {
+                        let table = table::RoutingTable::from_config(&cfg);
+                        tx.send(Ok(table)).ok();
+                    }
+                    Async::Ready(Some(SetSuffixEnabled(suffix, enabled))) => {
+                        cfg = self.set_suffix_enabled(&cfg, suffix, enabled);
+                    }
                     Async::Ready(Some(HostSubscribe(n, tx))) => {
                         self.host_subscribe(&cfg, n, tx);
                     }
                     Async::Ready(Some(Subscribe(n, tx))) => {
-                        self.subscribe(&cfg, n, tx);
+                        // rewriter is installed for every subscription,
+                        // because it may be set by a later config
+                        let (mut itx, irx) = slot::channel();
+                        itx.inherit_errors(&tx);
+                        self.subscribe(&cfg, n.clone(), itx);
+                        SubscrFuture::spawn_in(self,
+                            RewriteSubscr::new(&cfg, n, irx, tx));
+                    }
+                    Async::Ready(Some(PinHost(n, tx))) => {
+                        let (itx, irx) = oneshot::channel();
+                        self.resolve_host(&cfg, n.clone(), itx);
+                        self.spawn(PinStatic::new(n, irx, tx,
+                            |cfg, name, value| {
+                                cfg.hosts.insert(name, value);
+                            }));
+                    }
+                    Async::Ready(Some(Pin(n, tx))) => {
+                        let (itx, irx) = oneshot::channel();
+                        self.resolve(&cfg, n.clone(), itx);
+                        self.spawn(PinStatic::new(n, irx, tx,
+                            |cfg, name, value| {
+                                cfg.services.insert(name, value);
+                            }));
+                    }
+                    Async::Ready(Some(Refresh)) => {
+                        debug!("{}: network changed, refreshing \
+                            subscriptions", self.label);
+                        self.notify(Update::Refresh);
+                    }
+                    Async::Ready(Some(Shutdown)) => {
+                        self.shutdown();
                     }
                     Async::Ready(Some(Task(mut task))) => {
                         task.restart(self, &cfg);
                     }
+                    Async::Ready(Some(WatchShutdown(tx))) => {
+                        self.shutdown_watchers.retain(|tx| !tx.is_canceled());
+                        self.shutdown_watchers.push(tx);
+                    }
+                    Async::Ready(Some(request @ Cancelable(..))) |
+                    Async::Ready(Some(request @ Nested(..))) => {
+                        // router never wraps requests twice, but if it
+                        // happens, dropping the request closes its reply
+                        // channel, so the caller gets an error
+                        error!("{}: request is wrapped twice: {:?}",
+                               self.label, request);
+                    }
+                    Async::Ready(Some(Cancel(id))) => {
+                        // dropping senders wakes up the tasks, they are
+                        // stopped when polled below
+                        self.cancels.remove(&id);
+                    }
                     Async::Ready(None) => {
                         break;
                     }
@@ -206,45 +938,86 @@ impl Future for ResolverFuture {
                     }
                 }
             }
-            while let Ok(Async::Ready(Some(state))) = self.futures.poll() {
+            self.scope = None;
+            set_depth(None);
+            let mut budget = cfg.poll_fairness;
+            loop {
                 use self::FutureResult::*;
+                let state = match self.futures.poll() {
+                    Ok(Async::Ready(Some(state))) => state,
+                    // Futures spawned by the arms below (i.e. restarted
+                    // subscriptions) are queued by `FuturesUnordered::push`
+                    // and polled before it returns `NotReady`. It may also
+                    // return `NotReady` early to yield to other tasks, but
+                    // only after notifying the current task, so no future
+                    // waits for an external wakeup.
+                    Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                    Err(e) => unreachable(e),
+                };
                 match state {
                     Done => {}
-                    Stop => return Ok(Async::Ready(())),
+                    Stop => {
+                        debug!("{}: config stream closed, shutting down",
+                               self.label);
+                        self.notify_shutdown();
+                        return Ok(Async::Ready(()));
+                    }
                     UpdateConfig { cfg: new_cfg, next } => {
-                        self.current_config = Some(new_cfg.clone());
-                        cfg = new_cfg;
-                        let (tx, rx) = oneshot::channel();
-                        let tx = mem::replace(&mut self.update_tx, tx);
-                        self.update_rx = rx.shared();
-                        tx.send(()).ok();
+                        cfg = self.update_config(new_cfg);
+                        self.futures.push(next);
+                    }
+                    UpdateTiming { timing, next } => {
+                        self.update_timing(timing);
+                        cfg = self.current_config.clone()
+                            .expect("config is set");
                         self.futures.push(next);
                     }
+                    TimingClosed => {
+                        self.followers -= 1;
+                    }
                     Restart { mut task } => {
                         task.restart(self, &cfg);
                     }
                     DelayRestart { task } => {
-                        self.futures.push(Box::new(
-                            Timeout::new(cfg.restart_delay, &self.handle)
-                            .expect("can always set timeout")
-                            .map_err(|_| -> Void { unreachable!() })
-                            .map(move |_| Restart { task })
-                        ) as Box<Future<Item=_, Error=_>>);
+                        self.delay_restart(&cfg, task);
                     }
                 }
+                if let Some(ref mut budget) = budget {
+                    *budget -= 1;
+                    if *budget == 0 {
+                        // let other tasks run, and get back to requests
+                        // on the next iteration of the event loop
+                        task::current().notify();
+                        break;
+                    }
+                }
+            }
+            if self.shutting_down && self.futures.len() <= self.followers {
+                debug!("{}: all requests are drained, shut down",
+                       self.label);
+                self.notify_shutdown();
+                return Ok(Async::Ready(()));
             }
         } else {
             while let Ok(Async::Ready(Some(state))) = self.futures.poll() {
                 use self::FutureResult::*;
                 match state {
                     Done => {}
-                    Stop => return Ok(Async::Ready(())),
+                    Stop => {
+                        debug!("{}: config stream closed, shutting down",
+                               self.label);
+                        self.notify_shutdown();
+                        return Ok(Async::Ready(()));
+                    }
+                    UpdateTiming { timing, next } => {
+                        self.update_timing(timing);
+                        self.futures.push(next);
+                    }
+                    TimingClosed => {
+                        self.followers -= 1;
+                    }
                     UpdateConfig { cfg, next } => {
-                        self.current_config = Some(cfg);
-                        let (tx, rx) = oneshot::channel();
-                        let tx = mem::replace(&mut self.update_tx, tx);
-                        self.update_rx = rx.shared();
-                        tx.send(()).ok();
+                        self.update_config(cfg);
                         self.futures.push(next);
                         // we have a config, so we will not recurse more
                         return self.poll()
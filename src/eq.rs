@@ -0,0 +1,91 @@
+use std::net::{IpAddr, SocketAddr};
+
+use abstract_ns::{Address, IpList};
+
+
+/// Describes when two address sets are considered equal
+///
+/// This is used to find out whether the address is changed and must be
+/// delivered to the application. Set it with `Config::address_eq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressEqMode {
+    /// Addresses are compared using `PartialEq`
+    ///
+    /// This is the default. In this mode single-name subscriptions
+    /// deliver every value yielded by the resolver.
+    Exact,
+    /// Addresses are equal if they contain the same members
+    ///
+    /// Order of the addresses within a set (or in the `IpList`) and
+    /// duplicates are ignored, but priorities are still significant.
+    /// Values equal to the previous one aren't delivered to the
+    /// application.
+    SetEquality,
+}
+
+pub(crate) trait SetEq {
+    fn set_eq(&self, other: &Self) -> bool;
+}
+
+fn sorted<T: Ord, I: Iterator<Item=T>>(iter: I) -> Vec<T> {
+    let mut items = iter.collect::<Vec<_>>();
+    items.sort();
+    items.dedup();
+    items
+}
+
+impl SetEq for Address {
+    fn set_eq(&self, other: &Address) -> bool {
+        let mine = self.iter()
+            .map(|set| sorted::<SocketAddr, _>(set.addresses()));
+        let theirs = other.iter()
+            .map(|set| sorted::<SocketAddr, _>(set.addresses()));
+        mine.eq(theirs)
+    }
+}
+
+impl SetEq for IpList {
+    fn set_eq(&self, other: &IpList) -> bool {
+        sorted::<&IpAddr, _>(self.iter()) == sorted(other.iter())
+    }
+}
+
+impl AddressEqMode {
+    pub(crate) fn equal<T: PartialEq + SetEq>(&self, a: &T, b: &T) -> bool {
+        match *self {
+            AddressEqMode::Exact => a == b,
+            AddressEqMode::SetEquality => a.set_eq(b),
+        }
+    }
+}
+
+/// Remembers the last value sent to filter out equal ones
+pub(crate) struct Dedup<T> {
+    mode: AddressEqMode,
+    last: Option<T>,
+}
+
+impl<T: PartialEq + SetEq + Clone> Dedup<T> {
+    pub fn new(mode: AddressEqMode) -> Dedup<T> {
+        Dedup { mode, last: None }
+    }
+    pub fn set_mode(&mut self, mode: AddressEqMode) {
+        self.mode = mode;
+    }
+    /// Returns true if value must be sent to the application
+    pub fn is_new(&mut self, value: &T) -> bool {
+        match self.mode {
+            // exact mode forwards everything, as it always did
+            AddressEqMode::Exact => true,
+            AddressEqMode::SetEquality => {
+                if let Some(ref last) = self.last {
+                    if last.set_eq(value) {
+                        return false;
+                    }
+                }
+                self.last = Some(value.clone());
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::sync::Arc;
+
+use abstract_ns::{Name, Error};
+use futures::{Future, Async};
+use futures::sync::oneshot;
+use void::Void;
+
+use config::Config;
+use coroutine::{ResolverFuture, FutureResult, Continuation};
+use internal::{reply, fail};
+
+
+/// Waits for a suffix resolver and retries in fallthrough on temporary error
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct RootFallback<T, F> {
+    rx: oneshot::Receiver<Result<T, Error>>,
+    retry: Option<Retry<T, F>>,
+}
+
+pub(crate) struct Retry<T, F> {
+    name: Name,
+    tx: oneshot::Sender<Result<T, Error>>,
+    func: F,
+}
+
+impl<T, F> RootFallback<T, F>
+    where F: FnOnce(&mut ResolverFuture, &Arc<Config>,
+                    Name, oneshot::Sender<Result<T, Error>>) + 'static,
+{
+    pub fn new(name: Name, rx: oneshot::Receiver<Result<T, Error>>,
+        tx: oneshot::Sender<Result<T, Error>>, func: F)
+        -> RootFallback<T, F>
+    {
+        RootFallback {
+            rx,
+            retry: Some(Retry { name, tx, func }),
+        }
+    }
+}
+
+impl<T, F> fmt::Debug for Retry<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Retry")
+        .field("name", &self.name)
+        .finish()
+    }
+}
+
+impl<T, F> Continuation for Option<Retry<T, F>>
+    where F: FnOnce(&mut ResolverFuture, &Arc<Config>,
+                    Name, oneshot::Sender<Result<T, Error>>) + 'static,
+{
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let Retry { name, tx, func } = self.take()
+            .expect("continuation called twice");
+        func(res, cfg, name, tx)
+    }
+}
+
+impl<T, F> Future for RootFallback<T, F>
+    where T: Send + fmt::Debug + 'static,
+          F: FnOnce(&mut ResolverFuture, &Arc<Config>,
+                    Name, oneshot::Sender<Result<T, Error>>) + 'static,
+{
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        let retry = self.retry.take().expect("future polled twice");
+        match result {
+            Ok(value) => reply(&retry.name, retry.tx, value),
+            Err(Error::TemporaryError(e)) => {
+                debug!("Temporary error resolving {:?}: {}, \
+                    trying fallthrough", retry.name, e);
+                return Ok(Async::Ready(FutureResult::Restart {
+                    task: Box::new(Some(retry)),
+                }));
+            }
+            Err(e) => fail(&retry.name, retry.tx, e),
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
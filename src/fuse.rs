@@ -51,4 +51,9 @@ impl<S> Fuse<S> {
     pub fn is_done(&self) -> bool {
         self.done
     }
+
+    /// Marks stream as finished, so it's resubscribed on next restart
+    pub fn close(&mut self) {
+        self.done = true;
+    }
 }
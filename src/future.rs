@@ -1,14 +1,28 @@
 //! Futures and streams returned from router
 //!
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 
-use abstract_ns::{IpList, Address, Error};
+use abstract_ns::{Name, IpList, Address, Error, Resolve};
+use abstract_ns::addr::union;
 use futures::sync::oneshot;
 use futures::{Future, Async, Stream};
-use void::Void;
+use void::{Void, unreachable};
 
-use async_slot as slot;
+use slot;
+use cell::ConfigCell;
 use config::Config;
+use record::{RecordType, Record};
+use router::Router;
+use stats::RouterStats;
+use table::RoutingTable;
+use timer::{Timer, Delay};
+use trace::Trace;
 
 /// A future returned from `Router::resolve_host`
 #[derive(Debug)]
@@ -21,6 +35,102 @@ pub struct ResolveHostFuture(
 #[must_use = "futures do nothing unless polled"]
 pub struct ResolveFuture(pub(crate) oneshot::Receiver<Result<Address, Error>>);
 
+/// A future returned from `Router::resolve_one_with_load`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct PickFuture {
+    pub(crate) future: ResolveFuture,
+    pub(crate) loads: HashMap<SocketAddr, usize>,
+}
+
+/// A future returned from `Router::resolve_by_port`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct ByPortFuture(pub(crate) ResolveFuture);
+
+/// A future returned from `Router::resolve_record`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct RecordFuture(pub(crate) RecordState);
+
+#[derive(Debug)]
+pub(crate) enum RecordState {
+    Host(ResolveHostFuture, RecordType),
+    Service(ResolveFuture),
+}
+
+/// A future returned from `Router::pin_static` and `Router::pin_static_host`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct PinFuture(pub(crate) oneshot::Receiver<Result<(), Error>>);
+
+/// A future returned from `Router::matched_suffix`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct SuffixFuture(
+    pub(crate) oneshot::Receiver<Result<Option<String>, Error>>);
+
+/// A future returned from `Router::routing_table`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct RoutingTableFuture(
+    pub(crate) oneshot::Receiver<Result<RoutingTable, Error>>);
+
+/// A future returned from `Router::stats`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct RouterStatsFuture(
+    pub(crate) oneshot::Receiver<Result<RouterStats, Error>>);
+
+/// A future returned from `Router::resolve_traced`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct TracedFuture(
+    pub(crate) oneshot::Receiver<Result<(Address, Trace), Error>>);
+
+/// A future returned from `Router::resolve_many`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct ResolveManyFuture {
+    futures: Vec<Option<ResolveFuture>>,
+    results: Vec<Option<Result<Address, Error>>>,
+}
+
+/// A future returned from `Router::resolve_hosts_many`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct ResolveHostsManyFuture {
+    futures: Vec<ResolveHostFuture>,
+    ips: Vec<IpAddr>,
+}
+
+/// A future returned from `Router::resolve_retry`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct RetryFuture {
+    router: Router,
+    name: Name,
+    retries_left: usize,
+    backoff: Duration,
+    timer: Arc<Timer>,
+    state: RetryState,
+}
+
+#[derive(Debug)]
+enum RetryState {
+    Resolving(ResolveFuture),
+    Sleeping(Delay),
+}
+
+/// A future returned from `ResolveFuture::timeout` and
+/// `ResolveHostFuture::timeout`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct TimeoutFuture<F> {
+    future: F,
+    timer: Option<Delay>,
+}
+
 /// A stream returned from `Router::host_subscribe`
 #[derive(Debug)]
 #[must_use = "streams do nothing unless polled"]
@@ -31,10 +141,89 @@ pub struct HostStream(pub(crate) slot::Receiver<IpList>);
 #[must_use = "streams do nothing unless polled"]
 pub struct AddrStream(pub(crate) slot::Receiver<Address>);
 
+/// A future returned from `Router::first_value`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct FirstValue(pub(crate) AddrStream);
+
+/// A stream returned from `Router::subscribe_fallible`
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct FallibleStream {
+    stream: AddrStream,
+    shutdown: oneshot::Receiver<()>,
+    done: bool,
+}
+
+/// A stream of errors returned from `Router::subscribe_with_errors`
+///
+/// Only the latest error is kept if the stream isn't polled fast enough.
+/// The stream is finished when the subscription is closed.
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct ErrorStream(pub(crate) slot::Receiver<Error>);
+
+/// A stream returned from `Router::subscribe_with_init_timeout`
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct InitTimeoutStream {
+    stream: AddrStream,
+    timer: Option<Delay>,
+}
+
+/// A stream returned from `AddrStream::primary_changes`
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct PrimaryChanges {
+    stream: AddrStream,
+    last: Option<SocketAddr>,
+}
+
+/// A stream returned from `AddrStream::canonical`
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct Canonical {
+    stream: AddrStream,
+    last: Option<Vec<SocketAddr>>,
+}
+
+/// A picker returned from `AddrStream::round_robin`
+#[derive(Debug)]
+pub struct RoundRobin {
+    stream: AddrStream,
+    addresses: Vec<SocketAddr>,
+    index: usize,
+}
+
+/// A picker returned from `AddrStream::weighted_picker`
+#[derive(Debug)]
+pub struct WeightedPicker {
+    stream: AddrStream,
+    address: Option<Address>,
+}
+
+/// A stream returned from `union_streams`
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct UnionStream {
+    sources: Vec<(AddrStream, Option<Address>)>,
+    last: Option<Address>,
+}
+
+/// A stream returned from `AddrStream::scan_state` and
+/// `HostStream::scan_state`
+#[must_use = "streams do nothing unless polled"]
+pub struct ScanState<T, S, F> {
+    stream: T,
+    state: S,
+    func: F,
+}
+
 /// A sink that updates router created using `Router::updating_config`
 #[derive(Debug)]
 #[must_use = "sinks do nothing unless polled"]
-pub struct UpdateSink(pub(crate) slot::Sender<Arc<Config>>);
+pub struct UpdateSink(pub(crate) slot::Sender<Arc<Config>>,
+    pub(crate) Rc<ConfigCell>);
 
 
 impl UpdateSink {
@@ -44,6 +233,184 @@ impl UpdateSink {
     pub fn update(&self, config: &Arc<Config>) -> bool {
         self.0.swap(config.clone()).is_ok()
     }
+
+    /// Update a part of the config
+    ///
+    /// Function `f` is called with a copy of the config currently used by
+    /// the router (see `current_config`), and the modified config is sent
+    /// to the router as in `update`. For example:
+    ///
+    /// ```rust,ignore
+    /// update_sink.update_with(|cfg| {
+    ///     cfg.add_host(&"localhost".parse().unwrap(),
+    ///                  vec!["127.0.0.1".parse().unwrap()]);
+    /// });
+    /// ```
+    ///
+    /// Updates sent but not yet applied by the router are not visible
+    /// to `f`, so if `update_with` (or `update`) is called several times
+    /// before the router picks up the config, only the last update
+    /// wins.
+    ///
+    /// Returns `false` (and doesn't call `f`) if the router is shut down
+    /// or hasn't applied the first config yet.
+    pub fn update_with<F: FnOnce(&mut Config)>(&self, f: F) -> bool {
+        let mut cfg = match self.1.get() {
+            Some(cfg) => cfg,
+            None => return false,
+        };
+        f(Arc::make_mut(&mut cfg));
+        self.update(&cfg)
+    }
+
+    /// Returns the config currently used by the router
+    ///
+    /// This is the latest config applied by the router, including timing
+    /// updates and suffixes disabled by `Router::disable_suffix`. A config
+    /// passed to `update` is reflected here only after the router picks
+    /// it up (on the next iteration of the event loop). Returns `None`
+    /// until the router has applied the first config.
+    ///
+    /// There is no such method on `Router`: config holds resolvers which
+    /// may be bound to the thread of the router, while `Router` itself is
+    /// `Send`. So the config is only available through this sink, which
+    /// lives on the same thread.
+    pub fn current_config(&self) -> Option<Arc<Config>> {
+        self.1.get()
+    }
+}
+
+impl HostStream {
+    /// Folds every update into the accumulated state
+    ///
+    /// Function `f` is called for each new list of addresses and can
+    /// update the state in place. The returned stream yields a copy of
+    /// the state after each update and finishes when this stream does.
+    pub fn scan_state<S, F>(self, init: S, f: F) -> ScanState<Self, S, F>
+        where S: Clone, F: FnMut(&mut S, &IpList),
+    {
+        ScanState {
+            stream: self,
+            state: init,
+            func: f,
+        }
+    }
+}
+
+/// Merges several address streams into one
+///
+/// The returned stream yields union (see `abstract_ns::addr::union`) of
+/// the latest values of all the streams whenever any of them changes.
+/// Intermediate values are skipped if the stream isn't polled fast
+/// enough, the same as for the streams returned by the router.
+///
+/// When one of the streams is finished its addresses are removed from the
+/// union, others keep contributing. The returned stream is finished when
+/// all streams are.
+pub fn union_streams(streams: Vec<AddrStream>) -> UnionStream {
+    UnionStream {
+        sources: streams.into_iter().map(|s| (s, None)).collect(),
+        last: None,
+    }
+}
+
+impl AddrStream {
+    /// Returns a stream that yields only when primary address changes
+    ///
+    /// Primary address is the first address of the highest priority set.
+    /// Updates which only change other addresses are skipped. Updates with
+    /// an empty address are skipped too, so primary address isn't reset by
+    /// temporary absence of addresses.
+    pub fn primary_changes(self) -> PrimaryChanges {
+        PrimaryChanges {
+            stream: self,
+            last: None,
+        }
+    }
+
+    /// Returns a stream of addresses in canonical form
+    ///
+    /// Canonical form is a vector of all addresses (regardless of their
+    /// priority and weight) sorted by IP and then by port, without
+    /// duplicates. Stream yields only when canonical form changes, so
+    /// it's a stable input for hashing (e.g. for consistent hashing ring
+    /// membership).
+    pub fn canonical(self) -> Canonical {
+        Canonical {
+            stream: self,
+            last: None,
+        }
+    }
+
+    /// Returns a picker which cycles through the addresses
+    ///
+    /// `RoundRobin::pick` returns addresses of the highest priority set
+    /// one by one (weights are ignored), starting over when the end of the
+    /// set is reached. When the stream yields a new address, the picker
+    /// continues with it from the same position (or from the start if the
+    /// new set is shorter).
+    pub fn round_robin(self) -> RoundRobin {
+        RoundRobin {
+            stream: self,
+            addresses: Vec::new(),
+            index: 0,
+        }
+    }
+
+    /// Returns a picker which selects a random address by weight
+    ///
+    /// `WeightedPicker::pick` selects an address of the highest priority
+    /// set (others are only fallbacks), with probability proportional to
+    /// its weight. This is the selection algorithm of SRV records. If all
+    /// weights of the set are zero, every address is equally likely.
+    ///
+    /// Selection is done by `Address::pick_one`, so the thread-local
+    /// random generator of `abstract-ns` is used.
+    pub fn weighted_picker(self) -> WeightedPicker {
+        WeightedPicker {
+            stream: self,
+            address: None,
+        }
+    }
+
+    /// Folds every update into the accumulated state
+    ///
+    /// Function `f` is called for each new address and can update the
+    /// state in place. The returned stream yields a copy of the state
+    /// after each update and finishes when this stream does.
+    pub fn scan_state<S, F>(self, init: S, f: F) -> ScanState<Self, S, F>
+        where S: Clone, F: FnMut(&mut S, &Address),
+    {
+        ScanState {
+            stream: self,
+            state: init,
+            func: f,
+        }
+    }
+}
+
+impl ResolveHostFuture {
+    /// Fails with `Error::TemporaryError` if not resolved within `timeout`
+    ///
+    /// If the name is resolved at the same time as the timer expires,
+    /// the result of resolution is returned.
+    pub fn timeout<T: Timer>(self, timeout: Duration, timer: &T)
+        -> TimeoutFuture<Self>
+    {
+        TimeoutFuture::new(self, timeout, timer)
+    }
+}
+
+impl ResolveFuture {
+    /// Fails with `Error::TemporaryError` if not resolved within `timeout`
+    ///
+    /// If the name is resolved at the same time as the timer expires,
+    /// the result of resolution is returned.
+    pub fn timeout<T: Timer>(self, timeout: Duration, timer: &T)
+        -> TimeoutFuture<Self>
+    {
+        TimeoutFuture::new(self, timeout, timer)
+    }
 }
 
 impl Future for ResolveHostFuture {
@@ -72,6 +439,391 @@ impl Future for ResolveFuture {
     }
 }
 
+impl ResolveManyFuture {
+    pub(crate) fn new(futures: Vec<ResolveFuture>) -> ResolveManyFuture {
+        ResolveManyFuture {
+            results: futures.iter().map(|_| None).collect(),
+            futures: futures.into_iter().map(Some).collect(),
+        }
+    }
+}
+
+impl Future for ResolveManyFuture {
+    type Item = Vec<Result<Address, Error>>;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<Self::Item>, Void> {
+        let mut done = true;
+        for (slot, result) in self.futures.iter_mut().zip(&mut self.results) {
+            let value = match *slot {
+                Some(ref mut future) => match future.poll() {
+                    Ok(Async::NotReady) => {
+                        done = false;
+                        continue;
+                    }
+                    Ok(Async::Ready(addr)) => Ok(addr),
+                    Err(e) => Err(e),
+                },
+                None => continue,
+            };
+            *slot = None;
+            *result = Some(value);
+        }
+        if !done {
+            return Ok(Async::NotReady);
+        }
+        Ok(Async::Ready(self.results.drain(..)
+            .map(|r| r.expect("all futures are resolved"))
+            .collect()))
+    }
+}
+
+impl ResolveHostsManyFuture {
+    pub(crate) fn new(futures: Vec<ResolveHostFuture>)
+        -> ResolveHostsManyFuture
+    {
+        ResolveHostsManyFuture {
+            futures,
+            ips: Vec::new(),
+        }
+    }
+}
+
+impl Future for ResolveHostsManyFuture {
+    type Item = IpList;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<IpList>, Error> {
+        let mut idx = 0;
+        while idx < self.futures.len() {
+            match self.futures[idx].poll() {
+                Ok(Async::NotReady) => {
+                    idx += 1;
+                    continue;
+                }
+                Ok(Async::Ready(list)) => self.ips.extend(list.iter()),
+                Err(Error::NameNotFound) => {}
+                Err(e) => return Err(e),
+            }
+            drop(self.futures.swap_remove(idx));
+        }
+        if !self.futures.is_empty() {
+            return Ok(Async::NotReady);
+        }
+        let mut ips = mem::take(&mut self.ips);
+        ips.sort();
+        ips.dedup();
+        Ok(Async::Ready(ips.into()))
+    }
+}
+
+impl Future for PickFuture {
+    type Item = SocketAddr;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<SocketAddr>, Error> {
+        match self.future.poll()? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(addr) => {
+                least_loaded(&addr, &self.loads)
+                .map(Async::Ready)
+                .ok_or(Error::NameNotFound)
+            }
+        }
+    }
+}
+
+impl Future for ByPortFuture {
+    type Item = HashMap<u16, Vec<IpAddr>>;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Self::Item>, Error> {
+        let addr = match self.0.poll()? {
+            Async::NotReady => return Ok(Async::NotReady),
+            Async::Ready(addr) => addr,
+        };
+        let mut ports = HashMap::new();
+        for set in addr.iter() {
+            for sa in set.addresses() {
+                let ips = ports.entry(sa.port()).or_insert_with(Vec::new);
+                if !ips.contains(&sa.ip()) {
+                    ips.push(sa.ip());
+                }
+            }
+        }
+        Ok(Async::Ready(ports))
+    }
+}
+
+impl Future for RecordFuture {
+    type Item = Record;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Record>, Error> {
+        match self.0 {
+            RecordState::Host(ref mut future, kind) => {
+                let list = match future.poll()? {
+                    Async::NotReady => return Ok(Async::NotReady),
+                    Async::Ready(list) => list,
+                };
+                let ips = list.iter()
+                    .filter(|ip| match kind {
+                        RecordType::A => ip.is_ipv4(),
+                        RecordType::AAAA => ip.is_ipv6(),
+                        RecordType::SRV => unreachable!(),
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if ips.is_empty() {
+                    return Err(Error::NameNotFound);
+                }
+                Ok(Async::Ready(Record::Ip(ips.into())))
+            }
+            RecordState::Service(ref mut future) => {
+                Ok(future.poll()?.map(Record::Srv))
+            }
+        }
+    }
+}
+
+impl RoundRobin {
+    /// Returns the next address to connect to
+    ///
+    /// This polls the underlying stream to pick up the latest address, so
+    /// it must be called in the context of a task, the same as `poll`.
+    /// Returns `None` if no address is received yet or the latest
+    /// address is empty. When the stream is finished, the last address is
+    /// used.
+    pub fn pick(&mut self) -> Option<SocketAddr> {
+        loop {
+            match self.stream.poll() {
+                Ok(Async::Ready(Some(addr))) => {
+                    self.addresses = addr.addresses_at(0).collect();
+                }
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => unreachable(e),
+            }
+        }
+        if self.addresses.is_empty() {
+            return None;
+        }
+        if self.index >= self.addresses.len() {
+            self.index = 0;
+        }
+        let addr = self.addresses[self.index];
+        self.index += 1;
+        Some(addr)
+    }
+}
+
+impl WeightedPicker {
+    /// Returns a random address to connect to
+    ///
+    /// This polls the underlying stream to pick up the latest address, so
+    /// it must be called in the context of a task, the same as `poll`.
+    /// Returns `None` if no address is received yet or the latest
+    /// address is empty. When the stream is finished, the last address is
+    /// used.
+    pub fn pick(&mut self) -> Option<SocketAddr> {
+        loop {
+            match self.stream.poll() {
+                Ok(Async::Ready(Some(addr))) => self.address = Some(addr),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => unreachable(e),
+            }
+        }
+        self.address.as_ref().and_then(|addr| addr.pick_one())
+    }
+}
+
+impl Stream for PrimaryChanges {
+    type Item = SocketAddr;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<Option<SocketAddr>>, Void> {
+        loop {
+            let addr = match self.stream.poll()? {
+                Async::Ready(Some(addr)) => addr,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            let primary = match addr.addresses_at(0).next() {
+                Some(primary) => primary,
+                None => continue,
+            };
+            if self.last != Some(primary) {
+                self.last = Some(primary);
+                return Ok(Async::Ready(Some(primary)));
+            }
+        }
+    }
+}
+
+impl Stream for Canonical {
+    type Item = Vec<SocketAddr>;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<Option<Vec<SocketAddr>>>, Void> {
+        loop {
+            let addr = match self.stream.poll()? {
+                Async::Ready(Some(addr)) => addr,
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            let mut list = addr.iter()
+                .flat_map(|set| set.addresses().collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            list.sort_by_key(|a| (a.ip(), a.port()));
+            list.dedup();
+            if self.last.as_ref() != Some(&list) {
+                self.last = Some(list.clone());
+                return Ok(Async::Ready(Some(list)));
+            }
+        }
+    }
+}
+
+impl Stream for UnionStream {
+    type Item = Address;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<Option<Address>>, Void> {
+        let mut idx = 0;
+        while idx < self.sources.len() {
+            let done = {
+                let (ref mut stream, ref mut value) = self.sources[idx];
+                loop {
+                    match stream.poll()? {
+                        Async::Ready(Some(addr)) => *value = Some(addr),
+                        Async::Ready(None) => break true,
+                        Async::NotReady => break false,
+                    }
+                }
+            };
+            if done {
+                drop(self.sources.remove(idx));
+            } else {
+                idx += 1;
+            }
+        }
+        if self.sources.is_empty() {
+            return Ok(Async::Ready(None));
+        }
+        let seen = self.last.is_some() ||
+            self.sources.iter().any(|(_, v)| v.is_some());
+        if seen {
+            let value = union(
+                self.sources.iter().filter_map(|(_, v)| v.as_ref()));
+            if self.last.as_ref() != Some(&value) {
+                self.last = Some(value.clone());
+                return Ok(Async::Ready(Some(value)));
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<T, S, F> Stream for ScanState<T, S, F>
+    where T: Stream<Error=Void>,
+          S: Clone,
+          F: FnMut(&mut S, &T::Item),
+{
+    type Item = S;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<Option<S>>, Void> {
+        match self.stream.poll()? {
+            Async::Ready(Some(value)) => {
+                (self.func)(&mut self.state, &value);
+                Ok(Async::Ready(Some(self.state.clone())))
+            }
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<T: fmt::Debug, S: fmt::Debug, F> fmt::Debug for ScanState<T, S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ScanState")
+        .field("stream", &self.stream)
+        .field("state", &self.state)
+        .finish()
+    }
+}
+
+fn least_loaded(addr: &Address, loads: &HashMap<SocketAddr, usize>)
+    -> Option<SocketAddr>
+{
+    let mut best = None;
+    for set in addr.iter() {
+        for sa in set.addresses() {
+            let load = loads.get(&sa).cloned().unwrap_or(0);
+            match best {
+                Some((_, best_load)) if best_load <= load => {}
+                _ => best = Some((sa, load)),
+            }
+        }
+    }
+    best.map(|(sa, _)| sa)
+}
+
+impl Future for PinFuture {
+    type Item = ();
+    type Error = Error;
+    #[inline(always)]
+    fn poll(&mut self) -> Result<Async<()>, Error> {
+        match self.0.poll().map_err(|e| Error::TemporaryError(e.into()))? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Ok(r))  => Ok(Async::Ready(r)),
+            Async::Ready(Err(e))  => Err(e),
+        }
+    }
+}
+
+impl Future for SuffixFuture {
+    type Item = Option<String>;
+    type Error = Error;
+    #[inline(always)]
+    fn poll(&mut self) -> Result<Async<Option<String>>, Error> {
+        match self.0.poll().map_err(|e| Error::TemporaryError(e.into()))? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Ok(r))  => Ok(Async::Ready(r)),
+            Async::Ready(Err(e))  => Err(e),
+        }
+    }
+}
+
+impl Future for RoutingTableFuture {
+    type Item = RoutingTable;
+    type Error = Error;
+    #[inline(always)]
+    fn poll(&mut self) -> Result<Async<RoutingTable>, Error> {
+        match self.0.poll().map_err(|e| Error::TemporaryError(e.into()))? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Ok(r))  => Ok(Async::Ready(r)),
+            Async::Ready(Err(e))  => Err(e),
+        }
+    }
+}
+
+impl Future for RouterStatsFuture {
+    type Item = RouterStats;
+    type Error = Error;
+    #[inline(always)]
+    fn poll(&mut self) -> Result<Async<RouterStats>, Error> {
+        match self.0.poll().map_err(|e| Error::TemporaryError(e.into()))? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Ok(r))  => Ok(Async::Ready(r)),
+            Async::Ready(Err(e))  => Err(e),
+        }
+    }
+}
+
+impl Future for TracedFuture {
+    type Item = (Address, Trace);
+    type Error = Error;
+    #[inline(always)]
+    fn poll(&mut self) -> Result<Async<(Address, Trace)>, Error> {
+        match self.0.poll().map_err(|e| Error::TemporaryError(e.into()))? {
+            Async::NotReady => Ok(Async::NotReady),
+            Async::Ready(Ok(r))  => Ok(Async::Ready(r)),
+            Async::Ready(Err(e))  => Err(e),
+        }
+    }
+}
+
 impl Stream for HostStream {
     type Item = IpList;
     type Error = Void;
@@ -84,6 +836,187 @@ impl Stream for HostStream {
     }
 }
 
+impl Future for FirstValue {
+    type Item = Address;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Address>, Error> {
+        match self.0.poll()? {
+            Async::Ready(Some(addr)) => Ok(Async::Ready(addr)),
+            Async::Ready(None) => Err(Error::NameNotFound),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl FallibleStream {
+    pub(crate) fn new(stream: AddrStream, shutdown: oneshot::Receiver<()>)
+        -> FallibleStream
+    {
+        FallibleStream { stream, shutdown, done: false }
+    }
+}
+
+impl InitTimeoutStream {
+    pub(crate) fn new<T: Timer>(stream: AddrStream, timeout: Duration,
+        timer: &T)
+        -> InitTimeoutStream
+    {
+        InitTimeoutStream { stream, timer: Some(Delay::new(timer, timeout)) }
+    }
+}
+
+impl Stream for InitTimeoutStream {
+    type Item = Address;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Option<Address>>, Error> {
+        match self.stream.poll()? {
+            Async::NotReady => {}
+            other => {
+                self.timer = None;
+                return Ok(other);
+            }
+        }
+        let expired = match self.timer.as_mut().map(|t| t.poll()) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Err(e)) => {
+                error!("Initial resolution timer failed: {}", e);
+                self.timer = None;
+                false
+            }
+            Some(Ok(Async::NotReady)) | None => false,
+        };
+        if expired {
+            self.timer = None;
+            return Err(Error::TemporaryError(
+                "initial resolution timed out".into()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<F> TimeoutFuture<F> {
+    fn new<T: Timer>(future: F, timeout: Duration, timer: &T)
+        -> TimeoutFuture<F>
+    {
+        TimeoutFuture { future, timer: Some(Delay::new(timer, timeout)) }
+    }
+}
+
+impl<F: Future<Error=Error>> Future for TimeoutFuture<F> {
+    type Item = F::Item;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<F::Item>, Error> {
+        // inner future is polled first, so that the real result wins
+        // when both are ready
+        match self.future.poll()? {
+            Async::NotReady => {}
+            ready => return Ok(ready),
+        }
+        let expired = match self.timer.as_mut().map(|t| t.poll()) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Err(e)) => {
+                error!("Resolution timer failed: {}", e);
+                self.timer = None;
+                false
+            }
+            Some(Ok(Async::NotReady)) | None => false,
+        };
+        if expired {
+            self.timer = None;
+            return Err(Error::TemporaryError("timeout".into()));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl RetryFuture {
+    pub(crate) fn new<T: Timer + Clone>(router: &Router, name: &Name,
+        attempts: usize, backoff: Duration, timer: &T)
+        -> RetryFuture
+    {
+        RetryFuture {
+            router: router.clone(),
+            name: name.clone(),
+            retries_left: attempts - 1,
+            backoff,
+            timer: Arc::new(timer.clone()),
+            state: RetryState::Resolving(router.resolve(name)),
+        }
+    }
+}
+
+impl Future for RetryFuture {
+    type Item = Address;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Address>, Error> {
+        use self::RetryState::*;
+        loop {
+            let next = match self.state {
+                Resolving(ref mut future) => match future.poll() {
+                    Err(Error::TemporaryError(e)) => {
+                        if self.retries_left == 0 {
+                            return Err(Error::TemporaryError(e));
+                        }
+                        self.retries_left -= 1;
+                        debug!("Error resolving {:?}: {}, retrying in {:?}",
+                            self.name, e, self.backoff);
+                        Sleeping(Delay::new(&*self.timer, self.backoff))
+                    }
+                    other => return other,
+                },
+                Sleeping(ref mut timer) => match timer.poll() {
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Ok(Async::Ready(())) => {
+                        Resolving(self.router.resolve(&self.name))
+                    }
+                    Err(e) => {
+                        error!("Retry timer failed: {}", e);
+                        Resolving(self.router.resolve(&self.name))
+                    }
+                },
+            };
+            self.state = next;
+        }
+    }
+}
+
+impl Stream for FallibleStream {
+    type Item = Address;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Option<Address>>, Error> {
+        if self.done {
+            return Ok(Async::Ready(None));
+        }
+        match self.stream.poll()? {
+            Async::Ready(None) => {
+                self.done = true;
+                match self.shutdown.poll() {
+                    Ok(Async::Ready(())) => {
+                        Err(Error::TemporaryError(
+                            "Router is shut down".into()))
+                    }
+                    // router is dropped without shutting down (i.e. event
+                    // loop is dropped), just close the stream
+                    _ => Ok(Async::Ready(None)),
+                }
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+impl Stream for ErrorStream {
+    type Item = Error;
+    type Error = Void;
+    #[inline(always)]
+    fn poll(&mut self) -> Result<Async<Option<Error>>, Void> {
+        match self.0.poll() {
+            Ok(r) => Ok(r),
+            Err(_) => Ok(Async::Ready(None)),
+        }
+    }
+}
+
 impl Stream for AddrStream {
     type Item = Address;
     type Error = Void;
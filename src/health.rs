@@ -0,0 +1,355 @@
+//! A subscriber which filters out addresses failing a health check
+//!
+//! Create the instance with `SubscribeExt::health_checked`.
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use abstract_ns::{Resolve, HostResolve, Subscribe, HostSubscribe, Name};
+use abstract_ns::{Address, IpList};
+use abstract_ns::addr::Builder;
+use futures::{Future, IntoFuture, Stream, Async};
+use futures::stream::FuturesUnordered;
+use void::{Void, unreachable};
+
+use timer::{Timer, Delay};
+
+
+/// A subscriber which filters out addresses failing a health check
+///
+/// Create the instance with `SubscribeExt::health_checked`.
+///
+/// Only subscriptions are filtered, `resolve` and `resolve_host` are
+/// passed to the underlying resolver as is. When some addresses are
+/// filtered out, the rest keep their priorities, but get equal weights,
+/// because weights can't be read back from the `Address`.
+pub struct HealthCheckedSubscriber<R, F> {
+    subscriber: R,
+    check: Rc<F>,
+    interval: Duration,
+    debounce: u32,
+    host_port: Option<u16>,
+    timer: Arc<Timer>,
+}
+
+/// A stream returned by `HealthCheckedSubscriber::subscribe` and
+/// `subscribe_host`
+pub struct HealthStream<S: Stream, F> {
+    stream: S,
+    name: Name,
+    check: Rc<F>,
+    interval: Duration,
+    debounce: u32,
+    /// Port to check host addresses at, `None` disables checks
+    port: Option<u16>,
+    timer: Arc<Timer>,
+    delay: Option<Delay>,
+    current: Option<S::Item>,
+    last_value: Option<S::Item>,
+    health: HashMap<SocketAddr, Health>,
+    in_flight: HashSet<SocketAddr>,
+    checks: FuturesUnordered<CheckFuture>,
+}
+
+type CheckFuture = Box<Future<Item=(SocketAddr, bool), Error=Void>>;
+
+#[derive(Debug)]
+struct Health {
+    /// `None` until the first check is complete
+    healthy: Option<bool>,
+    /// Number of consecutive results contradicting `healthy`
+    streak: u32,
+}
+
+/// A value of subscription which can be filtered by health checks
+trait Checked: Clone + PartialEq {
+    fn targets(&self, port: u16) -> Vec<SocketAddr>;
+    fn filter(&self, port: u16, healthy: &Fn(SocketAddr) -> bool) -> Self;
+}
+
+impl<R, F> HealthCheckedSubscriber<R, F> {
+    pub(crate) fn new(subscriber: R, check: F, interval: Duration,
+        timer: Arc<Timer>)
+        -> HealthCheckedSubscriber<R, F>
+    {
+        HealthCheckedSubscriber {
+            subscriber, interval, timer,
+            check: Rc::new(check),
+            debounce: 2,
+            host_port: None,
+        }
+    }
+    /// Sets number of consecutive checks needed to change address state
+    ///
+    /// An address which is healthy is removed only after this number of
+    /// failed checks in a row, and an unhealthy address is added back
+    /// after this number of successful checks in a row. This prevents
+    /// flapping addresses from causing an update on every check. Default
+    /// is `2`.
+    ///
+    /// The state of a new address is decided by its first check regardless
+    /// of this setting.
+    pub fn debounce(mut self, checks: u32) -> Self {
+        assert!(checks > 0, "debounce must be at least one check");
+        self.debounce = checks;
+        self
+    }
+    /// Enables health checks for host subscriptions
+    ///
+    /// Ip lists don't contain ports, so by default host subscriptions are
+    /// passed as is. With this setting each ip is checked at the `port`.
+    pub fn host_port(mut self, port: u16) -> Self {
+        self.host_port = Some(port);
+        self
+    }
+    fn stream<S: Stream>(&self, stream: S, name: &Name, port: Option<u16>)
+        -> HealthStream<S, F>
+    {
+        HealthStream {
+            stream, port,
+            name: name.clone(),
+            check: self.check.clone(),
+            interval: self.interval,
+            debounce: self.debounce,
+            timer: self.timer.clone(),
+            delay: None,
+            current: None,
+            last_value: None,
+            health: HashMap::new(),
+            in_flight: HashSet::new(),
+            checks: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl<R: fmt::Debug, F> fmt::Debug for HealthCheckedSubscriber<R, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HealthCheckedSubscriber")
+        .field("subscriber", &self.subscriber)
+        .field("interval", &self.interval)
+        .field("debounce", &self.debounce)
+        .field("host_port", &self.host_port)
+        .finish()
+    }
+}
+
+impl<T: Resolve, F> Resolve for HealthCheckedSubscriber<T, F> {
+    type Future = T::Future;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        self.subscriber.resolve(name)
+    }
+}
+
+impl<T: HostResolve, F> HostResolve for HealthCheckedSubscriber<T, F> {
+    type HostFuture = T::HostFuture;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        self.subscriber.resolve_host(name)
+    }
+}
+
+impl<T: Subscribe, F, R> Subscribe for HealthCheckedSubscriber<T, F>
+    where F: Fn(SocketAddr) -> R,
+          R: IntoFuture<Item=bool>,
+          R::Future: 'static,
+{
+    type Error = T::Error;
+    type Stream = HealthStream<T::Stream, F>;
+    fn subscribe(&self, name: &Name) -> Self::Stream {
+        // addresses contain ports, so port is never used
+        self.stream(self.subscriber.subscribe(name), name, Some(0))
+    }
+}
+
+impl<T: HostSubscribe, F, R> HostSubscribe for HealthCheckedSubscriber<T, F>
+    where F: Fn(SocketAddr) -> R,
+          R: IntoFuture<Item=bool>,
+          R::Future: 'static,
+{
+    type HostError = T::HostError;
+    type HostStream = HealthStream<T::HostStream, F>;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        self.stream(self.subscriber.subscribe_host(name), name,
+            self.host_port)
+    }
+}
+
+impl Checked for Address {
+    fn targets(&self, _port: u16) -> Vec<SocketAddr> {
+        self.iter()
+            .flat_map(|set| set.addresses().collect::<Vec<_>>())
+            .collect()
+    }
+    fn filter(&self, port: u16, healthy: &Fn(SocketAddr) -> bool)
+        -> Address
+    {
+        if self.targets(port).into_iter().all(healthy) {
+            return self.clone();
+        }
+        // weights can't be read from the address, so they are reset
+        let mut builder = Builder::new();
+        for set in self.iter() {
+            let items = set.addresses()
+                .filter(|&a| healthy(a))
+                .map(|a| (0, a))
+                .collect::<Vec<_>>();
+            if !items.is_empty() {
+                builder.add_addresses(&items);
+            }
+        }
+        builder.into_address()
+    }
+}
+
+impl Checked for IpList {
+    fn targets(&self, port: u16) -> Vec<SocketAddr> {
+        self.iter().map(|&ip| SocketAddr::new(ip, port)).collect()
+    }
+    fn filter(&self, port: u16, healthy: &Fn(SocketAddr) -> bool)
+        -> IpList
+    {
+        self.iter().cloned()
+            .filter(|&ip| healthy(SocketAddr::new(ip, port)))
+            .collect::<Vec<IpAddr>>().into()
+    }
+}
+
+impl<S: Stream, F, R> HealthStream<S, F>
+    where F: Fn(SocketAddr) -> R,
+          R: IntoFuture<Item=bool>,
+          R::Future: 'static,
+{
+    fn start_check(&mut self, addr: SocketAddr) {
+        if !self.in_flight.insert(addr) {
+            return;
+        }
+        // failing check means address is unhealthy
+        let future = (self.check)(addr).into_future()
+            .then(move |res| Ok::<_, Void>((addr, res.unwrap_or(false))));
+        self.checks.push(Box::new(future));
+    }
+    fn update(&mut self, targets: Vec<SocketAddr>) {
+        self.health.retain(|addr, _| targets.contains(addr));
+        for addr in targets {
+            if let Entry::Vacant(e) = self.health.entry(addr) {
+                e.insert(Health {
+                    healthy: None,
+                    streak: 0,
+                });
+                self.start_check(addr);
+            }
+        }
+    }
+    fn recheck(&mut self) {
+        let addrs = self.health.keys().cloned().collect::<Vec<_>>();
+        for addr in addrs {
+            self.start_check(addr);
+        }
+    }
+    fn record(&mut self, addr: SocketAddr, ok: bool) {
+        self.in_flight.remove(&addr);
+        // address might be removed while check was in flight
+        let health = match self.health.get_mut(&addr) {
+            Some(health) => health,
+            None => return,
+        };
+        match health.healthy {
+            Some(healthy) if healthy == ok => health.streak = 0,
+            Some(_) => {
+                health.streak += 1;
+                if health.streak >= self.debounce {
+                    debug!("Address {} of {:?} is now {}", addr, self.name,
+                        if ok { "healthy" } else { "unhealthy" });
+                    health.healthy = Some(ok);
+                    health.streak = 0;
+                }
+            }
+            None => health.healthy = Some(ok),
+        }
+    }
+}
+
+impl<S: Stream, F, R> Stream for HealthStream<S, F>
+    where S::Item: Checked,
+          F: Fn(SocketAddr) -> R,
+          R: IntoFuture<Item=bool>,
+          R::Future: 'static,
+{
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<S::Item>>, S::Error> {
+        let port = match self.port {
+            Some(port) => port,
+            None => return self.stream.poll(),
+        };
+        loop {
+            match self.stream.poll()? {
+                Async::Ready(Some(value)) => {
+                    self.update(value.targets(port));
+                    self.current = Some(value);
+                }
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => break,
+            }
+        }
+        while self.current.is_some() {
+            if self.delay.is_none() {
+                self.delay = Some(Delay::new(&*self.timer, self.interval));
+            }
+            match self.delay.as_mut().map(|d| d.poll()) {
+                Some(Ok(Async::Ready(()))) => {
+                    self.delay = None;
+                    self.recheck();
+                }
+                Some(Ok(Async::NotReady)) | None => break,
+                Some(Err(e)) => {
+                    // this usually means reactor is shutting down
+                    error!("Timer for {:?} failed: {}, \
+                        stopping stream", self.name, e);
+                    return Ok(Async::Ready(None));
+                }
+            }
+        }
+        loop {
+            match self.checks.poll() {
+                Ok(Async::Ready(Some((addr, ok)))) => self.record(addr, ok),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => unreachable(e),
+            }
+        }
+        let value = {
+            let current = match self.current {
+                Some(ref current) => current,
+                None => return Ok(Async::NotReady),
+            };
+            // wait until every address is checked at least once
+            if self.health.values().any(|h| h.healthy.is_none()) {
+                return Ok(Async::NotReady);
+            }
+            let health = &self.health;
+            current.filter(port, &|addr| {
+                health.get(&addr).and_then(|h| h.healthy).unwrap_or(false)
+            })
+        };
+        if self.last_value.as_ref() == Some(&value) {
+            return Ok(Async::NotReady);
+        }
+        self.last_value = Some(value.clone());
+        Ok(Async::Ready(Some(value)))
+    }
+}
+
+impl<S: Stream, F> fmt::Debug for HealthStream<S, F>
+    where S::Item: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HealthStream")
+        .field("name", &self.name)
+        .field("last_value", &self.last_value)
+        .field("health", &self.health)
+        .finish()
+    }
+}
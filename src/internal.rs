@@ -1,25 +1,152 @@
+use std::cell::Cell;
 use std::fmt;
+use std::sync::Arc;
 
 use abstract_ns::{Name, Error, Address, IpList};
-use async_slot as slot;
+use slot;
+use futures::{Future, Async};
 use futures::sync::oneshot;
+use void::Void;
 
-use coroutine::{Continuation};
+use cancel::SubscriptionId;
+use config::Config;
+use coroutine::{Continuation, FutureResult, ResolverFuture};
+use stats::RouterStats;
+use table::RoutingTable;
+use trace::Trace;
 
 
 #[derive(Debug)]
 pub(crate) enum Request {
     ResolveHost(Name, oneshot::Sender<Result<IpList, Error>>),
     ResolveHostPort(Name, u16, oneshot::Sender<Result<Address, Error>>),
+    ResolveHostPorts(Name, Vec<u16>,
+        oneshot::Sender<Result<Address, Error>>),
     Resolve(Name, oneshot::Sender<Result<Address, Error>>),
+    ResolveBypassStatic(Name, oneshot::Sender<Result<Address, Error>>),
     HostSubscribe(Name, slot::Sender<IpList>),
     Subscribe(Name, slot::Sender<Address>),
+    PinHost(Name, oneshot::Sender<Result<(), Error>>),
+    Pin(Name, oneshot::Sender<Result<(), Error>>),
+    MatchedSuffix(Name, oneshot::Sender<Result<Option<String>, Error>>),
+    ResolveTraced(Name, oneshot::Sender<Result<(Address, Trace), Error>>),
+    SetSuffixEnabled(String, bool),
+    RoutingTable(oneshot::Sender<Result<RoutingTable, Error>>),
+    Stats(oneshot::Sender<Result<RouterStats, Error>>),
     Task(Box<Continuation+Send>),
+    Cancelable(SubscriptionId, Box<Request>),
+    /// Request sent by a resolver while resolving a request of that depth
+    Nested(usize, Box<Request>),
+    Cancel(SubscriptionId),
+    Refresh,
+    WatchShutdown(oneshot::Sender<()>),
+    Shutdown,
+}
+
+/// Waits for the internal request and replies with the converted value
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct ReplyMap<A, B, F> {
+    name: Name,
+    rx: oneshot::Receiver<Result<A, Error>>,
+    tx: Option<oneshot::Sender<Result<B, Error>>>,
+    func: Option<F>,
+}
+
+thread_local! {
+    /// Depth of the request which is being processed on this thread
+    static DEPTH: Cell<Option<usize>> = const { Cell::new(None) };
 }
 
 trait AssertTraits: Send {}
 impl AssertTraits for Request {}
 
+impl Request {
+    /// Wraps the request if it's sent while processing another request
+    ///
+    /// This is how resolution loops through a `Router` are detected.
+    pub fn nested(self) -> Request {
+        match DEPTH.with(|d| d.get()) {
+            Some(depth) => Request::Nested(depth + 1, Box::new(self)),
+            None => self,
+        }
+    }
+    /// Returns the original request back from `nested`
+    pub fn unnest(self) -> Request {
+        match self {
+            Request::Nested(_, request) => *request,
+            request => request,
+        }
+    }
+}
+
+/// Sets depth of the request being processed, `None` when it's done
+pub(crate) fn set_depth(depth: Option<usize>) {
+    DEPTH.with(|d| d.set(depth));
+}
+
+/// Keeps depth of the request for the subscription task it spawned
+///
+/// Otherwise a task restarted in a loop would begin at depth zero each time.
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct AtDepth<F> {
+    depth: usize,
+    future: F,
+}
+
+/// Restarts the task at the depth of the original request
+#[derive(Debug)]
+struct DepthScoped {
+    depth: usize,
+    task: Box<Continuation>,
+}
+
+impl<F> AtDepth<F> {
+    /// Wraps the future if a nested request is being processed
+    pub fn wrap(future: F) -> Result<AtDepth<F>, F> {
+        match DEPTH.with(|d| d.get()) {
+            // tasks restarted outside of a request are at depth zero anyway
+            Some(depth) if depth > 0 => Ok(AtDepth { depth, future }),
+            _ => Err(future),
+        }
+    }
+}
+
+impl<F> Future for AtDepth<F>
+    where F: Future<Item=FutureResult, Error=Void>,
+{
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        use coroutine::FutureResult::*;
+        let result = match self.future.poll()? {
+            Async::Ready(result) => result,
+            Async::NotReady => return Ok(Async::NotReady),
+        };
+        let depth = self.depth;
+        let result = match result {
+            Restart { task } => Restart {
+                task: Box::new(DepthScoped { depth, task }),
+            },
+            DelayRestart { task } => DelayRestart {
+                task: Box::new(DepthScoped { depth, task }),
+            },
+            other => other,
+        };
+        Ok(Async::Ready(result))
+    }
+}
+
+impl Continuation for DepthScoped {
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let prev = DEPTH.with(|d| d.replace(Some(self.depth)));
+        self.task.restart(res, cfg);
+        set_depth(prev);
+    }
+    fn name(&self) -> Option<&Name> {
+        self.task.name()
+    }
+}
+
 pub fn reply<X: Send + fmt::Debug + 'static>(name: &Name,
     tx: oneshot::Sender<Result<X, Error>>, value: X)
 {
@@ -31,6 +158,20 @@ pub fn reply<X: Send + fmt::Debug + 'static>(name: &Name,
         .ok();
 }
 
+/// Copies the error, `TemporaryError` is converted to its description
+pub(crate) fn clone_error(e: &Error) -> Error {
+    match *e {
+        Error::InvalidName(ref name, description) => {
+            Error::InvalidName(name.clone(), description)
+        }
+        Error::TemporaryError(ref e) => {
+            Error::TemporaryError(e.to_string().into())
+        }
+        Error::NameNotFound => Error::NameNotFound,
+        Error::NoDefaultPort => Error::NoDefaultPort,
+    }
+}
+
 pub fn fail<X: fmt::Debug>(name: &Name,
     tx: oneshot::Sender<Result<X, Error>>, error: Error)
 {
@@ -41,3 +182,38 @@ pub fn fail<X: fmt::Debug>(name: &Name,
         })
         .ok();
 }
+
+impl<A, B, F> ReplyMap<A, B, F>
+    where F: FnOnce(A) -> B,
+{
+    pub fn new(name: Name, rx: oneshot::Receiver<Result<A, Error>>,
+        tx: oneshot::Sender<Result<B, Error>>, func: F)
+        -> ReplyMap<A, B, F>
+    {
+        ReplyMap { name, rx, tx: Some(tx), func: Some(func) }
+    }
+}
+
+impl<A, B, F> Future for ReplyMap<A, B, F>
+    where F: FnOnce(A) -> B,
+          B: Send + fmt::Debug + 'static,
+{
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        let tx = self.tx.take().expect("future polled twice");
+        match result {
+            Ok(value) => {
+                let func = self.func.take().expect("future polled twice");
+                reply(&self.name, tx, func(value));
+            }
+            Err(e) => fail(&self.name, tx, e),
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
@@ -3,14 +3,16 @@ use std::sync::Arc;
 
 use abstract_ns::{Address, IpList, Name, Error};
 use abstract_ns::{HostResolve, Resolve, HostSubscribe, Subscribe};
-use async_slot as slot;
+use slot;
 use futures::{Future, Async};
 use futures::sync::oneshot;
 use void::Void;
 
 use config::Config;
 use coroutine::{ResolverFuture, FutureResult};
+use eq::Dedup;
 use fuse::Fuse;
+use mapped::Unmap;
 use subscr::{SubscrFuture, HostSubscr, Subscr, NoOpSubscr, HostNoOpSubscr};
 use internal::{reply, fail};
 
@@ -28,11 +30,36 @@ pub trait Resolver: Debug + 'static {
     fn subscribe(&self, res: &mut ResolverFuture,
         sub: &Arc<Resolver>, cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<Address>);
+    /// Returns a description of the resolver for the routing table
+    ///
+    /// Returns `None` for the resolver which is not set
+    fn describe(&self) -> Option<String>;
+    /// Makes resolver establish connections to its backend
+    ///
+    /// By default issues a host resolution of the suffix itself and
+    /// ignores the result.
+    fn warmup(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
+        suffix: &str)
+    {
+        let name = match suffix.parse::<Name>() {
+            Ok(name) => name,
+            Err(e) => {
+                debug!("Can't warm up suffix {:?}: {}", suffix, e);
+                return;
+            }
+        };
+        let (tx, rx) = oneshot::channel();
+        self.resolve_host(res, cfg, name.clone(), tx);
+        res.spawn(rx.then(move |result| {
+            debug!("Warmup of {:?} is done: {:?}", name, result);
+            Ok(FutureResult::Done)
+        }));
+    }
 }
 
 #[must_use = "futures do nothing unless polled"]
-struct SendResult<F: Future>(Name, F,
-    Option<oneshot::Sender<Result<F::Item, Error>>>);
+pub(crate) struct SendResult<F: Future>(pub Name, pub F,
+    pub Option<oneshot::Sender<Result<F::Item, Error>>>);
 
 #[derive(Debug)]
 pub struct Wrapper<R> {
@@ -42,6 +69,13 @@ pub struct Wrapper<R> {
 #[derive(Debug)]
 pub struct NullResolver;
 
+/// Resolver used by `Router::fixed`, returns the same address for any name
+#[derive(Debug)]
+pub struct FixedResolver {
+    addr: Address,
+    ips: IpList,
+}
+
 
 impl<R:Debug + 'static> Wrapper<R>
     where R: Resolve + HostResolve + Subscribe + HostSubscribe
@@ -56,52 +90,64 @@ impl<R:Debug + 'static> Wrapper<R>
 impl<R:Debug + 'static> Resolver for Wrapper<R>
     where R: Resolve + HostResolve + Subscribe + HostSubscribe
 {
-    fn resolve_host(&self, res: &mut ResolverFuture, _cfg: &Arc<Config>,
+    fn describe(&self) -> Option<String> {
+        Some(format!("{:?}", self.resolver))
+    }
+    fn resolve_host(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
         name: Name, tx: oneshot::Sender<Result<IpList, Error>>)
     {
-        let future = self.resolver.resolve_host(&name);
+        let unmap = cfg.normalize_mapped_v4;
+        let future = self.resolver.resolve_host(&name)
+            .map(move |x| x.unmap_if(unmap));
         res.spawn(SendResult(name, future, Some(tx)));
     }
-    fn resolve_host_port(&self, res: &mut ResolverFuture, _cfg: &Arc<Config>,
+    fn resolve_host_port(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
         name: Name, port: u16, tx: oneshot::Sender<Result<Address, Error>>)
     {
+        let unmap = cfg.normalize_mapped_v4;
         let future = self.resolver.resolve_host(&name);
-        let future = future.map(move |x| x.with_port(port));
+        let future = future.map(move |x| x.unmap_if(unmap).with_port(port));
         res.spawn(SendResult(name, future, Some(tx)));
     }
 
-    fn resolve(&self, res: &mut ResolverFuture, _cfg: &Arc<Config>,
+    fn resolve(&self, res: &mut ResolverFuture, cfg: &Arc<Config>,
         name: Name, tx: oneshot::Sender<Result<Address, Error>>)
     {
-        let f = self.resolver.resolve(&name);
+        let unmap = cfg.normalize_mapped_v4;
+        let f = self.resolver.resolve(&name)
+            .map(move |x| x.unmap_if(unmap));
         res.spawn(SendResult(name, f, Some(tx)));
     }
 
     fn subscribe(&self, res: &mut ResolverFuture,
-        sub: &Arc<Resolver>, _cfg: &Arc<Config>,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<Address>)
     {
         let update_rx = res.update_rx();
-        res.spawn(SubscrFuture {
+        res.spawn_subscription(SubscrFuture {
             update_rx,
             task: Some(Subscr {
                 subscriber: sub.clone(),
                 source: Fuse::new(self.resolver.subscribe(&name)),
+                dedup: Dedup::new(cfg.address_eq),
+                unmap: cfg.normalize_mapped_v4,
                 name, tx,
             }),
         });
     }
 
     fn host_subscribe(&self, res: &mut ResolverFuture,
-        sub: &Arc<Resolver>, _cfg: &Arc<Config>,
+        sub: &Arc<Resolver>, cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<IpList>)
     {
         let update_rx = res.update_rx();
-        res.spawn(SubscrFuture {
+        res.spawn_subscription(SubscrFuture {
             update_rx,
             task: Some(HostSubscr {
                 subscriber: sub.clone(),
                 source: Fuse::new(self.resolver.subscribe_host(&name)),
+                dedup: Dedup::new(cfg.address_eq),
+                unmap: cfg.normalize_mapped_v4,
                 name, tx,
             }),
         });
@@ -109,6 +155,13 @@ impl<R:Debug + 'static> Resolver for Wrapper<R>
 }
 
 impl Resolver for NullResolver {
+    fn warmup(&self, _res: &mut ResolverFuture, _cfg: &Arc<Config>,
+        _suffix: &str)
+    {
+    }
+    fn describe(&self) -> Option<String> {
+        None
+    }
     fn resolve_host(&self, _res: &mut ResolverFuture, _cfg: &Arc<Config>,
         _name: Name, tx: oneshot::Sender<Result<IpList, Error>>)
     {
@@ -130,14 +183,77 @@ impl Resolver for NullResolver {
         _sub: &Arc<Resolver>, _cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<Address>)
     {
-        SubscrFuture::spawn_in(res, NoOpSubscr { name, tx });
+        SubscrFuture::spawn_in(res, NoOpSubscr { name, tx, value: None });
+    }
+
+    fn host_subscribe(&self, res: &mut ResolverFuture,
+        _sub: &Arc<Resolver>, _cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<IpList>)
+    {
+        SubscrFuture::spawn_in(res,
+            HostNoOpSubscr { name, tx, value: None });
+    }
+}
+
+impl FixedResolver {
+    pub fn new(addr: Address) -> FixedResolver {
+        let mut ips = Vec::new();
+        let all = addr.iter()
+            .flat_map(|set| set.addresses().collect::<Vec<_>>());
+        for ip in all.map(|a| a.ip()) {
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+        FixedResolver { addr, ips: ips.into() }
+    }
+}
+
+impl Resolver for FixedResolver {
+    fn warmup(&self, _res: &mut ResolverFuture, _cfg: &Arc<Config>,
+        _suffix: &str)
+    {
+    }
+    fn describe(&self) -> Option<String> {
+        Some(format!("Fixed({:?})", self.addr))
+    }
+    fn resolve_host(&self, _res: &mut ResolverFuture, _cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<IpList, Error>>)
+    {
+        reply(&name, tx, self.ips.clone());
+    }
+    fn resolve_host_port(&self, _res: &mut ResolverFuture, _cfg: &Arc<Config>,
+        name: Name, port: u16, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        reply(&name, tx, self.ips.with_port(port));
+    }
+
+    fn resolve(&self, _res: &mut ResolverFuture, _cfg: &Arc<Config>,
+        name: Name, tx: oneshot::Sender<Result<Address, Error>>)
+    {
+        reply(&name, tx, self.addr.clone());
+    }
+
+    fn subscribe(&self, res: &mut ResolverFuture,
+        _sub: &Arc<Resolver>, _cfg: &Arc<Config>,
+        name: Name, tx: slot::Sender<Address>)
+    {
+        if tx.swap(self.addr.clone()).is_ok() {
+            SubscrFuture::spawn_in(res, NoOpSubscr {
+                name, tx, value: Some(self.addr.clone()),
+            });
+        }
     }
 
     fn host_subscribe(&self, res: &mut ResolverFuture,
         _sub: &Arc<Resolver>, _cfg: &Arc<Config>,
         name: Name, tx: slot::Sender<IpList>)
     {
-        SubscrFuture::spawn_in(res, HostNoOpSubscr { name, tx });
+        if tx.swap(self.ips.clone()).is_ok() {
+            SubscrFuture::spawn_in(res, HostNoOpSubscr {
+                name, tx, value: Some(self.ips.clone()),
+            });
+        }
     }
 }
 
@@ -0,0 +1,189 @@
+//! Resolution latency statistics
+//!
+//! Latency is only tracked when enabled by `Config::track_latency`, and
+//! stats can be fetched by `Router::latency_stats`.
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use abstract_ns::{Name, Error};
+use futures::{Future, Async};
+use futures::sync::oneshot;
+use void::Void;
+
+use coroutine::FutureResult;
+use internal::{reply, fail};
+
+
+/// Upper bounds of histogram buckets in milliseconds
+///
+/// Last bucket (not listed here) counts everything that is longer.
+const BUCKETS: [u64; 14] = [
+    1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10000, 30000];
+
+/// Kind of the request that latency is measured for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Host,
+    HostPort,
+    Service,
+}
+
+/// A bucketed histogram of resolution latencies
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    counts: [u64; 15],
+}
+
+/// A snapshot of resolution latencies for each kind of request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Latencies of `resolve_host` requests
+    pub host: Histogram,
+    /// Latencies of `resolve_host_port` requests (including the ones that
+    /// are made by `resolve_auto`)
+    pub host_port: Histogram,
+    /// Latencies of `resolve` requests (including the ones that are made
+    /// by `resolve_auto`)
+    pub service: Histogram,
+}
+
+/// Waits for the reply and records the time passed since request
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Timed<T> {
+    name: Name,
+    kind: Kind,
+    start: Instant,
+    stats: Arc<Mutex<LatencyStats>>,
+    rx: oneshot::Receiver<Result<T, Error>>,
+    tx: Option<oneshot::Sender<Result<T, Error>>>,
+}
+
+fn millis(dur: Duration) -> u64 {
+    dur.as_secs()*1000 + dur.subsec_millis() as u64
+}
+
+impl Histogram {
+    /// Record a single value into the histogram
+    pub fn record(&mut self, value: Duration) {
+        let ms = millis(value);
+        let idx = BUCKETS.iter().position(|&b| ms < b)
+            .unwrap_or(BUCKETS.len());
+        self.counts[idx] += 1;
+    }
+
+    /// Total number of values recorded
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns list of buckets with their counts
+    ///
+    /// Each bucket is represented by its (exclusive) upper bound. The
+    /// last bucket has `None` as the upper bound, it counts every value
+    /// longer than 30 seconds.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        BUCKETS.iter().map(|&b| Some(Duration::from_millis(b)))
+            .chain(Some(None))
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+
+    /// Returns upper bound of the bucket containing the percentile
+    ///
+    /// For example `percentile(0.99)` returns p99. Returns `None` if there
+    /// are no values recorded or if percentile falls into the last bucket
+    /// (which has no upper bound).
+    pub fn percentile(&self, value: f64) -> Option<Duration> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+        let target = ((total as f64) * value).ceil().max(1.) as u64;
+        let mut seen = 0;
+        for (idx, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return BUCKETS.get(idx).map(|&b| Duration::from_millis(b));
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Debug for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Histogram")
+        .field("count", &self.count())
+        .field("p50", &self.percentile(0.5))
+        .field("p99", &self.percentile(0.99))
+        .finish()
+    }
+}
+
+impl LatencyStats {
+    fn histogram(&mut self, kind: Kind) -> &mut Histogram {
+        match kind {
+            Kind::Host => &mut self.host,
+            Kind::HostPort => &mut self.host_port,
+            Kind::Service => &mut self.service,
+        }
+    }
+}
+
+impl<T> Timed<T> {
+    pub fn new(name: Name, kind: Kind, stats: &Arc<Mutex<LatencyStats>>,
+        rx: oneshot::Receiver<Result<T, Error>>,
+        tx: oneshot::Sender<Result<T, Error>>)
+        -> Timed<T>
+    {
+        Timed {
+            name, kind, rx,
+            start: Instant::now(),
+            stats: stats.clone(),
+            tx: Some(tx),
+        }
+    }
+}
+
+impl<T: Send + fmt::Debug + 'static> Future for Timed<T> {
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        self.stats.lock().expect("stats lock is not poisoned")
+            .histogram(self.kind)
+            .record(self.start.elapsed());
+        let tx = self.tx.take().expect("future polled twice");
+        match result {
+            Ok(value) => reply(&self.name, tx, value),
+            Err(e) => fail(&self.name, tx, e),
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::Histogram;
+
+    #[test]
+    fn percentiles() {
+        let mut h = Histogram::default();
+        assert_eq!(h.percentile(0.5), None);
+        for _ in 0..98 {
+            h.record(Duration::from_millis(3));
+        }
+        h.record(Duration::from_millis(150));
+        h.record(Duration::from_secs(60));
+        assert_eq!(h.count(), 100);
+        assert_eq!(h.percentile(0.5), Some(Duration::from_millis(5)));
+        assert_eq!(h.percentile(0.99), Some(Duration::from_millis(200)));
+        assert_eq!(h.percentile(1.0), None);
+    }
+}
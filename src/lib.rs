@@ -112,23 +112,65 @@ extern crate tokio_core;
 extern crate void;
 #[macro_use] extern crate log;
 #[macro_use] extern crate quick_error;
+#[cfg(feature="serde")] extern crate humantime;
+#[cfg(feature="serde")] extern crate serde;
+#[cfg(feature="idna")] extern crate idna;
 
+mod cache;
+mod cancel;
+mod cell;
+mod chain;
 mod config;
 mod coroutine;
+mod eq;
+mod fallback;
 mod fuse;
 mod internal;
 mod internal_traits;
+mod latency;
+mod mapped;
+mod metrics;
 mod multisubscr;
 mod name;
+mod negative;
+mod pin;
+mod record;
+mod redundant;
 mod router;
+mod seeded;
+mod slot;
+mod stats;
 mod subscr;
+mod table;
+mod trace;
+#[cfg(feature="blocking")] pub mod blocking;
 pub mod future;
+pub mod health;
+pub mod middleware;
+#[cfg(feature="proxy")] pub mod proxy;
+pub mod runtime;
+#[cfg(feature="serde")] mod spec;
 pub mod subscribe_ext;
+pub mod timer;
 
 pub use router::Router;
-pub use config::Config;
-pub use name::{AutoName, IntoNameIter};
-pub use subscribe_ext::SubscribeExt;
+pub use cancel::Subscription;
+pub use config::{Config, Timing, SuffixOpts};
+pub use eq::AddressEqMode;
+pub use latency::{LatencyStats, Histogram};
+pub use metrics::Metrics;
+pub use middleware::ResolveMiddleware;
+pub use name::{AutoName, AutoNameBuf, IntoNameIter, ResolvedMode};
+pub use record::{RecordType, Record};
+pub use redundant::BoxedSubscriber;
+pub use runtime::{Spawn, Runtime};
+pub use slot::BackpressureStats;
+#[cfg(feature="serde")] pub use spec::{ConfigSpec, SpecError};
+pub use stats::RouterStats;
+pub use subscribe_ext::{SubscribeExt, MinTtl};
+pub use table::{RoutingTable, SuffixEntry};
+pub use trace::{Route, Trace};
+pub use timer::{Timer, ManualTimer};
 
 trait AssertTraits: Clone + Send + Sync {}
 impl AssertTraits for Router {}
@@ -0,0 +1,65 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use abstract_ns::{Address, IpList};
+use abstract_ns::addr::Builder;
+
+
+/// Converts IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) back to IPv4
+pub(crate) trait Unmap: Sized {
+    fn unmap(self) -> Self;
+    /// Unmaps the value only if `enabled` is true
+    fn unmap_if(self, enabled: bool) -> Self {
+        if enabled { self.unmap() } else { self }
+    }
+}
+
+fn unmap_ip(ip: IpAddr) -> Option<Ipv4Addr> {
+    match ip {
+        IpAddr::V6(ip) => {
+            let s = ip.segments();
+            if s[..5] == [0, 0, 0, 0, 0] && s[5] == 0xffff {
+                Some(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8,
+                                   (s[7] >> 8) as u8, s[7] as u8))
+            } else {
+                None
+            }
+        }
+        IpAddr::V4(_) => None,
+    }
+}
+
+fn unmap_sock(addr: SocketAddr) -> Option<SocketAddr> {
+    unmap_ip(addr.ip())
+        .map(|ip| SocketAddr::new(IpAddr::V4(ip), addr.port()))
+}
+
+impl Unmap for IpList {
+    fn unmap(self) -> IpList {
+        if !self.iter().any(|ip| unmap_ip(*ip).is_some()) {
+            return self;
+        }
+        self.iter()
+            .map(|ip| unmap_ip(*ip).map(IpAddr::V4).unwrap_or(*ip))
+            .collect::<Vec<_>>().into()
+    }
+}
+
+impl Unmap for Address {
+    fn unmap(self) -> Address {
+        if !self.iter().any(|set| {
+            set.addresses().any(|a| unmap_sock(a).is_some())
+        }) {
+            return self;
+        }
+        // weights can't be read back from the `Address`, so every address
+        // gets zero weight (as `Address::from` does), but priorities are kept
+        let mut builder = Builder::new();
+        for set in self.iter() {
+            let items = set.addresses()
+                .map(|a| (0, unmap_sock(a).unwrap_or(a)))
+                .collect::<Vec<_>>();
+            builder.add_addresses(&items);
+        }
+        builder.into_address()
+    }
+}
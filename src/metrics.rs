@@ -0,0 +1,145 @@
+//! Hooks for collecting metrics of the router
+//!
+//! Metrics are only collected when enabled by `Config::set_metrics`.
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use abstract_ns::{Name, Error};
+use futures::{Future, Async};
+use futures::sync::oneshot;
+use void::Void;
+
+use config::Config;
+use coroutine::FutureResult;
+use internal::{reply, fail};
+
+
+/// Receives events of the router, every method is a no-op by default
+///
+/// Methods are called on the reactor thread, except `on_subscribe` and
+/// `on_unsubscribe` which are called from wherever subscription is created
+/// or dropped. So implementations are expected to use atomic counters or
+/// similar.
+///
+/// Every method receives the `label` of the router (see
+/// `Config::set_label`, it's `"router"` by default), so a single instance
+/// can be shared by multiple routers and still count them separately.
+pub trait Metrics: fmt::Debug + Send + Sync + 'static {
+    /// Resolution of a name is requested (i.e. `resolve`, `resolve_host`)
+    fn on_resolve_start(&self, label: &str) {
+        let _ = label;
+    }
+    /// Result of resolution is ready
+    fn on_resolve_done(&self, label: &str, success: bool) {
+        let _ = (label, success);
+    }
+    /// A subscription is created (i.e. `subscribe`, `subscribe_many`)
+    ///
+    /// Only subscriptions created when metrics are set in the active
+    /// config are reported.
+    fn on_subscribe(&self, label: &str) {
+        let _ = label;
+    }
+    /// A subscription reported by `on_subscribe` is closed
+    ///
+    /// This is called on the same instance and with the same label as
+    /// `on_subscribe`, even if config is updated in the meantime.
+    fn on_unsubscribe(&self, label: &str) {
+        let _ = label;
+    }
+    /// A new config is applied by the router
+    fn on_config_update(&self, label: &str) {
+        let _ = label;
+    }
+}
+
+/// Metrics of the active config along with the label of the router
+#[derive(Debug, Clone)]
+pub(crate) struct Labeled {
+    label: String,
+    metrics: Arc<Metrics>,
+}
+
+pub(crate) type SharedMetrics = Arc<RwLock<Option<Labeled>>>;
+
+/// Reports `on_unsubscribe` when dropped
+pub(crate) struct Guard(Labeled);
+
+/// Waits for the reply and reports whether resolution succeeded
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Metered<T> {
+    name: Name,
+    metrics: Labeled,
+    rx: oneshot::Receiver<Result<T, Error>>,
+    tx: Option<oneshot::Sender<Result<T, Error>>>,
+}
+
+impl Labeled {
+    pub fn new(label: &str, metrics: &Arc<Metrics>) -> Labeled {
+        Labeled {
+            label: label.to_string(),
+            metrics: metrics.clone(),
+        }
+    }
+    /// Metrics of the config before it is applied by the router
+    pub fn from_config(config: &Config) -> Option<Labeled> {
+        let label = config.label.as_ref().map_or("router", |x| &x[..]);
+        config.metrics.as_ref().map(|m| Labeled::new(label, m))
+    }
+    pub fn config_updated(&self) {
+        self.metrics.on_config_update(&self.label);
+    }
+}
+
+impl Guard {
+    pub fn new(metrics: &Labeled) -> Guard {
+        metrics.metrics.on_subscribe(&metrics.label);
+        Guard(metrics.clone())
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.metrics.on_unsubscribe(&self.0.label);
+    }
+}
+
+impl fmt::Debug for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Guard")
+    }
+}
+
+impl<T> Metered<T> {
+    pub fn new(name: Name, metrics: Labeled,
+        rx: oneshot::Receiver<Result<T, Error>>,
+        tx: oneshot::Sender<Result<T, Error>>)
+        -> Metered<T>
+    {
+        metrics.metrics.on_resolve_start(&metrics.label);
+        Metered {
+            name, rx, metrics,
+            tx: Some(tx),
+        }
+    }
+}
+
+impl<T: Send + fmt::Debug + 'static> Future for Metered<T> {
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        self.metrics.metrics.on_resolve_done(&self.metrics.label,
+                                             result.is_ok());
+        let tx = self.tx.take().expect("future polled twice");
+        match result {
+            Ok(value) => reply(&self.name, tx, value),
+            Err(e) => fail(&self.name, tx, e),
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
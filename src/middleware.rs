@@ -0,0 +1,30 @@
+//! Middleware that can observe and override resolution results
+use std::fmt;
+
+use abstract_ns::{Name, Address, Error};
+use futures::Future;
+
+
+/// A boxed future passed through the middleware chain
+pub type AddressFuture = Box<Future<Item=Address, Error=Error>>;
+
+/// A middleware that wraps resolution of every service or host-port name
+///
+/// Middleware is added with `Config::add_middleware`. It wraps both
+/// static and dynamic resolution of service names (`resolve`) and of
+/// host and port pairs (`resolve_auto`). Host resolution (i.e.
+/// `resolve_host`) and subscriptions are not passed through middleware.
+///
+/// Note: by the time `around_resolve` is called the name is already
+/// dispatched to the underlying resolver, so if the middleware doesn't
+/// use `next` future (i.e. to short-circuit resolution) its result is
+/// just discarded.
+pub trait ResolveMiddleware: fmt::Debug + 'static {
+    /// Wraps the future that resolves `name`
+    ///
+    /// The returned future is used instead of `next`. You may return
+    /// `next` itself, combinators on it (to log or modify the result) or
+    /// a completely different future.
+    fn around_resolve(&self, name: &Name, next: AddressFuture)
+        -> AddressFuture;
+}
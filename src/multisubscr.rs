@@ -1,47 +1,129 @@
-use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::mem;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use abstract_ns::{IpList, Address};
-use abstract_ns::addr::union;
-use async_slot as slot;
+use abstract_ns::addr::{Builder, Weight};
+use slot;
 use futures::{Stream, Future, Async};
-use tokio_core::reactor::Timeout;
+use futures::stream::FuturesUnordered;
+use futures::sync::oneshot;
+use void::{Void, unreachable};
 
-use config::Config;
-use coroutine::{ResolverFuture, get_suffix};
+use config::{Config, AddressRewriter};
+use coroutine::{ResolverFuture, get_suffix, get_opts};
+use eq::AddressEqMode;
 use name::InternalName;
 use subscr::{Task, TaskResult, SubscrFuture};
+use timer::Sleep;
 
 
+/// State of a single name
+///
+/// Receivers of the non-static names live in the `Child` futures, the
+/// sender here is only used to cancel the child when the name is dropped.
+#[allow(dead_code)]  // senders are never read, only dropped
 pub enum State {
     StaticHost(IpList, u16),
     StaticAddr(Address),
-    Host(slot::Receiver<IpList>, Option<IpList>, u16),
-    Addr(slot::Receiver<Address>, Option<Address>),
+    /// A socket address given directly, with its weight
+    Literal(SocketAddr, Weight),
+    Host(oneshot::Sender<()>, Option<IpList>, u16),
+    Addr(oneshot::Sender<()>, Option<Address>),
+}
+
+enum Value {
+    Host(IpList),
+    Addr(Address),
+}
+
+enum Source {
+    Host(slot::Receiver<IpList>),
+    Addr(slot::Receiver<Address>),
+}
+
+/// A future which resolves when next value for the name is received
+///
+/// Children are polled by `FuturesUnordered`, so only names that have
+/// been notified are polled, which keeps polling large lists cheap.
+struct Child(Option<ChildState>);
+
+struct ChildState {
+    name: InternalName,
+    cancel: oneshot::Receiver<()>,
+    source: Source,
 }
 
 pub(crate) struct MultiSubscr<S: Stream<Item=Vec<InternalName>>> {
     input: S,
     current: Vec<InternalName>,
+    /// Whether `current` is received from the `input` (it may be empty)
+    received: bool,
     items: HashMap<InternalName, State>,
-    timer: Option<Timeout>,
+    children: FuturesUnordered<Child>,
+    timer: Option<Sleep>,
+    convergence_delay: Option<Duration>,
+    eq: AddressEqMode,
+    rewriter: Option<AddressRewriter>,
     tx: slot::Sender<Address>,
 }
 
+impl Future for Child {
+    /// Value and the child to continue with, or `None` if cancelled
+    type Item = Option<(InternalName, Value, Child)>;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<Self::Item>, Void> {
+        let value = {
+            let state = self.0.as_mut().expect("future polled twice");
+            match state.cancel.poll() {
+                Ok(Async::NotReady) => {}
+                // sender is never used, it's only dropped
+                Ok(Async::Ready(())) | Err(_) => {
+                    return Ok(Async::Ready(None));
+                }
+            }
+            match state.source {
+                Source::Host(ref mut rx) => match rx.poll() {
+                    Err(()) => unreachable!(), // slot doesn't fail
+                    Ok(Async::Ready(Some(x))) => Value::Host(x),
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                },
+                Source::Addr(ref mut rx) => match rx.poll() {
+                    Err(()) => unreachable!(), // slot doesn't fail
+                    Ok(Async::Ready(Some(x))) => Value::Addr(x),
+                    Ok(Async::Ready(None)) => return Ok(Async::Ready(None)),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                },
+            }
+        };
+        let state = self.0.take().expect("future polled twice");
+        Ok(Async::Ready(Some((state.name.clone(), value, Child(Some(state))))))
+    }
+}
+
 impl State {
-    fn addr(&self) -> Option<Cow<Address>> {
+    /// Addresses of the name grouped by priority, with their weights
+    ///
+    /// Weights can't be read from the `Address`, so only literal
+    /// addresses have their own weight, others get weight of `1`.
+    fn sets(&self) -> Vec<Vec<(Weight, SocketAddr)>> {
         use self::State::*;
-        use std::borrow::Cow::*;
         match *self {
-            StaticHost(ref list, port) => Some(Owned(list.with_port(port))),
-            StaticAddr(ref addr) => Some(Borrowed(addr)),
-            Host(_, Some(ref list), port) => Some(Owned(list.with_port(port))),
-            Host(_, None, _) => None,
-            Addr(_, Some(ref addr)) => Some(Borrowed(addr)),
-            Addr(_, None) => None,
+            StaticHost(ref list, port) | Host(_, Some(ref list), port) => {
+                vec![list.iter().map(|ip| (1, SocketAddr::new(*ip, port)))
+                     .collect()]
+            }
+            StaticAddr(ref addr) | Addr(_, Some(ref addr)) => {
+                addr.iter()
+                    .map(|set| set.addresses().map(|a| (1, a)).collect())
+                    .collect()
+            }
+            Literal(addr, weight) => vec![vec![(weight, addr)]],
+            Host(_, None, _) | Addr(_, None) => Vec::new(),
         }
     }
     fn is_static(&self) -> bool {
@@ -49,6 +131,7 @@ impl State {
         match *self {
             StaticHost(_, _) => true,
             StaticAddr(_) => true,
+            Literal(..) => true,
             Host(_, _, _) => false,
             Addr(_, _) => false,
         }
@@ -58,6 +141,7 @@ impl State {
         match *self {
             StaticHost(_, _) => true,
             StaticAddr(_) => true,
+            Literal(..) => true,
             Host(_, Some(_), _) => true,
             Host(_, None, _) => false,
             Addr(_, Some(_)) => true,
@@ -71,14 +155,111 @@ impl<S: Stream<Item=Vec<InternalName>>> MultiSubscr<S> {
         MultiSubscr {
             tx, input,
             current: Vec::new(),
+            received: false,
             items: HashMap::new(),
+            children: FuturesUnordered::new(),
             timer: None,
+            convergence_delay: None,
+            eq: AddressEqMode::Exact,
+            rewriter: None,
+        }
+    }
+    /// Overrides `convergence_delay` from the config
+    pub(crate) fn convergence_delay(mut self, delay: Duration) -> Self {
+        self.convergence_delay = Some(delay);
+        self
+    }
+    fn add_child(&mut self, name: &InternalName, source: Source)
+        -> oneshot::Sender<()>
+    {
+        let (cancel_tx, cancel) = oneshot::channel();
+        self.children.push(Child(Some(ChildState {
+            name: name.clone(),
+            cancel,
+            source,
+        })));
+        cancel_tx
+    }
+    fn update(&mut self, name: &InternalName, value: Value) -> bool {
+        use self::State::*;
+        let eq = self.eq;
+        match (self.items.get_mut(name), value) {
+            (Some(&mut Host(_, ref mut v, _)), Value::Host(x))
+                if v.as_ref().is_none_or(|v| !eq.equal(v, &x)) =>
+            {
+                *v = Some(x);
+                true
+            }
+            (Some(&mut Addr(_, ref mut v)), Value::Addr(x))
+                if v.as_ref().is_none_or(|v| !eq.equal(v, &x)) =>
+            {
+                *v = Some(x);
+                true
+            }
+            // value is unchanged, or name is dropped or became static,
+            // but its child is not cancelled yet
+            _ => false,
         }
     }
     fn send_current(&mut self) -> bool {
-        self.tx.swap(union(self.items.values()
-            .filter_map(|x| x.addr()))).is_ok()
+        let value = self.merge();
+        let value = match self.rewriter {
+            Some(ref rewriter) => rewriter.rewrite(value),
+            None => value,
+        };
+        self.tx.swap(value).is_ok()
+    }
+    /// Merges addresses of all names by priority
+    ///
+    /// Sets of the same priority are concatenated in the order of names.
+    /// Socket address returned for multiple names is kept only once, in
+    /// the set of the highest priority.
+    fn merge(&self) -> Address {
+        let names = self.current.iter()
+            .filter_map(|name| self.items.get(name))
+            .map(|state| state.sets())
+            .collect::<Vec<_>>();
+        let levels = names.iter().map(|sets| sets.len()).max().unwrap_or(0);
+        if levels == 0 {
+            return Address::from(&[][..]);
+        }
+        let mut seen = HashSet::new();
+        let mut builder = Builder::new();
+        for priority in 0..levels {
+            let mut items = Vec::<(Weight, SocketAddr)>::new();
+            for sets in &names {
+                if let Some(set) = sets.get(priority) {
+                    items.extend(set.iter()
+                        .filter(|&&(_, a)| seen.insert(a)));
+                }
+            }
+            // a set of equal weights is sent with zero weights, the same
+            // as `Address::from` builds, so it compares equal to it
+            if items.windows(2).all(|pair| pair[0].0 == pair[1].0) {
+                for item in &mut items {
+                    item.0 = 0;
+                }
+            }
+            builder.add_addresses(&items);
+        }
+        builder.into_address()
+    }
+}
+
+/// Returns the largest convergence delay among suffixes of the names
+fn convergence_delay(cfg: &Arc<Config>, names: &[InternalName]) -> Duration {
+    let mut result = None;
+    for name in names {
+        let name = match *name {
+            InternalName::HostPort(ref name, _) => name,
+            InternalName::Service(ref name) => name,
+            InternalName::Addr(..) => continue,
+        };
+        let delay = get_opts(cfg, name).convergence_delay
+            .unwrap_or(cfg.convergence_delay);
+        result = Some(result.map_or(delay, |x: Duration| x.max(delay)));
     }
+    result.unwrap_or(cfg.convergence_delay)
 }
 
 impl<S: Stream<Item=Vec<InternalName>> + 'static> Task for MultiSubscr<S>
@@ -86,11 +267,15 @@ impl<S: Stream<Item=Vec<InternalName>> + 'static> Task for MultiSubscr<S>
 {
     fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
         use self::State::*;
-        let mut timeo = Timeout::new(cfg.convergence_delay, res.handle())
-            .expect("timeout never fails");
+        self.eq = cfg.address_eq;
+        self.rewriter = cfg.rewriter.clone();
+        let delay = self.convergence_delay
+            .unwrap_or_else(|| convergence_delay(cfg, &self.current));
+        let mut timeo = res.sleep(cfg, delay);
         let mut old_items = mem::replace(&mut self.items, HashMap::new());
         let mut all_ok = true;
-        for name in &self.current {
+        let current = mem::take(&mut self.current);
+        for name in &current {
             if let Some(item) = old_items.remove(name) {
                 if !item.is_static() {
                     if !item.is_complete() {
@@ -108,49 +293,63 @@ impl<S: Stream<Item=Vec<InternalName>> + 'static> Task for MultiSubscr<S>
                         self.items.insert(name.clone(),
                             StaticHost(value.clone(), port));
                     } else {
-                        let sub = get_suffix(cfg, host.as_ref());
+                        let sub = get_suffix(cfg, host);
                         let (tx, rx) = slot::channel();
                         sub.host_subscribe(res, sub, cfg, host.clone(), tx);
+                        let cancel = self.add_child(name, Source::Host(rx));
                         self.items.insert(name.clone(),
-                            Host(rx, None, port));
+                            Host(cancel, None, port));
+                        all_ok = false;
                     }
                 }
                 InternalName::Service(ref service) => {
-                    if let Some(value) = cfg.services.get(&service) {
-                        self.items.insert(name.clone(),
-                                          StaticAddr(value.clone()));
+                    if let Some(value) = cfg.service(&service) {
+                        self.items.insert(name.clone(), StaticAddr(value));
                     } else {
-                        let sub = get_suffix(cfg, service.as_ref());
+                        let sub = get_suffix(cfg, service);
                         let (tx, rx) = slot::channel();
                         sub.subscribe(res, sub, cfg, service.clone(), tx);
-                        self.items.insert(name.clone(), Addr(rx, None));
+                        let cancel = self.add_child(name, Source::Addr(rx));
+                        self.items.insert(name.clone(), Addr(cancel, None));
+                        all_ok = false;
                     }
 
                 }
-                InternalName::Addr(addr) => {
-                        self.items.insert(name.clone(),
-                                          StaticAddr(addr.into()));
+                InternalName::Addr(addr, weight) => {
+                    self.items.insert(name.clone(),
+                        Literal(addr, weight.unwrap_or(1)));
                 }
             }
         }
-        if all_ok && self.current.len() > 0 {
+        self.current = current;
+        drop(old_items);  // cancels children of the dropped names
+        let send_now = !self.current.is_empty() ||
+            (self.received && cfg.emit_empty_for_empty_list);
+        if all_ok && send_now {
             if !self.send_current() {
                 return;
             }
         } else {
-            match timeo.poll().expect("timeout never fails") {
-                Async::Ready(()) => {
-                    // App is probably too slow, but we should process
-                    // this situation anyway
-                    // Or maybe just convergence_delay is zero
-                    if !self.send_current() {
-                        return;
-                    }
-                    self.timer = None;
-                }
-                Async::NotReady => {
+            let ready = match timeo.poll() {
+                Ok(Async::Ready(())) => true,
+                Ok(Async::NotReady) => {
                     self.timer = Some(timeo);
+                    false
+                }
+                Err(e) => {
+                    warn!("Convergence timer failed: {}, \
+                        sending names immediately", e);
+                    true
                 }
+            };
+            if ready {
+                // App is probably too slow, but we should process
+                // this situation anyway
+                // Or maybe just convergence_delay is zero
+                if !self.send_current() {
+                    return;
+                }
+                self.timer = None;
             }
         }
         SubscrFuture::spawn_in(res, self)
@@ -162,7 +361,10 @@ impl<S: Stream<Item=Vec<InternalName>> + 'static> Task for MultiSubscr<S>
             Async::NotReady => {}
         }
         let tpoll = self.timer.as_mut().map(|t| {
-            t.poll().expect("timeout never fails")
+            t.poll().unwrap_or_else(|e| {
+                warn!("Convergence timer failed: {}", e);
+                Async::Ready(())
+            })
         });
         if let Some(poll_result) = tpoll {
             match poll_result {
@@ -184,7 +386,8 @@ impl<S: Stream<Item=Vec<InternalName>> + 'static> Task for MultiSubscr<S>
                 }
                 Ok(Async::NotReady) => break,
                 Ok(Async::Ready(Some(x))) => {
-                    if self.current != x {
+                    if !self.received || self.current != x {
+                        self.received = true;
                         self.current = x;
                         // restart, so timer is started again
                         return TaskResult::Restart;
@@ -192,41 +395,20 @@ impl<S: Stream<Item=Vec<InternalName>> + 'static> Task for MultiSubscr<S>
                 }
             }
         }
-        for item in self.items.values_mut() {
-            use self::State::*;
-            match *item {
-                StaticHost(_, _) => {}
-                StaticAddr(_) => {}
-                Host(ref mut s, ref mut v, _) => {
-                    loop {
-                        match s.poll() {
-                            Err(()) => unreachable!(), // slot doesn't fail
-                            Ok(Async::Ready(Some(x))) => {
-                                if Some(&x) != v.as_ref() {
-                                    *v = Some(x);
-                                    updated = true;
-                                }
-                            },
-                            Ok(Async::Ready(None)) => unreachable!(),
-                            Ok(Async::NotReady) => break,
-                        }
-                    }
-                }
-                Addr(ref mut s, ref mut v) => {
-                    loop {
-                        match s.poll() {
-                            Err(()) => unreachable!(), // slot doesn't fail
-                            Ok(Async::Ready(Some(x))) => {
-                                if Some(&x) != v.as_ref() {
-                                    *v = Some(x);
-                                    updated = true;
-                                }
-                            },
-                            Ok(Async::Ready(None)) => unreachable!(),
-                            Ok(Async::NotReady) => break,
-                        }
+        loop {
+            match self.children.poll() {
+                Err(e) => unreachable(e),
+                Ok(Async::Ready(Some(Some((name, value, child))))) => {
+                    if self.update(&name, value) {
+                        updated = true;
                     }
+                    self.children.push(child);
                 }
+                // cancelled child
+                Ok(Async::Ready(Some(None))) => {}
+                // no children (yet)
+                Ok(Async::Ready(None)) => break,
+                Ok(Async::NotReady) => break,
             }
         }
         if updated {
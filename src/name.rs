@@ -1,20 +1,30 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
 use std::num::ParseIntError;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
 
 use abstract_ns;
+use abstract_ns::Address;
+use abstract_ns::addr::{Builder, Weight};
 use abstract_ns::name::{self, Name};
 use quick_error::ResultExt;
 
+use config::Config;
+
 quick_error! {
     #[derive(Debug)]
     pub enum Error {
         Name(name: String, err: name::Error) {
+            display("bad name {:?}: {}", name, err)
             cause(err)
             context(name: &'a str, err: name::Error)
                 -> (name.to_string(), err)
         }
         Port(name: String, err: ParseIntError) {
+            display("bad port in {:?}, neither a number nor a known \
+                     service name: {}", name, err)
             cause(err)
             context(name: &'a str, err: ParseIntError)
                 -> (name.to_string(), err)
@@ -29,12 +39,23 @@ quick_error! {
 ///
 /// * `example.org` → A record example.org, port 80
 /// * `example.org:8080` → A record example.org, port 8080
+/// * `example.org:https` → A record example.org, port 443 (see below)
+/// * `example.org.:8080` → same as `example.org:8080` (trailing dot of
+///   the fully-qualified name is stripped)
 /// * `_service._proto.example.org` → SRV record, and port from the record
 /// * `127.0.0.1` → IP used directly, port 80
 /// * `127.0.0.1:8080` → IP/port used directly
 /// * `2001:db8::2:1` → IPv6 address (note: no brackets)
 /// * `[2001:db8::2:1]:1235` → IPv6 address and port (note: square brackets)
 ///
+/// Unicode host names (like `münchen.example:8080`) are encoded into
+/// the ASCII form if `Config::idna_names` is enabled, underscored labels
+/// and ports are kept intact.
+///
+/// Symbolic port names are looked up in the names registered by
+/// `Config::register_port` and then in a built-in table of common services
+/// (`http`, `https`, `ssh`, `smtp`).
+///
 /// This works by wrapping the string read from configuration file into
 /// `AutoName::Auto` and using it in `Router`. You might override things
 /// via configuration specific things, for example in yaml you might want
@@ -63,8 +84,63 @@ pub enum AutoName<'a> {
     IpAddr(IpAddr),
     /// A bare socket address used directly as a service address
     SocketAddr(SocketAddr),
+    /// A socket address used directly, with the specified weight
+    ///
+    /// This allows to prefer some addresses over others in
+    /// `Router::subscribe_many` (other addresses usually have weight `1`).
+    WeightedAddr(SocketAddr, u32),
 }
 
+/// An owned counterpart of `AutoName`
+///
+/// This is useful to keep names in configuration structures and to parse
+/// them from strings. Unlike `AutoName::Auto`, parsing a string (via
+/// `FromStr`) determines the most specific variant right away:
+///
+/// ```
+/// # use ns_router::AutoNameBuf;
+/// let name = "_svc._tcp.example.org".parse::<AutoNameBuf>().unwrap();
+/// assert_eq!(name, AutoNameBuf::Service("_svc._tcp.example.org".into()));
+/// let name = "example.org:8080".parse::<AutoNameBuf>().unwrap();
+/// assert_eq!(name, AutoNameBuf::HostPort("example.org".into(), 8080));
+/// ```
+///
+/// Symbolic ports are resolved using only built-in port names, because
+/// there is no config at this point. Use `as_auto_name` (or `&name` where
+/// `Into<AutoName>` is expected) to pass it to the router.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AutoNameBuf {
+    /// Auto-determine how to treat the name
+    Auto(String),
+    /// Resolve host and attach specified port
+    HostPort(String, u16),
+    /// Resolve host and attach default port to it
+    HostDefaultPort(String),
+    /// Use service name and port resolved using SRV record or similar
+    Service(String),
+    /// A bare IP used directly as a host
+    IpAddr(IpAddr),
+    /// A bare socket address used directly as a service address
+    SocketAddr(SocketAddr),
+    /// A socket address used directly, with the specified weight
+    ///
+    /// This allows to prefer some addresses over others in
+    /// `Router::subscribe_many` (other addresses usually have weight `1`).
+    WeightedAddr(SocketAddr, u32),
+}
+
+/// The way a name is resolved, as decided when parsing an `AutoName`
+///
+/// Returned from `Router::resolve_auto_explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResolvedMode {
+    /// Host name is resolved and the port is attached to the addresses
+    HostPort,
+    /// Service is resolved using SRV record or similar
+    Service,
+    /// Socket address is used directly, nothing is resolved
+    Addr,
+}
 
 /// A helper trait to convert anything (yielded by a Stream) into name
 ///
@@ -85,15 +161,61 @@ pub trait IntoNameIter<'a> {
     fn into_name_iter(&'a self) -> Self::IntoIter;
 }
 
+/// Options of name parsing, taken from the config currently in use
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ParseOptions {
+    /// Port names registered by `Config::register_port`
+    pub ports: HashMap<String, u16>,
+    /// Encode unicode host names, set by `Config::idna_names`
+    pub idna: bool,
+}
+
+/// Parse options shared between router (which parses names) and resolver
+/// coroutine (which receives configs)
+pub(crate) type SharedParseOptions = Arc<RwLock<ParseOptions>>;
+
+/// Port numbers used for common service names
+const KNOWN_PORTS: &[(&str, u16)] = &[
+    ("http", 80),
+    ("https", 443),
+    ("ssh", 22),
+    ("smtp", 25),
+];
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub(crate) enum InternalName {
     HostPort(Name, u16),
     Service(Name),
-    Addr(SocketAddr),
+    /// Weight is only set for `AutoName::WeightedAddr`
+    Addr(SocketAddr, Option<Weight>),
+}
+
+impl InternalName {
+    pub(crate) fn mode(&self) -> ResolvedMode {
+        match *self {
+            InternalName::HostPort(..) => ResolvedMode::HostPort,
+            InternalName::Service(..) => ResolvedMode::Service,
+            InternalName::Addr(..) => ResolvedMode::Addr,
+        }
+    }
+}
+
+/// Returns an address consisting of a single socket address
+pub(crate) fn weighted_address(addr: SocketAddr, weight: Option<Weight>)
+    -> Address
+{
+    match weight {
+        Some(weight) => {
+            let mut builder = Builder::new();
+            builder.add_addresses(&[(weight, addr)]);
+            builder.into_address()
+        }
+        None => addr.into(),
+    }
 }
 
 impl<'a> AutoName<'a> {
-    pub(crate) fn parse(&self, default_port: u16)
+    pub(crate) fn parse(&self, default_port: u16, opts: &ParseOptions)
         -> Result<InternalName, Error>
     {
         use self::AutoName as A;
@@ -101,27 +223,185 @@ impl<'a> AutoName<'a> {
         match *self {
             A::Auto(x) => {
                 if let Ok(ip) = x.parse() {
-                    Ok(I::Addr(SocketAddr::new(ip, default_port)))
+                    Ok(I::Addr(SocketAddr::new(ip, default_port), None))
                 } else if let Ok(sa) = x.parse() {
-                    Ok(I::Addr(sa))
+                    Ok(I::Addr(sa, None))
                 } else if x.starts_with("_") {
-                    Ok(I::Service(Name::from_str(x).context(x)?))
+                    Ok(I::Service(host_name(x, x, opts)?))
                 } else if let Some(pos) = x.find(':') {
-                    Ok(I::HostPort(Name::from_str(&x[..pos]).context(x)?,
-                                   x[pos+1..].parse().context(x)?))
+                    Ok(I::HostPort(host_name(x, &x[..pos], opts)?,
+                                   parse_port(x, &x[pos+1..], &opts.ports)?))
                 } else {
-                    Ok(I::HostPort(Name::from_str(x).context(x)?,
-                                   default_port))
+                    Ok(I::HostPort(host_name(x, x, opts)?, default_port))
                 }
             }
-            A::HostPort(name, port)
-            => Ok(I::HostPort(Name::from_str(name).context(name)?, port)),
-            A::HostDefaultPort(name)
-            => Ok(I::HostPort(Name::from_str(name).context(name)?, default_port)),
+            // literal IPs are used directly, there is nothing to resolve
+            A::HostPort(name, port) => match name.parse() {
+                Ok(ip) => Ok(I::Addr(SocketAddr::new(ip, port), None)),
+                Err(_) => Ok(I::HostPort(host_name(name, name, opts)?, port)),
+            },
+            A::HostDefaultPort(name) => match name.parse() {
+                Ok(ip) => {
+                    Ok(I::Addr(SocketAddr::new(ip, default_port), None))
+                }
+                Err(_) => Ok(I::HostPort(host_name(name, name, opts)?,
+                                         default_port)),
+            },
             A::Service(name)
-            => Ok(I::Service(Name::from_str(name).context(name)?)),
-            A::IpAddr(ip) => Ok(I::Addr(SocketAddr::new(ip, default_port))),
-            A::SocketAddr(sa) => Ok(I::Addr(sa)),
+            => Ok(I::Service(host_name(name, name, opts)?)),
+            A::IpAddr(ip) => {
+                Ok(I::Addr(SocketAddr::new(ip, default_port), None))
+            }
+            A::SocketAddr(sa) => Ok(I::Addr(sa, None)),
+            A::WeightedAddr(sa, weight) => {
+                Ok(I::Addr(sa, Some(weight.into())))
+            }
+        }
+    }
+}
+
+impl ParseOptions {
+    pub fn from_config(cfg: &Config) -> ParseOptions {
+        ParseOptions {
+            ports: cfg.ports.clone(),
+            idna: cfg.idna_names,
+        }
+    }
+}
+
+/// Converts host part of the `name` into `Name`, encoding unicode labels
+/// if enabled
+///
+/// A single trailing dot of the fully-qualified name is stripped.
+fn host_name(name: &str, host: &str, opts: &ParseOptions)
+    -> Result<Name, Error>
+{
+    let host = if host.ends_with('.') && !host.ends_with("..") {
+        &host[..host.len()-1]
+    } else {
+        host
+    };
+    if opts.idna && !host.is_ascii() {
+        // if host can't be encoded, `Name` rejects it with a proper error
+        if let Some(host) = to_ascii(host) {
+            return Ok(Name::from_str(&host).context(name)?);
+        }
+    }
+    Ok(Name::from_str(host).context(name)?)
+}
+
+#[cfg(feature="idna")]
+fn to_ascii(host: &str) -> Option<String> {
+    ::idna::domain_to_ascii(host).ok()
+}
+
+#[cfg(not(feature="idna"))]
+fn to_ascii(_host: &str) -> Option<String> {
+    None
+}
+
+fn parse_port(name: &str, port: &str, ports: &HashMap<String, u16>)
+    -> Result<u16, Error>
+{
+    match port.parse() {
+        Ok(port) => Ok(port),
+        Err(e) => ports.get(port).cloned()
+            .or_else(|| {
+                KNOWN_PORTS.iter()
+                    .find(|&&(known, _)| known == port)
+                    .map(|&(_, num)| num)
+            })
+            .ok_or(e).context(name).map_err(Error::from),
+    }
+}
+
+impl AutoNameBuf {
+    /// Borrow the name as `AutoName`
+    pub fn as_auto_name(&self) -> AutoName<'_> {
+        use self::AutoNameBuf as B;
+        use self::AutoName as A;
+        match *self {
+            B::Auto(ref x) => A::Auto(x),
+            B::HostPort(ref x, port) => A::HostPort(x, port),
+            B::HostDefaultPort(ref x) => A::HostDefaultPort(x),
+            B::Service(ref x) => A::Service(x),
+            B::IpAddr(ip) => A::IpAddr(ip),
+            B::SocketAddr(sa) => A::SocketAddr(sa),
+            B::WeightedAddr(sa, weight) => A::WeightedAddr(sa, weight),
+        }
+    }
+}
+
+impl FromStr for AutoNameBuf {
+    type Err = abstract_ns::Error;
+    fn from_str(x: &str) -> Result<AutoNameBuf, abstract_ns::Error> {
+        parse_buf(x).map_err(|e| e.into())
+    }
+}
+
+fn parse_buf(x: &str) -> Result<AutoNameBuf, Error> {
+    use self::AutoNameBuf as B;
+    let opts = ParseOptions::default();
+    if let Ok(ip) = x.parse() {
+        Ok(B::IpAddr(ip))
+    } else if let Ok(sa) = x.parse() {
+        Ok(B::SocketAddr(sa))
+    } else if x.starts_with("_") {
+        host_name(x, x, &opts)?;
+        Ok(B::Service(x.to_string()))
+    } else if let Some(pos) = x.find(':') {
+        host_name(x, &x[..pos], &opts)?;
+        let port = parse_port(x, &x[pos+1..], &opts.ports)?;
+        Ok(B::HostPort(x[..pos].to_string(), port))
+    } else {
+        host_name(x, x, &opts)?;
+        Ok(B::HostDefaultPort(x.to_string()))
+    }
+}
+
+/// Renders the name in the form accepted by `AutoName::Auto`
+///
+/// Note: `Service` is rendered as is, so service names which don't start
+/// with underscore are read back as hosts. Weight of `WeightedAddr` is not
+/// rendered.
+impl<'a> fmt::Display for AutoName<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::AutoName as A;
+        match *self {
+            A::Auto(x) | A::HostDefaultPort(x) | A::Service(x) => {
+                f.write_str(x)
+            }
+            A::HostPort(x, port) => write!(f, "{}:{}", x, port),
+            A::IpAddr(ip) => write!(f, "{}", ip),
+            A::SocketAddr(sa) | A::WeightedAddr(sa, _) => write!(f, "{}", sa),
+        }
+    }
+}
+
+impl fmt::Display for AutoNameBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_auto_name().fmt(f)
+    }
+}
+
+impl<'a> From<&'a AutoNameBuf> for AutoName<'a> {
+    fn from(val: &'a AutoNameBuf) -> AutoName<'a> {
+        val.as_auto_name()
+    }
+}
+
+impl<'a> From<AutoName<'a>> for AutoNameBuf {
+    fn from(val: AutoName<'a>) -> AutoNameBuf {
+        use self::AutoNameBuf as B;
+        use self::AutoName as A;
+        match val {
+            A::Auto(x) => B::Auto(x.to_string()),
+            A::HostPort(x, port) => B::HostPort(x.to_string(), port),
+            A::HostDefaultPort(x) => B::HostDefaultPort(x.to_string()),
+            A::Service(x) => B::Service(x.to_string()),
+            A::IpAddr(ip) => B::IpAddr(ip),
+            A::SocketAddr(sa) => B::SocketAddr(sa),
+            A::WeightedAddr(sa, weight) => B::WeightedAddr(sa, weight),
         }
     }
 }
@@ -157,7 +437,7 @@ impl Into<abstract_ns::Error> for Error {
                 abstract_ns::Error::InvalidName(name, "bad name")
             }
             Error::Port(name, _) => {
-                abstract_ns::Error::InvalidName(name, "bad port number")
+                abstract_ns::Error::InvalidName(name, "bad port")
             }
         }
     }
@@ -167,26 +447,137 @@ impl Into<abstract_ns::Error> for Error {
 mod test {
     use abstract_ns::Name;
     use super::AutoName as A;
+    use super::ParseOptions;
     use super::InternalName as I;
 
     fn name(name: &str) -> Name {
         name.parse().unwrap()
     }
 
+    fn no_ports() -> ParseOptions {
+        ParseOptions::default()
+    }
+
     #[test]
     fn auto() {
-        assert_eq!(A::Auto("localhost").parse(1234).unwrap(),
+        assert_eq!(A::Auto("localhost").parse(1234, &no_ports()).unwrap(),
             I::HostPort(name("localhost"), 1234));
-        assert_eq!(A::Auto("localhost:8080").parse(1234).unwrap(),
+        assert_eq!(A::Auto("localhost:8080").parse(1234, &no_ports()).unwrap(),
             I::HostPort(name("localhost"), 8080));
-        assert_eq!(A::Auto("_my._svc.localhost").parse(1234).unwrap(),
-            I::Service(name("_my._svc.localhost")));
+        assert_eq!(A::Auto("_my._svc.localhost").parse(1234, &no_ports())
+            .unwrap(), I::Service(name("_my._svc.localhost")));
+    }
+
+    #[test]
+    fn literal_hosts() {
+        let addr = "127.0.0.1:8080".parse().unwrap();
+        assert_eq!(A::HostPort("127.0.0.1", 8080).parse(1234, &no_ports())
+            .unwrap(), I::Addr(addr, None));
+        let addr = "[::1]:1234".parse().unwrap();
+        assert_eq!(A::HostDefaultPort("::1").parse(1234, &no_ports())
+            .unwrap(), I::Addr(addr, None));
     }
 
     #[test]
     #[should_panic(expected="InvalidChar")]
     fn bad_names() {
-        A::Auto("_my._svc.localhost:8080").parse(1234).unwrap();
+        A::Auto("_my._svc.localhost:8080").parse(1234, &no_ports()).unwrap();
+    }
+
+    #[test]
+    fn symbolic_ports() {
+        assert_eq!(A::Auto("localhost:https").parse(1234, &no_ports())
+            .unwrap(), I::HostPort(name("localhost"), 443));
+        assert_eq!(A::Auto("localhost:ssh").parse(1234, &no_ports())
+            .unwrap(), I::HostPort(name("localhost"), 22));
+        let mut ports = ParseOptions::default();
+        ports.ports.insert("admin".to_string(), 8081);
+        ports.ports.insert("http".to_string(), 8080);
+        assert_eq!(A::Auto("localhost:admin").parse(1234, &ports).unwrap(),
+            I::HostPort(name("localhost"), 8081));
+        // registered ports override built-in ones
+        assert_eq!(A::Auto("localhost:http").parse(1234, &ports).unwrap(),
+            I::HostPort(name("localhost"), 8080));
+        assert_eq!(A::Auto("localhost:8080").parse(1234, &ports).unwrap(),
+            I::HostPort(name("localhost"), 8080));
+    }
+
+    #[test]
+    #[cfg(feature="idna")]
+    fn idna() {
+        let opts = ParseOptions { idna: true, .. ParseOptions::default() };
+        assert_eq!(A::Auto("münchen.example:8080").parse(1234, &opts)
+            .unwrap(), I::HostPort(name("xn--mnchen-3ya.example"), 8080));
+        assert_eq!(A::Auto("münchen.example").parse(1234, &opts)
+            .unwrap(), I::HostPort(name("xn--mnchen-3ya.example"), 1234));
+        assert_eq!(A::Auto("_http._tcp.münchen.example").parse(1234, &opts)
+            .unwrap(), I::Service(name("_http._tcp.xn--mnchen-3ya.example")));
+        assert_eq!(A::HostPort("münchen.example", 443).parse(1234, &opts)
+            .unwrap(), I::HostPort(name("xn--mnchen-3ya.example"), 443));
+    }
+
+    #[test]
+    #[should_panic(expected="InvalidChar")]
+    fn idna_disabled() {
+        A::Auto("münchen.example:8080").parse(1234, &no_ports()).unwrap();
+    }
+
+    #[test]
+    fn trailing_dot() {
+        assert_eq!(A::Auto("localhost.").parse(1234, &no_ports()).unwrap(),
+            I::HostPort(name("localhost"), 1234));
+        assert_eq!(A::Auto("example.org.:8080").parse(1234, &no_ports())
+            .unwrap(), I::HostPort(name("example.org"), 8080));
+        assert_eq!(A::Auto("_my._svc.localhost.").parse(1234, &no_ports())
+            .unwrap(), I::Service(name("_my._svc.localhost")));
+        assert_eq!(A::HostPort("example.org.", 80).parse(1234, &no_ports())
+            .unwrap(), I::HostPort(name("example.org"), 80));
+    }
+
+    #[test]
+    fn double_trailing_dot() {
+        assert!(A::Auto("example.org..").parse(1234, &no_ports()).is_err());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(A::HostPort("example.org", 8080).to_string(),
+            "example.org:8080");
+        assert_eq!(A::HostDefaultPort("example.org").to_string(),
+            "example.org");
+        assert_eq!(A::Service("_my._svc.localhost").to_string(),
+            "_my._svc.localhost");
+        assert_eq!(A::IpAddr("2001:db8::2:1".parse().unwrap()).to_string(),
+            "2001:db8::2:1");
+        assert_eq!(A::SocketAddr("[2001:db8::2:1]:1235".parse().unwrap())
+            .to_string(), "[2001:db8::2:1]:1235");
     }
-}
 
+    #[test]
+    fn from_str() {
+        use super::AutoNameBuf as B;
+        for (src, val) in vec![
+            ("localhost", B::HostDefaultPort("localhost".into())),
+            ("localhost:8080", B::HostPort("localhost".into(), 8080)),
+            ("localhost:https", B::HostPort("localhost".into(), 443)),
+            ("_my._svc.localhost", B::Service("_my._svc.localhost".into())),
+            ("127.0.0.1", B::IpAddr("127.0.0.1".parse().unwrap())),
+            ("2001:db8::2:1", B::IpAddr("2001:db8::2:1".parse().unwrap())),
+            ("[2001:db8::2:1]:1235",
+             B::SocketAddr("[2001:db8::2:1]:1235".parse().unwrap())),
+        ] {
+            let parsed = src.parse::<B>().unwrap();
+            assert_eq!(parsed, val);
+            // round trip
+            assert_eq!(parsed.to_string().parse::<B>().unwrap(), val);
+        }
+        assert!("localhost:nope".parse::<B>().is_err());
+        assert!("_my._svc.localhost:8080".parse::<B>().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected="Port")]
+    fn unknown_port() {
+        A::Auto("localhost:admin").parse(1234, &no_ports()).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Cache of names which are known not to exist
+//!
+//! Enabled by `Config::negative_cache_ttl`.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use abstract_ns::{Name, Error};
+use futures::{Future, Async};
+use futures::sync::oneshot;
+use void::Void;
+
+use coroutine::FutureResult;
+use internal::{reply, fail};
+
+
+/// Kind of the lookup, host and service of the same name are cached
+/// separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Lookup {
+    Host,
+    Service,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct NegativeCache {
+    expire: HashMap<(Name, Lookup), Instant>,
+}
+
+pub(crate) type SharedNegativeCache = Rc<RefCell<NegativeCache>>;
+
+/// Waits for the reply and remembers the name if it's not found
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Remember<T> {
+    name: Name,
+    lookup: Lookup,
+    ttl: Duration,
+    cache: SharedNegativeCache,
+    rx: oneshot::Receiver<Result<T, Error>>,
+    tx: Option<oneshot::Sender<Result<T, Error>>>,
+}
+
+impl NegativeCache {
+    /// Returns `true` if name is known not to exist, drops expired entry
+    pub fn contains(&mut self, name: &Name, lookup: Lookup) -> bool {
+        let key = (name.clone(), lookup);
+        match self.expire.get(&key) {
+            Some(&deadline) if deadline > Instant::now() => return true,
+            Some(_) => {}
+            None => return false,
+        }
+        self.expire.remove(&key);
+        false
+    }
+    pub fn clear(&mut self) {
+        self.expire.clear();
+    }
+}
+
+impl<T> Remember<T> {
+    pub fn new(name: Name, lookup: Lookup, ttl: Duration,
+        cache: &SharedNegativeCache,
+        rx: oneshot::Receiver<Result<T, Error>>,
+        tx: oneshot::Sender<Result<T, Error>>)
+        -> Remember<T>
+    {
+        Remember {
+            name, lookup, ttl, rx,
+            cache: cache.clone(),
+            tx: Some(tx),
+        }
+    }
+}
+
+impl<T: Send + fmt::Debug + 'static> Future for Remember<T> {
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        let tx = self.tx.take().expect("future polled twice");
+        match result {
+            Ok(value) => reply(&self.name, tx, value),
+            Err(Error::NameNotFound) => {
+                debug!("{:?} is not found, caching for {:?}",
+                    self.name, self.ttl);
+                self.cache.borrow_mut().expire.insert(
+                    (self.name.clone(), self.lookup),
+                    Instant::now() + self.ttl);
+                fail(&self.name, tx, Error::NameNotFound);
+            }
+            Err(e) => fail(&self.name, tx, e),
+        }
+        Ok(Async::Ready(FutureResult::Done))
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::sync::Arc;
+
+use abstract_ns::{Name, Error};
+use futures::{Future, Async};
+use futures::future::ok;
+use futures::sync::oneshot;
+use void::Void;
+
+use config::Config;
+use coroutine::{ResolverFuture, FutureResult, Continuation};
+use internal::{reply, fail};
+
+
+/// Waits for name to be resolved and then pins it into the config
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct PinStatic<T> {
+    rx: oneshot::Receiver<Result<T, Error>>,
+    apply: Option<Apply<T>>,
+}
+
+pub(crate) struct Apply<T> {
+    name: Name,
+    value: Option<T>,
+    tx: oneshot::Sender<Result<(), Error>>,
+    insert: fn(&mut Config, Name, T),
+}
+
+impl<T> PinStatic<T> {
+    pub fn new(name: Name, rx: oneshot::Receiver<Result<T, Error>>,
+        tx: oneshot::Sender<Result<(), Error>>,
+        insert: fn(&mut Config, Name, T))
+        -> PinStatic<T>
+    {
+        PinStatic {
+            rx,
+            apply: Some(Apply { name, value: None, tx, insert }),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Apply<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Apply")
+        .field("name", &self.name)
+        .finish()
+    }
+}
+
+impl<T: 'static> Continuation for Option<Apply<T>> {
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let Apply { name, value, tx, insert } = self.take()
+            .expect("continuation called twice");
+        let value = value.expect("value is resolved");
+        let mut new_cfg = cfg.clone();
+        insert(Arc::make_mut(&mut new_cfg), name.clone(), value);
+        res.spawn(ok(FutureResult::UpdateConfig {
+            cfg: new_cfg,
+            next: Box::new(ok(FutureResult::Done)),
+        }));
+        reply(&name, tx, ());
+    }
+}
+
+impl<T: 'static> Future for PinStatic<T> {
+    type Item = FutureResult;
+    type Error = Void;
+    fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
+        let result = match self.rx.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => result,
+            Err(_) => Err(Error::TemporaryError("Resolver is down".into())),
+        };
+        let mut apply = self.apply.take().expect("future polled twice");
+        match result {
+            Ok(value) => {
+                // config must be updated from the coroutine because
+                // another config might be applied while we were resolving
+                apply.value = Some(value);
+                Ok(Async::Ready(FutureResult::Restart {
+                    task: Box::new(Some(apply)),
+                }))
+            }
+            Err(e) => {
+                fail(&apply.name, apply.tx, e);
+                Ok(Async::Ready(FutureResult::Done))
+            }
+        }
+    }
+}
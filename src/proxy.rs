@@ -0,0 +1,140 @@
+//! Resolver that leaves some names for the proxy to resolve
+//!
+//! When application connects through a SOCKS5 or HTTP proxy, names
+//! often must be resolved by the proxy rather than locally (for example
+//! because they are only resolvable in the network of the proxy).
+//!
+//! The integration contract is the following: connection layer calls
+//! `ProxyResolver::resolve_auto` and matches on the returned `Target`:
+//!
+//! * `Target::Resolved(addr)` -- connect to the proxy and ask it to connect
+//!   to one of the addresses (or connect directly, if proxy isn't needed
+//!   for this address)
+//! * `Target::Proxy(name, port)` -- connect to the proxy and pass the name
+//!   unchanged (i.e. SOCKS5 `CONNECT` with domain name address type or
+//!   HTTP `CONNECT name:port`)
+//!
+//! This module is only available with the `proxy` feature enabled.
+use std::collections::HashSet;
+
+use abstract_ns::{Name, Address, Error, Resolve};
+use futures::{Future, Async};
+
+use future::ResolveFuture;
+use name::{AutoName, InternalName, weighted_address};
+use router::Router;
+
+
+/// A resolution result of the `ProxyResolver`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// Name is resolved locally
+    Resolved(Address),
+    /// Name must be passed to proxy unchanged with this port
+    Proxy(Name, u16),
+}
+
+/// A wrapper around router that passes some names to the proxy
+///
+/// Only host names (i.e. `example.org:8080` or `example.org` with the
+/// default port) are passed to the proxy. Service names (SRV records)
+/// and bare IP addresses are always resolved locally, because proxy
+/// protocols can only carry a host and a port.
+#[derive(Debug, Clone)]
+pub struct ProxyResolver {
+    router: Router,
+    suffixes: HashSet<String>,
+    all: bool,
+}
+
+/// A future returned from `ProxyResolver::resolve_auto`
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct ProxyFuture(State);
+
+#[derive(Debug)]
+enum State {
+    Ready(Option<Result<Target, Error>>),
+    Resolving(ResolveFuture),
+}
+
+impl ProxyResolver {
+    /// Create a proxy resolver that resolves everything locally
+    ///
+    /// Use `proxy_suffix` or `proxy_all` to configure names that are
+    /// passed to the proxy.
+    pub fn new(router: &Router) -> ProxyResolver {
+        ProxyResolver {
+            router: router.clone(),
+            suffixes: HashSet::new(),
+            all: false,
+        }
+    }
+
+    /// Pass names with this suffix to the proxy
+    ///
+    /// Suffix matches the name itself and all of its subdomains, the
+    /// same way as `Config::add_suffix` does.
+    pub fn proxy_suffix<S: Into<String>>(&mut self, suffix: S) -> &mut Self {
+        self.suffixes.insert(suffix.into());
+        self
+    }
+
+    /// Pass all host names to the proxy
+    pub fn proxy_all(&mut self) -> &mut Self {
+        self.all = true;
+        self
+    }
+
+    fn is_proxied(&self, name: &str) -> bool {
+        if self.all || self.suffixes.contains(name) {
+            return true;
+        }
+        name.match_indices('.')
+            .any(|(idx, _)| self.suffixes.contains(&name[idx+1..]))
+    }
+
+    /// Resolve a name or leave it for the proxy
+    ///
+    /// See `Router::resolve_auto` for the description of the names.
+    pub fn resolve_auto<'x, N: Into<AutoName<'x>>>(&self,
+        name: N, default_port: u16)
+        -> ProxyFuture
+    {
+        match self.router.parse_name(name.into(), default_port) {
+            Ok(InternalName::HostPort(ref name, port))
+                if self.is_proxied(name.as_ref())
+            => {
+                ProxyFuture(State::Ready(Some(
+                    Ok(Target::Proxy(name.clone(), port)))))
+            }
+            Ok(InternalName::HostPort(name, port)) => {
+                ProxyFuture(State::Resolving(self.router.resolve_auto(
+                    AutoName::HostPort(name.as_ref(), port), port)))
+            }
+            Ok(InternalName::Service(name)) => {
+                ProxyFuture(State::Resolving(self.router.resolve(&name)))
+            }
+            Ok(InternalName::Addr(addr, weight)) => {
+                ProxyFuture(State::Ready(Some(Ok(Target::Resolved(
+                    weighted_address(addr, weight))))))
+            }
+            Err(e) => ProxyFuture(State::Ready(Some(Err(e.into())))),
+        }
+    }
+}
+
+impl Future for ProxyFuture {
+    type Item = Target;
+    type Error = Error;
+    fn poll(&mut self) -> Result<Async<Target>, Error> {
+        match self.0 {
+            State::Ready(ref mut value) => {
+                value.take().expect("future polled twice").map(Async::Ready)
+            }
+            State::Resolving(ref mut future) => {
+                Ok(future.poll()?.map(Target::Resolved))
+            }
+        }
+    }
+}
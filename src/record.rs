@@ -0,0 +1,22 @@
+use abstract_ns::{Address, IpList};
+
+
+/// A type of the record to resolve by `Router::resolve_record`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    /// IPv4 addresses of the host
+    A,
+    /// IPv6 addresses of the host
+    AAAA,
+    /// Service addresses (i.e. what `Router::resolve` returns)
+    SRV,
+}
+
+/// A result of `Router::resolve_record`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Record {
+    /// Addresses of the host, returned for `A` and `AAAA` records
+    Ip(IpList),
+    /// Addresses of the service, returned for `SRV` records
+    Srv(Address),
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+use std::sync::Arc;
+
+use abstract_ns::{Name, Address, Error, Subscribe};
+use abstract_ns::addr::union;
+use futures::{Stream, Async};
+
+use config::Config;
+use coroutine::{ResolverFuture, Continuation};
+use fuse::Fuse;
+use mapped::Unmap;
+use slot;
+use subscr::{Task, TaskResult, SubscrFuture};
+
+
+/// A boxed subscriber accepted by `Router::subscribe_redundant`
+///
+/// Boxing allows to mix resolvers of different types in one call.
+pub type BoxedSubscriber = Box<Subscribe<Error=Error,
+    Stream=Box<Stream<Item=Address, Error=Error> + Send>> + Send>;
+
+/// A request to subscribe to the name in all of the resolvers, see
+/// `Router::subscribe_redundant`
+pub(crate) struct SubscribeRedundant {
+    pub name: Name,
+    pub resolvers: Vec<BoxedSubscriber>,
+    pub tx: slot::Sender<Address>,
+}
+
+struct Source {
+    resolver: BoxedSubscriber,
+    stream: Fuse<Box<Stream<Item=Address, Error=Error> + Send>>,
+    value: Option<Address>,
+}
+
+/// Subscription which sends union of the latest values of all resolvers
+pub(crate) struct RedundantSubscr {
+    name: Name,
+    sources: Vec<Source>,
+    tx: slot::Sender<Address>,
+    last: Option<Address>,
+    unmap: bool,
+}
+
+impl fmt::Debug for SubscribeRedundant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // boxed resolvers aren't `Debug`
+        f.debug_struct("SubscribeRedundant")
+        .field("name", &self.name)
+        .field("resolvers", &self.resolvers.len())
+        .finish()
+    }
+}
+
+impl Continuation for Option<SubscribeRedundant> {
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let SubscribeRedundant { name, resolvers, tx } = self.take()
+            .expect("continuation called twice");
+        let sources = resolvers.into_iter()
+            .map(|resolver| Source {
+                stream: Fuse::new(resolver.subscribe(&name)),
+                resolver,
+                value: None,
+            })
+            .collect();
+        SubscrFuture::spawn_in(res, RedundantSubscr {
+            name, sources, tx,
+            last: None,
+            unmap: cfg.normalize_mapped_v4,
+        });
+    }
+    fn name(&self) -> Option<&Name> {
+        self.as_ref().map(|s| &s.name)
+    }
+}
+
+impl Source {
+    /// Polls the stream, returns `false` if stream is finished
+    fn poll(&mut self, name: &Name, unmap: bool) -> bool {
+        if self.stream.is_done() {
+            return false;
+        }
+        loop {
+            match self.stream.poll() {
+                Ok(Async::Ready(Some(x))) => {
+                    self.value = Some(x.unmap_if(unmap));
+                }
+                Ok(Async::Ready(None)) => {
+                    error!("End of stream while following {:?}", name);
+                    self.value = None;
+                    return false;
+                }
+                Err(e) => {
+                    error!("Error while following {:?}: {}", name, e);
+                    self.value = None;
+                    return false;
+                }
+                Ok(Async::NotReady) => return true,
+            }
+        }
+    }
+}
+
+impl Task for RedundantSubscr {
+    fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        for source in &mut self.sources {
+            if source.stream.is_done() {
+                source.stream = Fuse::new(
+                    source.resolver.subscribe(&self.name));
+            }
+        }
+        self.unmap = cfg.normalize_mapped_v4;
+        SubscrFuture::spawn_in(res, self)
+    }
+    fn poll(&mut self) -> TaskResult {
+        let mut alive = 0;
+        for source in &mut self.sources {
+            if source.poll(&self.name, self.unmap) {
+                alive += 1;
+            }
+        }
+        let seen = self.last.is_some() ||
+            self.sources.iter().any(|s| s.value.is_some());
+        if seen {
+            let value = union(
+                self.sources.iter().filter_map(|s| s.value.as_ref()));
+            if self.last.as_ref() != Some(&value) {
+                self.last = Some(value.clone());
+                if self.tx.swap(value).is_err() {
+                    return TaskResult::Stop;
+                }
+            }
+        }
+        if alive == 0 {
+            return TaskResult::DelayRestart;
+        }
+        match self.tx.poll_cancel() {
+            Ok(Async::NotReady) => TaskResult::Continue,
+            _ => TaskResult::Stop,
+        }
+    }
+    fn refresh(&mut self) {
+        for source in &mut self.sources {
+            source.stream.close();
+        }
+    }
+    fn name(&self) -> Option<&Name> {
+        Some(&self.name)
+    }
+}
@@ -1,5 +1,10 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::fmt;
-use std::sync::Arc;
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use abstract_ns::{Name, Resolve, HostResolve, Subscribe, HostSubscribe};
 use abstract_ns::{Address, Error};
@@ -8,18 +13,32 @@ use futures::future::{empty};
 use futures::stream::{once};
 use futures::sync::oneshot;
 use futures::sync::mpsc::{unbounded, UnboundedSender};
-use tokio_core::reactor::Handle;
 use void::Void;
 
-use config::Config;
+use cancel::{Subscription, SubscriptionId};
+use config::{Config, Timing};
 use coroutine::{ResolverFuture};
 use future::{AddrStream, ResolveFuture, HostStream, ResolveHostFuture};
-use future::{UpdateSink};
+use future::{UpdateSink, PinFuture, PickFuture, SuffixFuture};
+use future::{FallibleStream, FirstValue, ByPortFuture, ErrorStream};
+use future::{RoutingTableFuture, RecordFuture, RecordState};
+use future::RouterStatsFuture;
+use future::{TracedFuture, InitTimeoutStream, RetryFuture};
+use future::{ResolveManyFuture, ResolveHostsManyFuture};
 use internal::{fail, Request};
+use internal_traits::FixedResolver;
+use latency::LatencyStats;
+use metrics::Labeled;
+use record::RecordType;
+use redundant::{SubscribeRedundant, BoxedSubscriber};
+use runtime::Runtime;
 use multisubscr::MultiSubscr;
-use name::{AutoName, InternalName, IntoNameIter};
-use async_slot as slot;
-use subscr::Wrapper;
+use name::{self, AutoName, InternalName, IntoNameIter, ResolvedMode};
+use name::{ParseOptions, SharedParseOptions};
+use seeded::Seeded;
+use slot::{self, Backpressure, BackpressureStats};
+use subscr::{Wrapper, SubscribeWith};
+use timer::Timer;
 
 /// An actual router class
 ///
@@ -33,22 +52,48 @@ use subscr::Wrapper;
 /// we consider this a bug. If you want to force close all futures and
 /// subscriptions create a router with `from_stream` or `updating_config` and
 /// send EOS on stream or drop `UpdatingSink` respectively.
+///
+/// When a new config is applied, subscriptions are only resubscribed if
+/// the name is routed differently in the new config (a different suffix
+/// resolver or a static entry has been added, changed or removed).
+/// Subscriptions whose routing is unchanged keep their underlying stream
+/// and don't receive a duplicate value.
 #[derive(Debug, Clone)]
 pub struct Router {
     requests: UnboundedSender<Request>,
+    latency: Arc<Mutex<LatencyStats>>,
+    backpressure: Arc<Backpressure>,
+    parse_opts: SharedParseOptions,
 }
 
 
 impl Router {
 
     /// Create a router for a static config
-    pub fn from_config(config: &Arc<Config>, handle: &Handle) -> Router {
+    ///
+    /// Router is spawned on the `runtime`, which is usually
+    /// `&core.handle()`. See `ns_router::runtime` for other executors.
+    pub fn from_config<R: Runtime>(config: &Arc<Config>, runtime: &R)
+        -> Router
+    {
         let (tx, rx) = unbounded();
-        handle.spawn(ResolverFuture::new(
+        let latency = Arc::new(Mutex::new(LatencyStats::default()));
+        let future = ResolverFuture::new(
             once(Ok(config.clone())).chain(empty().into_stream()),
-            rx, &handle));
+            rx, &latency, Arc::new(runtime.clone()));
+        let parse_opts = future.parse_options();
+        // names can be parsed before config is applied by the coroutine
+        *parse_opts.write().expect("options lock is not poisoned") =
+            ParseOptions::from_config(config);
+        let metrics = future.metrics();
+        *metrics.write().expect("metrics lock is not poisoned") =
+            Labeled::from_config(config);
+        runtime.spawn(Box::new(future));
         Router {
             requests: tx,
+            latency,
+            backpressure: Arc::new(Backpressure::new(metrics)),
+            parse_opts,
         }
     }
 
@@ -60,13 +105,56 @@ impl Router {
     ///
     /// Note 2: when stream is closed router is shut down, so usually the
     /// stream must be infinite.
-    pub fn from_stream<S>(stream: S, handle: &Handle) -> Router
-        where S: Stream<Item=Arc<Config>, Error=Void> + 'static
+    pub fn from_stream<S, R>(stream: S, runtime: &R) -> Router
+        where S: Stream<Item=Arc<Config>, Error=Void> + 'static,
+              R: Runtime,
+    {
+        let (tx, rx) = unbounded();
+        let latency = Arc::new(Mutex::new(LatencyStats::default()));
+        let future = ResolverFuture::new(stream, rx, &latency,
+            Arc::new(runtime.clone()));
+        let parse_opts = future.parse_options();
+        let metrics = future.metrics();
+        runtime.spawn(Box::new(future));
+        Router {
+            requests: tx,
+            latency,
+            backpressure: Arc::new(Backpressure::new(metrics)),
+            parse_opts,
+        }
+    }
+
+    /// Create a router with updating config and timing parameters
+    ///
+    /// This works like `from_stream` but additionally accepts a stream of
+    /// timing parameters. Timing is applied on top of every config received
+    /// and, unlike a config update, doesn't restart subscriptions. So a
+    /// control plane can tune delays often without disturbing name
+    /// resolution.
+    ///
+    /// When the timing stream is closed, the last timing values are kept.
+    /// Router is shut down when the config stream is closed (as in
+    /// `from_stream`).
+    pub fn from_config_and_timing<S, T, R>(config: S, timing: T,
+        runtime: &R)
+        -> Router
+        where S: Stream<Item=Arc<Config>, Error=Void> + 'static,
+              T: Stream<Item=Timing, Error=Void> + 'static,
+              R: Runtime,
     {
         let (tx, rx) = unbounded();
-        handle.spawn(ResolverFuture::new(stream, rx, &handle));
+        let latency = Arc::new(Mutex::new(LatencyStats::default()));
+        let mut future = ResolverFuture::new(config, rx, &latency,
+            Arc::new(runtime.clone()));
+        future.follow_timing(timing);
+        let parse_opts = future.parse_options();
+        let metrics = future.metrics();
+        runtime.spawn(Box::new(future));
         Router {
             requests: tx,
+            latency,
+            backpressure: Arc::new(Backpressure::new(metrics)),
+            parse_opts,
         }
     }
 
@@ -74,29 +162,82 @@ impl Router {
     ///
     /// Note: router is shut down when `UpdateSink` is dropped. So keep
     /// it somewhere so you can update config.
-    pub fn updating_config(config: &Arc<Config>, handle: &Handle)
+    ///
+    /// The config currently used by the router can be inspected with
+    /// `UpdateSink::current_config`.
+    pub fn updating_config<R: Runtime>(config: &Arc<Config>, runtime: &R)
         -> (Router, UpdateSink)
     {
         let (ctx, crx) = slot::channel();
         let stream = once(Ok(config.clone())).chain(crx)
             .map_err(|_| unreachable!());
         let (tx, rx) = unbounded();
-        handle.spawn(ResolverFuture::new(stream, rx, &handle));
+        let latency = Arc::new(Mutex::new(LatencyStats::default()));
+        let future = ResolverFuture::new(stream, rx, &latency,
+            Arc::new(runtime.clone()));
+        let cell = future.config_cell();
+        let parse_opts = future.parse_options();
+        *parse_opts.write().expect("options lock is not poisoned") =
+            ParseOptions::from_config(config);
+        let metrics = future.metrics();
+        *metrics.write().expect("metrics lock is not poisoned") =
+            Labeled::from_config(config);
+        runtime.spawn(Box::new(future));
         return (
             Router {
                 requests: tx,
+                latency,
+                backpressure: Arc::new(Backpressure::new(metrics)),
+                parse_opts,
             },
-            UpdateSink(ctx),
+            UpdateSink(ctx, cell),
         );
     }
 
+    /// Create a router which resolves every name to the same address
+    ///
+    /// This is a test double: services (and subscriptions to them) resolve
+    /// to `addr`, hosts resolve to the IP addresses of `addr` (with the
+    /// port requested, if any). This is useful for testing applications
+    /// end to end without configuring any real resolver.
+    pub fn fixed<R: Runtime>(addr: Address, runtime: &R) -> Router {
+        let mut cfg = Config::new();
+        cfg.root = Arc::new(FixedResolver::new(addr));
+        Router::from_config(&cfg.done(), runtime)
+    }
+
+    /// Returns a snapshot of resolution latency statistics
+    ///
+    /// Latencies are only recorded when enabled with
+    /// `Config::track_latency`, otherwise all histograms are empty.
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latency.lock().expect("stats lock is not poisoned").clone()
+    }
+
+    /// Returns a snapshot of updates dropped because of slow consumers
+    ///
+    /// Subscription keeps only the latest value, so if application doesn't
+    /// poll the stream before the next update arrives, the previous update
+    /// is dropped. Non-zero values here mean that some consumers can't keep
+    /// up with the rate of updates.
+    pub fn backpressure_stats(&self) -> BackpressureStats {
+        self.backpressure.stats()
+    }
+
     pub(crate) fn _subscribe_stream<S>(&self,
         stream: S, tx: slot::Sender<Address>)
         where S: Stream<Item=Vec<InternalName>> + Send + 'static,
               S::Error: fmt::Display,
+    {
+        self._spawn_multi(MultiSubscr::new(stream, tx))
+    }
+
+    fn _spawn_multi<S>(&self, task: MultiSubscr<S>)
+        where S: Stream<Item=Vec<InternalName>> + Send + 'static,
+              S::Error: fmt::Display,
     {
         self.requests.unbounded_send(
-            Request::Task(Wrapper::wrap_send(MultiSubscr::new(stream, tx))))
+            Request::Task(Wrapper::wrap_send(task)))
             // can't do anything when resolver is down, (no error in stream)
             // but this will shut down stream which will be visible
             // for the appplication, which is probably shutting down anyway
@@ -119,24 +260,174 @@ impl Router {
     /// You can also specify a way to resolve the service by providing
     /// iterator over `AutoName` instances instead of plain `&str` (both are
     /// accepted in this method).
+    ///
+    /// Literal addresses (including `AutoName::HostPort` with an IP in
+    /// place of the host) never change, so they are emitted as is without
+    /// consulting any resolver.
+    ///
+    /// Addresses of all names are merged by priority: sets of the same
+    /// priority of every name are joined, in the order of names, into the
+    /// set of that priority in the resulting address. If the same socket
+    /// address is returned for multiple names, only its occurrence with
+    /// the highest priority is kept. Weights can't be read back from the
+    /// `Address` returned by a resolver, so only `AutoName::WeightedAddr`
+    /// keeps its weight, and all other addresses get weight of `1`.
+    ///
+    /// The first value is sent as soon as all the names are resolved, or
+    /// when `Config::convergence_delay` expires, whichever happens first
+    /// (names which are not resolved by then are added by later updates).
+    /// An empty list of names yields an empty address immediately, see
+    /// `Config::emit_empty_for_empty_list`.
     pub fn subscribe_many<'x, I>(&self, iter: I, default_port: u16)
         -> AddrStream
         where I: IntoIterator,
               I::Item: Into<AutoName<'x>>,
     {
-        let (tx, rx) = slot::channel();
-        let mut lst = Vec::new();
-        for addr in iter {
-            match addr.into().parse(default_port) {
-                Ok(x) => lst.push(x),
-                Err(e) => {
-                    warn!("Error parsing name: {}", e);
-                }
+        let (tx, rx) = self.backpressure.channel();
+        let (lst, _) = parse_names(iter, default_port, &self.parse_opts);
+        self._subscribe_stream(
+            once(Ok::<_, Void>(lst)).chain(empty().into_stream()), tx);
+        AddrStream(rx)
+    }
+
+    /// Subscribes to a list of names, each with its own default port
+    ///
+    /// This works the same as [`subscribe_many`](#method.subscribe_many),
+    /// but the iterator yields pairs of a name and a default port for
+    /// that name. This allows mixing names with different default ports
+    /// in a single subscription, so their addresses are still merged
+    /// together.
+    pub fn subscribe_many_ports<'x, I, N>(&self, iter: I) -> AddrStream
+        where I: IntoIterator<Item=(N, u16)>,
+              N: Into<AutoName<'x>>,
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let (lst, _) = parse_names_ports(iter, &self.parse_opts);
+        self._subscribe_stream(
+            once(Ok::<_, Void>(lst)).chain(empty().into_stream()), tx);
+        AddrStream(rx)
+    }
+
+    /// Subscribes to a list of names and returns a guard canceling
+    /// subscription
+    ///
+    /// This works the same as [`subscribe_many`](#method.subscribe_many),
+    /// but subscriptions of all the names are dropped right away when
+    /// `Subscription` is dropped, see `subscribe_cancelable` for more
+    /// info.
+    pub fn subscribe_many_cancelable<'x, I>(&self, iter: I,
+        default_port: u16)
+        -> (AddrStream, Subscription)
+        where I: IntoIterator,
+              I::Item: Into<AutoName<'x>>,
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let (lst, _) = parse_names(iter, default_port, &self.parse_opts);
+        let task = MultiSubscr::new(
+            once(Ok::<_, Void>(lst)).chain(empty().into_stream()), tx);
+        let id = SubscriptionId::new();
+        self.requests.unbounded_send(Request::Cancelable(id,
+                Box::new(Request::Task(Wrapper::wrap_send(task)))))
+            .map_err(|_| debug!("Stream subscription when resolver is down"))
+            .ok();
+        (AddrStream(rx), Subscription::new(id, &self.requests))
+    }
+
+    /// Subscribes to a single name
+    ///
+    /// Name is parsed the same way as names in
+    /// [`subscribe_many`](#method.subscribe_many). Service names are
+    /// subscribed directly (as in `subscribe`), so no list machinery is
+    /// involved. Host names are subscribed and the port is attached to
+    /// every address. Bare IP and socket addresses are emitted once and
+    /// the stream is kept open.
+    ///
+    /// If the name can't be parsed, a warning is logged and the stream
+    /// behaves like a subscription to an empty list.
+    pub fn subscribe_auto<'x, N: Into<AutoName<'x>>>(&self,
+        name: N, default_port: u16)
+        -> AddrStream
+    {
+        let (lst, _) = parse_names(Some(name), default_port,
+                                   &self.parse_opts);
+        match lst.into_iter().next() {
+            Some(InternalName::Service(name)) => self.subscribe(&name),
+            name => {
+                let (tx, rx) = self.backpressure.channel();
+                self._subscribe_stream(
+                    once(Ok::<_, Void>(name.into_iter().collect()))
+                    .chain(empty().into_stream()), tx);
+                AddrStream(rx)
             }
         }
+    }
+
+    /// Subscribes to a list of names with custom convergence delay
+    ///
+    /// This works the same as [`subscribe_many`](#method.subscribe_many)
+    /// but `convergence_delay` overrides the one set by
+    /// `Config::convergence_delay` for this subscription only. This is
+    /// useful for critical subscriptions that need partial results
+    /// faster (or, conversely, want to wait longer for all names).
+    pub fn subscribe_many_with<'x, I>(&self, iter: I, default_port: u16,
+        convergence_delay: Duration)
+        -> AddrStream
+        where I: IntoIterator,
+              I::Item: Into<AutoName<'x>>,
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let (lst, _) = parse_names(iter, default_port, &self.parse_opts);
+        self._spawn_multi(MultiSubscr::new(
+            once(Ok::<_, Void>(lst)).chain(empty().into_stream()), tx)
+            .convergence_delay(convergence_delay));
+        AddrStream(rx)
+    }
+
+    /// Subscribes to a list of names and returns names failed to parse
+    ///
+    /// This works the same as [`subscribe_many`](#method.subscribe_many)
+    /// but in addition to logging a warning, returns a list of names that
+    /// can't be parsed along with their index in the input list.
+    ///
+    /// Valid names are subscribed anyway.
+    pub fn subscribe_many_checked<'x, I>(&self, iter: I, default_port: u16)
+        -> (AddrStream, Vec<(usize, Error)>)
+        where I: IntoIterator,
+              I::Item: Into<AutoName<'x>>,
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let (lst, errors) = parse_names(iter, default_port, &self.parse_opts);
+        self._subscribe_stream(
+            once(Ok::<_, Void>(lst)).chain(empty().into_stream()), tx);
+        let errors = errors.into_iter()
+            .map(|(idx, e)| (idx, e.into()))
+            .collect();
+        (AddrStream(rx), errors)
+    }
+
+    /// Subscribes to a list of names only if all of them are valid
+    ///
+    /// This works the same as [`subscribe_many`](#method.subscribe_many)
+    /// but nothing is subscribed if any name can't be parsed. Instead,
+    /// every rejected name is returned along with the parse error, so
+    /// the list can be shown to the user (e.g. when validating
+    /// configuration). Errors are not logged.
+    pub fn try_subscribe_many<'x, I>(&self, iter: I, default_port: u16)
+        -> Result<AddrStream, Vec<(String, Error)>>
+        where I: IntoIterator,
+              I::Item: Into<AutoName<'x>>,
+    {
+        let (lst, errors) = try_parse_names(iter, default_port,
+                                            &self.parse_opts);
+        if !errors.is_empty() {
+            return Err(errors.into_iter()
+                .map(|(_, name, e)| (name, e.into()))
+                .collect());
+        }
+        let (tx, rx) = self.backpressure.channel();
         self._subscribe_stream(
             once(Ok::<_, Void>(lst)).chain(empty().into_stream()), tx);
-        AddrStream(rx)
+        Ok(AddrStream(rx))
     }
 
     /// Subscribes to a stream that yields lists of names
@@ -161,18 +452,10 @@ impl Router {
               S::Error: fmt::Display,
               <S::Item as IntoIterator>::Item: Into<AutoName<'x>>,
     {
-        let (tx, rx) = slot::channel();
+        let (tx, rx) = self.backpressure.channel();
+        let opts = self.parse_opts.clone();
         self._subscribe_stream(stream.map(move |iter| {
-            let mut lst = Vec::new();
-            for addr in iter {
-                match addr.into().parse(default_port) {
-                    Ok(x) => lst.push(x),
-                    Err(e) => {
-                        warn!("Error parsing name: {}", e);
-                    }
-                }
-            }
-            lst
+            parse_names(iter, default_port, &opts).0
         }), tx);
         AddrStream(rx)
     }
@@ -196,23 +479,517 @@ impl Router {
               S::Error: fmt::Display,
               for<'x> S::Item: IntoNameIter<'x>,
     {
-        let (tx, rx) = slot::channel();
+        let (tx, rx) = self.backpressure.channel();
+        let opts = self.parse_opts.clone();
         self._subscribe_stream(stream.map(move |iter| {
-            let mut lst = Vec::new();
-            for addr in iter.into_name_iter() {
-                match addr.into().parse(default_port) {
-                    Ok(x) => lst.push(x),
-                    Err(e) => {
-                        warn!("Error parsing name: {}", e);
-                        //unimplemented!();
-                    }
-                }
-            }
-            lst
+            parse_names(iter.into_name_iter(), default_port, &opts).0
         }), tx);
         AddrStream(rx)
     }
 
+    /// Returns the first value of the subscription to the name
+    ///
+    /// Unlike `resolve` this uses the subscription path, so works for
+    /// resolvers that only support subscriptions (i.e. have a watch API
+    /// but no one-shot request). The subscription is dropped when the
+    /// value is received or the future is dropped.
+    ///
+    /// If subscription is closed before yielding any value (i.e. router
+    /// is shut down), the future resolves to `Error::NameNotFound`.
+    pub fn first_value(&self, name: &Name) -> FirstValue {
+        FirstValue(self.subscribe(name))
+    }
+
+    /// Subscribes to a name and reports router shutdown as an error
+    ///
+    /// This works the same as `subscribe`, but when router is shut down
+    /// (config stream is closed or `Router::shutdown` is called) the
+    /// stream yields `Error::TemporaryError` before closing. This allows
+    /// to distinguish router shutdown from subscription being closed for
+    /// other reasons in code that handles generic streams. If the stream
+    /// is closed for any other reason (e.g. event loop is dropped) it
+    /// closes without an error.
+    pub fn subscribe_fallible(&self, name: &Name) -> FallibleStream {
+        let (tx, rx) = oneshot::channel();
+        self.requests.unbounded_send(Request::WatchShutdown(tx))
+            // if resolver is down already, `rx` is canceled and the
+            // stream is closed without an error
+            .map_err(|_| debug!("Subscription when resolver is down"))
+            .ok();
+        FallibleStream::new(self.subscribe(name), rx)
+    }
+
+    /// Subscribes to a name and reports errors of the resolver
+    ///
+    /// The address stream works the same as `subscribe`. Additionally,
+    /// whenever the stream of the underlying resolver fails, the error is
+    /// yielded by `ErrorStream` (subscription is restarted in
+    /// `restart_delay` as usual, so addresses continue to flow when the
+    /// resolver recovers). This is useful for health reporting.
+    ///
+    /// Error stream is finished when subscription is closed, i.e. shortly
+    /// after the address stream is dropped.
+    pub fn subscribe_with_errors(&self, name: &Name)
+        -> (AddrStream, ErrorStream)
+    {
+        let (tx, rx, erx) = self.backpressure.channel_with_errors();
+        self.requests.unbounded_send(
+            Request::Subscribe(name.clone(), tx))
+            // can't do anything when resolver is down, (no error in stream)
+            // but this will shut down stream which will be visible
+            // for the appplication, which is probably shutting down anyway
+            .map_err(|_| debug!("Subscription for {} when resolver is down",
+                name))
+            .ok();
+        (AddrStream(rx), ErrorStream(erx))
+    }
+
+    /// Subscribes to a name and returns a guard canceling subscription
+    ///
+    /// The address stream works the same as `subscribe`, i.e. dropping
+    /// it closes the subscription. Additionally, when `Subscription` is
+    /// dropped (or `Subscription::cancel` is called), the router drops
+    /// the subscription right away, so the underlying resolver is not
+    /// polled any more, and the address stream is finished. This allows
+    /// to stop the subscription from the code that doesn't own the
+    /// stream.
+    pub fn subscribe_cancelable(&self, name: &Name)
+        -> (AddrStream, Subscription)
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let id = SubscriptionId::new();
+        self.requests.unbounded_send(Request::Cancelable(id,
+                Box::new(Request::Subscribe(name.clone(), tx))))
+            // can't do anything when resolver is down, (no error in stream)
+            // but this will shut down stream which will be visible
+            // for the appplication, which is probably shutting down anyway
+            .map_err(|_| debug!("Subscription for {} when resolver is down",
+                name))
+            .ok();
+        (AddrStream(rx), Subscription::new(id, &self.requests))
+    }
+
+    /// Subscribes to a name and reports a slow initial resolution
+    ///
+    /// This works the same as `subscribe`, but if no value is received
+    /// within `timeout` after subscribing, the stream yields
+    /// `Error::TemporaryError("initial resolution timed out")`. The stream
+    /// isn't closed after the error, the name is still being resolved and
+    /// the value is yielded as soon as it arrives.
+    ///
+    /// Timeout only applies to the first value. After that the stream never
+    /// fails, even if the next update takes long.
+    pub fn subscribe_with_init_timeout<T: Timer>(&self, name: &Name,
+        timeout: Duration, timer: &T)
+        -> InitTimeoutStream
+    {
+        InitTimeoutStream::new(self.subscribe(name), timeout, timer)
+    }
+
+    /// Subscribes to a name starting with a known address
+    ///
+    /// The `seed` (i.e. addresses persisted on previous run) is yielded
+    /// immediately, even if router has no configuration yet. When first
+    /// live value is received, union of the seed and the live value is
+    /// yielded for [`seed_grace_period`], after that only live addresses
+    /// are yielded.
+    ///
+    /// [`seed_grace_period`]: struct.Config.html#method.seed_grace_period
+    pub fn subscribe_seeded(&self, name: &Name, seed: Address)
+        -> AddrStream
+    {
+        let (tx, rx) = self.backpressure.channel();
+        // can't fail, we own the receiver
+        tx.swap(seed.clone()).ok();
+        let task = Seeded::new(name.clone(), seed, tx);
+        self.requests.unbounded_send(
+            Request::Task(Wrapper::wrap_send(task)))
+            // can't do anything when resolver is down, (no error in stream)
+            // but this will shut down stream which will be visible
+            // for the appplication, which is probably shutting down anyway
+            .map_err(|_| debug!("Subscription for {} when resolver is down",
+                name))
+            .ok();
+        AddrStream(rx)
+    }
+
+    /// Subscribes to a name using specified resolver
+    ///
+    /// This bypasses both static hosts/services and suffix matching of
+    /// the current config, i.e. the name is always resolved by the
+    /// `resolver`. It's useful for tests and for picking resolver
+    /// dynamically per request.
+    ///
+    /// Subscription is still restarted in `restart_delay` when the
+    /// stream returned by resolver is finished or errored. It's not
+    /// rerouted on configuration updates.
+    pub fn subscribe_with<R>(&self, name: &Name, resolver: R) -> AddrStream
+        where R: Subscribe + Debug + Send + 'static,
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let task = Some(SubscribeWith { name: name.clone(), resolver, tx });
+        self.requests.unbounded_send(Request::Task(Box::new(task)))
+            // can't do anything when resolver is down, (no error in stream)
+            // but this will shut down stream which will be visible
+            // for the appplication, which is probably shutting down anyway
+            .map_err(|_| debug!("Subscription for {} when resolver is down",
+                name))
+            .ok();
+        AddrStream(rx)
+    }
+
+    /// Subscribes to a name in several resolvers at once
+    ///
+    /// Every resolver is subscribed independently (bypassing static
+    /// hosts/services and suffix matching as in `subscribe_with`), and
+    /// the stream yields union of the latest addresses of all of them.
+    /// This gives some redundancy when resolvers are backed by
+    /// independent sources (i.e. consul and DNS).
+    ///
+    /// When the stream of some resolver is finished or errored, its
+    /// addresses are removed from the union, other resolvers keep
+    /// contributing. Finished resolvers are resubscribed on the next
+    /// config update or `on_network_change`, or in `restart_delay` when
+    /// all of them are finished.
+    ///
+    /// Resolvers are boxed (see `BoxedSubscriber`), so resolvers of
+    /// different types can be mixed.
+    pub fn subscribe_redundant(&self, name: &Name,
+        resolvers: Vec<BoxedSubscriber>)
+        -> AddrStream
+    {
+        let (tx, rx) = self.backpressure.channel();
+        let task = Some(SubscribeRedundant {
+            name: name.clone(), resolvers, tx,
+        });
+        self.requests.unbounded_send(Request::Task(Box::new(task)))
+            // can't do anything when resolver is down, (no error in stream)
+            // but this will shut down stream which will be visible
+            // for the appplication, which is probably shutting down anyway
+            .map_err(|_| debug!("Subscription for {} when resolver is down",
+                name))
+            .ok();
+        AddrStream(rx)
+    }
+
+    /// Refreshes all active subscriptions
+    ///
+    /// Call this when network is changed (i.e. interface is up or down,
+    /// or the machine joined a different network), as DNS results are
+    /// likely changed too. Every subscription that isn't served from a
+    /// static host or service is resubscribed in the underlying resolver
+    /// immediately, so it doesn't need to wait for the next poll
+    /// interval.
+    pub fn on_network_change(&self) {
+        self.requests.unbounded_send(Request::Refresh)
+            .map_err(|_| debug!("Network change when resolver is down"))
+            .ok();
+    }
+
+    /// Shuts down the router
+    ///
+    /// The router stops accepting new requests, but resolves which are
+    /// already in flight are finished. All subscriptions are closed, i.e.
+    /// streams returned by `subscribe` and similar methods end right away.
+    /// Requests made after shutdown fail the same way as when the reactor
+    /// running the router is stopped.
+    pub fn shutdown(&self) {
+        self.requests.unbounded_send(Request::Shutdown)
+            .map_err(|_| debug!("Shutdown when resolver is already down"))
+            .ok();
+    }
+
+    /// Resolve a service and pick the least loaded address
+    ///
+    /// The `loads` map contains the number of connections (or any other
+    /// measure of the load) that the application has to each address.
+    /// Addresses which are absent in the map are treated as having zero
+    /// load.
+    ///
+    /// Ties are broken by priority (addresses from the higher priority set
+    /// are preferred) and then by the order of addresses in the set.
+    ///
+    /// Future returns `NameNotFound` if name resolves to an empty address.
+    pub fn resolve_one_with_load(&self, name: &Name,
+        loads: &HashMap<SocketAddr, usize>)
+        -> PickFuture
+    {
+        PickFuture {
+            future: self.resolve(name),
+            loads: loads.clone(),
+        }
+    }
+
+    /// Resolve a host and attach each of the specified ports to it
+    ///
+    /// The host is resolved once (as `resolve_host` does) and resulting
+    /// address contains an entry for every combination of IP and port.
+    /// All entries have the same priority and weight. Entries are ordered
+    /// by port first (in the order ports are specified) and then by IP (in
+    /// the order returned by resolver).
+    ///
+    /// This is useful when a single host serves several ports, for
+    /// example `80` for HTTP and `9100` for metrics.
+    pub fn resolve_host_ports(&self, name: &Name, ports: &[u16])
+        -> ResolveFuture
+    {
+        let (tx, rx) = oneshot::channel();
+        match self.requests.unbounded_send(
+            Request::ResolveHostPorts(name.clone(), ports.to_vec(), tx))
+        {
+            Ok(()) => {}
+            Err(e) => match e.into_inner() {
+                Request::ResolveHostPorts(name, _, tx) => {
+                    fail(&name, tx, Error::TemporaryError(
+                        "Resolver is down".into()));
+                }
+                _ => unreachable!(),
+            }
+        }
+        ResolveFuture(rx)
+    }
+
+    /// Resolve a service and group resulting IP addresses by port
+    ///
+    /// This is useful for services listening on multiple ports, when
+    /// application dials specific port. Addresses for each port are
+    /// ordered by priority and then in the order returned by resolver,
+    /// duplicates are removed. Weights are not preserved.
+    pub fn resolve_by_port(&self, name: &Name) -> ByPortFuture {
+        ByPortFuture(self.resolve(name))
+    }
+
+    /// Resolve a host and add it to the current config as a static host
+    ///
+    /// This is useful for "resolve once at boot, then treat as static"
+    /// pattern: when the future is complete, subsequent lookups of the
+    /// name are answered immediately and always return the same value
+    /// as if it was added by `Config::add_host`.
+    ///
+    /// Note: this snapshots a point-in-time result of the resolution.
+    /// Also the pinned entry is a part of the current config only, so
+    /// when a new config is pushed to the router (see `from_stream` and
+    /// `updating_config`) the pinned value is forgotten.
+    pub fn pin_static_host(&self, name: &Name) -> PinFuture {
+        let (tx, rx) = oneshot::channel();
+        match self.requests.unbounded_send(
+            Request::PinHost(name.clone(), tx))
+        {
+            Ok(()) => {}
+            Err(e) => match e.into_inner() {
+                Request::PinHost(name, tx) => {
+                    fail(&name, tx, Error::TemporaryError(
+                        "Resolver is down".into()));
+                }
+                _ => unreachable!(),
+            }
+        }
+        PinFuture(rx)
+    }
+
+    /// Resolve a service and add it to the current config as static one
+    ///
+    /// This is the same as [`pin_static_host`](#method.pin_static_host)
+    /// but for service names, i.e. the result is stored as if it was
+    /// added by `Config::add_service`.
+    pub fn pin_static(&self, name: &Name) -> PinFuture {
+        let (tx, rx) = oneshot::channel();
+        match self.requests.unbounded_send(
+            Request::Pin(name.clone(), tx))
+        {
+            Ok(()) => {}
+            Err(e) => match e.into_inner() {
+                Request::Pin(name, tx) => {
+                    fail(&name, tx, Error::TemporaryError(
+                        "Resolver is down".into()));
+                }
+                _ => unreachable!(),
+            }
+        }
+        PinFuture(rx)
+    }
+
+    /// Returns the suffix which the name is routed to in current config
+    ///
+    /// Returns `None` if no suffix matches, i.e. name is resolved by the
+    /// fallthrough resolver. Note: static hosts and services take
+    /// precedence over suffixes, so the suffix is not used for the
+    /// names added by `Config::add_host` and `Config::add_service`.
+    ///
+    /// This is useful for debugging routing decisions.
+    pub fn matched_suffix(&self, name: &Name) -> SuffixFuture {
+        let (tx, rx) = oneshot::channel();
+        match self.requests.unbounded_send(
+            Request::MatchedSuffix(name.clone(), tx))
+        {
+            Ok(()) => {}
+            Err(e) => match e.into_inner() {
+                Request::MatchedSuffix(name, tx) => {
+                    fail(&name, tx, Error::TemporaryError(
+                        "Resolver is down".into()));
+                }
+                _ => unreachable!(),
+            }
+        }
+        SuffixFuture(rx)
+    }
+
+    /// Resolve a service name and return how it was routed
+    ///
+    /// This works like `resolve`, but also returns the routing rule which
+    /// matched the name and the `Debug` representation of the resolver
+    /// that produced the answer. Resolvers are only described for this
+    /// kind of request, so other requests don't pay for formatting.
+    ///
+    /// This is useful for "explain" tooling.
+    pub fn resolve_traced(&self, name: &Name) -> TracedFuture {
+        let (tx, rx) = oneshot::channel();
+        match self.requests.unbounded_send(
+            Request::ResolveTraced(name.clone(), tx))
+        {
+            Ok(()) => {}
+            Err(e) => match e.into_inner() {
+                Request::ResolveTraced(name, tx) => {
+                    fail(&name, tx, Error::TemporaryError(
+                        "Resolver is down".into()));
+                }
+                _ => unreachable!(),
+            }
+        }
+        TracedFuture(rx)
+    }
+
+    /// Exports the current routing table
+    ///
+    /// Returns static hosts and services, suffixes and the fallthrough
+    /// resolver of the config currently in use (including names pinned by
+    /// `pin_static` and suffixes disabled by `disable_suffix`). Resolvers
+    /// are described by their `Debug` representation.
+    ///
+    /// This is useful for documentation generation and audits.
+    pub fn routing_table(&self) -> RoutingTableFuture {
+        let (tx, rx) = oneshot::channel();
+        // if resolver is down, `tx` is dropped and future returns error
+        self.requests.unbounded_send(Request::RoutingTable(tx)).ok();
+        RoutingTableFuture(rx)
+    }
+
+    /// Returns the number of resolutions and subscriptions in flight
+    ///
+    /// One-shot resolutions (`resolve`, `resolve_host` and others) are
+    /// counted separately from subscription tasks. A subscription task is
+    /// removed on the next iteration of the router after the stream is
+    /// dropped.
+    ///
+    /// This is useful for spotting leaked subscriptions.
+    pub fn stats(&self) -> RouterStatsFuture {
+        let (tx, rx) = oneshot::channel();
+        // if resolver is down, `tx` is dropped and future returns error
+        self.requests.unbounded_send(Request::Stats(tx)).ok();
+        RouterStatsFuture(rx)
+    }
+
+    /// Temporarily disables a suffix resolver
+    ///
+    /// Names matching the suffix are routed as if the suffix wasn't
+    /// configured, i.e. to a shorter matching suffix or to the fallthrough
+    /// resolver. Subscriptions are rerouted immediately.
+    ///
+    /// This is meant to route around a broken resolver during an incident.
+    /// The suffix is disabled until `enable_suffix` is called or a new
+    /// config is received from the config stream (the new config has all
+    /// suffixes enabled).
+    pub fn disable_suffix<S: Into<String>>(&self, suffix: S) {
+        self.set_suffix_enabled(suffix.into(), false)
+    }
+
+    /// Enables suffix resolver previously disabled by `disable_suffix`
+    pub fn enable_suffix<S: Into<String>>(&self, suffix: S) {
+        self.set_suffix_enabled(suffix.into(), true)
+    }
+
+    fn set_suffix_enabled(&self, suffix: String, enabled: bool) {
+        self.requests.unbounded_send(
+            Request::SetSuffixEnabled(suffix.to_ascii_lowercase(), enabled))
+            .map_err(|_| debug!("Suffix toggled when resolver is down"))
+            .ok();
+    }
+
+    /// Resolve a service ignoring static services in the config
+    ///
+    /// The name is sent directly to the suffix resolver (or fallthrough
+    /// resolver if no suffix matches) even if it's added by
+    /// `Config::add_service` or pinned by `pin_static`. This is mostly
+    /// useful for debugging and verification, i.e. to find out what
+    /// DNS actually returns for a name that has a local override.
+    ///
+    /// Note: resolution middleware and latency tracking are not applied
+    /// to this request.
+    pub fn resolve_bypass_static(&self, name: &Name) -> ResolveFuture {
+        let (tx, rx) = oneshot::channel();
+        match self.requests.unbounded_send(
+            Request::ResolveBypassStatic(name.clone(), tx))
+        {
+            Ok(()) => {}
+            Err(e) => match e.into_inner() {
+                Request::ResolveBypassStatic(name, tx) => {
+                    fail(&name, tx, Error::TemporaryError(
+                        "Resolver is down".into()));
+                }
+                _ => unreachable!(),
+            }
+        }
+        ResolveFuture(rx)
+    }
+
+    /// Resolve a service retrying on temporary errors
+    ///
+    /// Resolution is tried up to `attempts` times in total (so `1` means
+    /// no retries) with `backoff` sleep between tries. Only
+    /// `TemporaryError` is retried, other errors (e.g. `NameNotFound`) are
+    /// returned immediately. When all attempts fail the last error is
+    /// returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `attempts` is zero.
+    pub fn resolve_retry<T: Timer + Clone>(&self, name: &Name,
+        attempts: usize, backoff: Duration, timer: &T)
+        -> RetryFuture
+    {
+        assert!(attempts > 0, "at least one attempt is required");
+        RetryFuture::new(self, name, attempts, backoff, timer)
+    }
+
+    /// Resolve a record of the specified type
+    ///
+    /// This is a more explicit alternative to `resolve_auto`: `A` and
+    /// `AAAA` records are resolved via `resolve_host` and filtered by
+    /// address family, `SRV` records are resolved via `resolve`.
+    ///
+    /// If there are no addresses of the requested family, or the resolver
+    /// doesn't support the record type, future returns `NameNotFound`.
+    pub fn resolve_record(&self, name: &Name, kind: RecordType)
+        -> RecordFuture
+    {
+        match kind {
+            RecordType::A | RecordType::AAAA => {
+                RecordFuture(RecordState::Host(self.resolve_host(name), kind))
+            }
+            RecordType::SRV => {
+                RecordFuture(RecordState::Service(self.resolve(name)))
+            }
+        }
+    }
+
+    /// Parses name using parse options of the current config
+    pub(crate) fn parse_name(&self, name: AutoName, default_port: u16)
+        -> Result<InternalName, name::Error>
+    {
+        name.parse(default_port,
+            &self.parse_opts.read().expect("options lock is not poisoned"))
+    }
+
     /// Resolve a string or other things into an address
     ///
     /// See description of [`subscribe_many`] to find out how names are parsed
@@ -224,9 +1001,30 @@ impl Router {
     pub fn resolve_auto<'x, N: Into<AutoName<'x>>>(&self,
         name: N, default_port: u16)
         -> ResolveFuture
+    {
+        self._resolve_parsed(self.parse_name(name.into(), default_port))
+    }
+
+    /// Resolve a string or other things and tell how it's resolved
+    ///
+    /// This works the same as [`resolve_auto`](#method.resolve_auto), but
+    /// also returns the way the name is resolved (i.e. to check that
+    /// `_xmpp._tcp.example.org` is resolved as a service). Mode is `None`
+    /// if the name can't be parsed, the future fails in this case.
+    pub fn resolve_auto_explain<'x, N: Into<AutoName<'x>>>(&self,
+        name: N, default_port: u16)
+        -> (ResolveFuture, Option<ResolvedMode>)
+    {
+        let parsed = self.parse_name(name.into(), default_port);
+        let mode = parsed.as_ref().ok().map(|name| name.mode());
+        (self._resolve_parsed(parsed), mode)
+    }
+
+    fn _resolve_parsed(&self, name: Result<InternalName, name::Error>)
+        -> ResolveFuture
     {
         let (tx, rx) = oneshot::channel();
-        match name.into().parse(default_port) {
+        match name {
             Ok(InternalName::HostPort(name, port)) => {
                 match self.requests.unbounded_send(
                     Request::ResolveHostPort(name.clone(), port, tx))
@@ -255,8 +1053,8 @@ impl Router {
                     }
                 }
             }
-            Ok(InternalName::Addr(addr)) => {
-                tx.send(Ok(addr.into())).ok();
+            Ok(InternalName::Addr(addr, weight)) => {
+                tx.send(Ok(name::weighted_address(addr, weight))).ok();
             }
             Err(e) => {
                 tx.send(Err(e.into())).ok();
@@ -265,6 +1063,132 @@ impl Router {
         ResolveFuture(rx)
     }
 
+    /// Resolve a list of names concurrently
+    ///
+    /// Names are parsed as in [`resolve_auto`] and each one is resolved
+    /// independently. Future completes when all names are resolved,
+    /// results are returned in the same order as names. An error in one
+    /// of the names (including a parse error) doesn't affect others, it's
+    /// just put into the respective position of the list.
+    ///
+    /// [`resolve_auto`]: #method.resolve_auto
+    pub fn resolve_many<'x, I>(&self, iter: I, default_port: u16)
+        -> ResolveManyFuture
+        where I: IntoIterator,
+              I::Item: Into<AutoName<'x>>,
+    {
+        ResolveManyFuture::new(iter.into_iter()
+            .map(|name| self.resolve_auto(name, default_port))
+            .collect())
+    }
+
+    /// Resolve a list of host names into a single list of IP addresses
+    ///
+    /// Names are resolved concurrently (the same as `resolve_host`), and
+    /// the future completes when all of them are resolved. Addresses of
+    /// all names are merged, sorted and deduplicated. Names which are not
+    /// found are skipped, but any other error fails the whole future.
+    ///
+    /// Use [`resolve_many`] to get a result for every name.
+    ///
+    /// [`resolve_many`]: #method.resolve_many
+    pub fn resolve_hosts_many<I>(&self, iter: I) -> ResolveHostsManyFuture
+        where I: IntoIterator,
+              I::Item: Borrow<Name>,
+    {
+        ResolveHostsManyFuture::new(iter.into_iter()
+            .map(|name| self.resolve_host(name.borrow()))
+            .collect())
+    }
+
+    /// Resolve a string or other things into a list of IP addresses
+    ///
+    /// Names are parsed the same way as in [`resolve_auto`], but only
+    /// host part of the name is resolved and the port (either specified
+    /// or `default_port`) is dropped. Bare IP addresses and socket
+    /// addresses are returned as is (without the port).
+    ///
+    /// Service names (`_service._proto.example.org` or
+    /// `AutoName::Service`) carry ports in their records, so they can't
+    /// be resolved as hosts, the future fails with `InvalidName` for them.
+    ///
+    /// [`resolve_auto`]: #method.resolve_auto
+    pub fn resolve_host_auto<'x, N: Into<AutoName<'x>>>(&self,
+        name: N, default_port: u16)
+        -> ResolveHostFuture
+    {
+        let (tx, rx) = oneshot::channel();
+        match self.parse_name(name.into(), default_port) {
+            Ok(InternalName::HostPort(name, _)) => {
+                return self.resolve_host(&name);
+            }
+            Ok(InternalName::Service(name)) => {
+                tx.send(Err(Error::InvalidName(name.to_string(),
+                    "service name can't be resolved as a host"))).ok();
+            }
+            Ok(InternalName::Addr(addr, _)) => {
+                tx.send(Ok(vec![addr.ip()].into())).ok();
+            }
+            Err(e) => {
+                tx.send(Err(e.into())).ok();
+            }
+        }
+        ResolveHostFuture(rx)
+    }
+
+}
+
+fn parse_names<'x, I>(iter: I, default_port: u16,
+    opts: &SharedParseOptions)
+    -> (Vec<InternalName>, Vec<(usize, name::Error)>)
+    where I: IntoIterator,
+          I::Item: Into<AutoName<'x>>,
+{
+    parse_names_ports(iter.into_iter().map(|n| (n, default_port)), opts)
+}
+
+/// Parses names, each with its own default port
+fn parse_names_ports<'x, I, N>(iter: I, opts: &SharedParseOptions)
+    -> (Vec<InternalName>, Vec<(usize, name::Error)>)
+    where I: IntoIterator<Item=(N, u16)>,
+          N: Into<AutoName<'x>>,
+{
+    let (lst, errors) = try_parse_names_ports(iter, opts);
+    let errors = errors.into_iter()
+        .map(|(idx, _, e)| {
+            warn!("Error parsing name #{}: {}", idx, e);
+            (idx, e)
+        })
+        .collect();
+    (lst, errors)
+}
+
+/// Parses names, keeping the source text of names which fail to parse
+fn try_parse_names<'x, I>(iter: I, default_port: u16,
+    opts: &SharedParseOptions)
+    -> (Vec<InternalName>, Vec<(usize, String, name::Error)>)
+    where I: IntoIterator,
+          I::Item: Into<AutoName<'x>>,
+{
+    try_parse_names_ports(iter.into_iter().map(|n| (n, default_port)), opts)
+}
+
+fn try_parse_names_ports<'x, I, N>(iter: I, opts: &SharedParseOptions)
+    -> (Vec<InternalName>, Vec<(usize, String, name::Error)>)
+    where I: IntoIterator<Item=(N, u16)>,
+          N: Into<AutoName<'x>>,
+{
+    let opts = opts.read().expect("options lock is not poisoned");
+    let mut lst = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, (addr, default_port)) in iter.into_iter().enumerate() {
+        let addr = addr.into();
+        match addr.parse(default_port, &opts) {
+            Ok(x) => lst.push(x),
+            Err(e) => errors.push((idx, addr.to_string(), e)),
+        }
+    }
+    (lst, errors)
 }
 
 impl HostResolve for Router {
@@ -272,10 +1196,10 @@ impl HostResolve for Router {
     fn resolve_host(&self, name: &Name) -> ResolveHostFuture {
         let (tx, rx) = oneshot::channel();
         match self.requests.unbounded_send(
-            Request::ResolveHost(name.clone(), tx))
+            Request::ResolveHost(name.clone(), tx).nested())
         {
             Ok(()) => {}
-            Err(e) => match e.into_inner() {
+            Err(e) => match e.into_inner().unnest() {
                 Request::ResolveHost(name, tx) => {
                     fail(&name, tx, Error::TemporaryError(
                         "Resolver is down".into()));
@@ -292,10 +1216,10 @@ impl Resolve for Router {
     fn resolve(&self, name: &Name) -> ResolveFuture {
         let (tx, rx) = oneshot::channel();
         match self.requests.unbounded_send(
-            Request::Resolve(name.clone(), tx))
+            Request::Resolve(name.clone(), tx).nested())
         {
             Ok(()) => {}
-            Err(e) => match e.into_inner() {
+            Err(e) => match e.into_inner().unnest() {
                 Request::Resolve(name, tx) => {
                     fail(&name, tx, Error::TemporaryError(
                         "Resolver is down".into()));
@@ -312,9 +1236,9 @@ impl HostSubscribe for Router {
     type HostError = Void;
     type HostStream = HostStream;
     fn subscribe_host(&self, name: &Name) -> HostStream {
-        let (tx, rx) = slot::channel();
+        let (tx, rx) = self.backpressure.channel();
         self.requests.unbounded_send(
-            Request::HostSubscribe(name.clone(), tx))
+            Request::HostSubscribe(name.clone(), tx).nested())
             // can't do anything when resolver is down, (no error in stream)
             // but this will shut down stream which will be visible
             // for the appplication, which is probably shutting down anyway
@@ -329,9 +1253,9 @@ impl Subscribe for Router {
     type Error = Void;
     type Stream = AddrStream;
     fn subscribe(&self, name: &Name) -> AddrStream {
-        let (tx, rx) = slot::channel();
+        let (tx, rx) = self.backpressure.channel();
         self.requests.unbounded_send(
-            Request::Subscribe(name.clone(), tx))
+            Request::Subscribe(name.clone(), tx).nested())
             // can't do anything when resolver is down, (no error in stream)
             // but this will shut down stream which will be visible
             // for the appplication, which is probably shutting down anyway
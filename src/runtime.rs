@@ -0,0 +1,53 @@
+//! Abstraction of the executor the router runs on
+//!
+//! Router needs to spawn its main future and to create timers. Both are
+//! provided by `tokio_core::reactor::Handle` out of the box. To run the
+//! router on another executor, implement `Spawn` and `Timer` for a type
+//! which refers to it:
+//!
+//! ```rust,ignore
+//! #[derive(Debug, Clone)]
+//! struct MyRuntime { /* ... */ }
+//!
+//! impl Spawn for MyRuntime {
+//!     fn spawn(&self, future: Box<Future<Item=(), Error=()>>) {
+//!         // run the future to completion on the current thread
+//!     }
+//! }
+//!
+//! impl Timer for MyRuntime {
+//!     fn sleep(&self, duration: Duration) -> Sleep {
+//!         // return a future which resolves after `duration`
+//!     }
+//! }
+//!
+//! let router = Router::from_config(&config, &MyRuntime { /* ... */ });
+//! ```
+use futures::Future;
+use tokio_core::reactor::Handle;
+
+use timer::Timer;
+
+
+/// Spawns futures of the router
+pub trait Spawn {
+    /// Runs the future in the background until it's complete
+    ///
+    /// The future is not `Send`, so it must be polled on the thread that
+    /// spawned it (or on a thread local executor of it).
+    fn spawn(&self, future: Box<Future<Item=(), Error=()>>);
+}
+
+/// Everything the router needs from an executor
+///
+/// This is implemented automatically for every type that implements
+/// `Spawn`, `Timer` and `Clone`, including `tokio_core::reactor::Handle`.
+pub trait Runtime: Spawn + Timer + Clone {}
+
+impl<T: Spawn + Timer + Clone> Runtime for T {}
+
+impl Spawn for Handle {
+    fn spawn(&self, future: Box<Future<Item=(), Error=()>>) {
+        Handle::spawn(self, future)
+    }
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use abstract_ns::{Name, Address};
+use abstract_ns::addr::union;
+use slot;
+use futures::{Future, Stream, Async};
+
+use config::{Config, AddressRewriter};
+use coroutine::ResolverFuture;
+use subscr::{Task, TaskResult, SubscrFuture};
+use timer::Sleep;
+
+
+/// Subscription which is started with a seed value
+///
+/// Seed is unioned with the live value for the `seed_grace_period` after
+/// the first live value is received, and then dropped.
+pub(crate) struct Seeded {
+    name: Name,
+    seed: Option<Address>,
+    live: Option<Address>,
+    source: Option<slot::Receiver<Address>>,
+    timer: Option<Sleep>,
+    rewriter: Option<AddressRewriter>,
+    tx: slot::Sender<Address>,
+}
+
+impl Seeded {
+    pub(crate) fn new(name: Name, seed: Address, tx: slot::Sender<Address>)
+        -> Seeded
+    {
+        Seeded {
+            name, tx,
+            seed: Some(seed),
+            live: None,
+            source: None,
+            timer: None,
+            rewriter: None,
+        }
+    }
+    fn send_current(&mut self) -> bool {
+        let value = match (self.seed.as_ref(), self.live.as_ref()) {
+            (Some(seed), Some(live)) => union(vec![seed, live]),
+            (Some(seed), None) => seed.clone(),
+            (None, Some(live)) => live.clone(),
+            (None, None) => return true,
+        };
+        let value = match self.rewriter {
+            Some(ref rewriter) => rewriter.rewrite(value),
+            None => value,
+        };
+        self.tx.swap(value).is_ok()
+    }
+}
+
+impl Task for Seeded {
+    fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        self.rewriter = cfg.rewriter.clone();
+        if self.source.is_none() {
+            // underlying subscription is a separate task, which follows
+            // config updates by itself
+            let (tx, rx) = slot::channel();
+            res.subscribe(cfg, self.name.clone(), tx);
+            self.source = Some(rx);
+        }
+        if self.seed.is_some() && self.live.is_some() && self.timer.is_none() {
+            self.timer = Some(res.sleep(cfg, cfg.seed_grace_period));
+            if !self.send_current() {
+                return;
+            }
+        }
+        SubscrFuture::spawn_in(res, self)
+    }
+    fn poll(&mut self) -> TaskResult {
+        let mut updated = false;
+        let expired = match self.timer.as_mut().map(|t| t.poll()) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Err(e)) => {
+                warn!("Grace timer for {:?} failed: {}", self.name, e);
+                true
+            }
+            Some(Ok(Async::NotReady)) | None => false,
+        };
+        if expired {
+            self.timer = None;
+            self.seed = None;
+            updated = true;
+        }
+        if let Some(ref mut source) = self.source {
+            loop {
+                match source.poll() {
+                    Err(()) => unreachable!(), // slot doesn't fail
+                    Ok(Async::Ready(Some(x))) => {
+                        self.live = Some(x);
+                        updated = true;
+                    }
+                    Ok(Async::Ready(None)) => return TaskResult::Stop,
+                    Ok(Async::NotReady) => break,
+                }
+            }
+        }
+        if updated {
+            if self.seed.is_some() && self.timer.is_none() {
+                // restart, so grace timer is started
+                return TaskResult::Restart;
+            }
+            if !self.send_current() {
+                return TaskResult::Stop;
+            }
+        }
+        match self.tx.poll_cancel() {
+            Ok(Async::NotReady) => {}
+            _ => return TaskResult::Stop,
+        }
+        TaskResult::Continue
+    }
+}
@@ -0,0 +1,209 @@
+//! A wrapper around `async_slot` that counts dropped updates
+//!
+//! Slot keeps only the latest value, so when the application is slower
+//! than updates arrive, intermediate values are replaced (dropped). Senders
+//! created by `Backpressure::channel` count such values.
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use abstract_ns::Error;
+use async_slot;
+use futures::{Async, Poll, Stream};
+use futures::task::{self, Task};
+
+use internal::clone_error;
+use metrics::{SharedMetrics, Guard};
+
+pub(crate) use async_slot::SendError;
+
+
+/// A snapshot of the updates dropped because of slow consumers
+///
+/// Fetch it with `Router::backpressure_stats`. Only subscriptions made by
+/// the router (i.e. `subscribe`, `subscribe_many`) are tracked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackpressureStats {
+    /// Total number of updates dropped since router was created
+    ///
+    /// This includes updates dropped by subscriptions that are already
+    /// closed.
+    pub dropped_updates: u64,
+    /// Number of active subscriptions that dropped at least one update
+    pub lagging_subscriptions: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct Backpressure {
+    total: Arc<AtomicUsize>,
+    subscriptions: Mutex<Vec<Weak<Counter>>>,
+    metrics: SharedMetrics,
+}
+
+#[derive(Debug)]
+struct Counter {
+    dropped: AtomicUsize,
+    total: Arc<AtomicUsize>,
+}
+
+pub(crate) struct Sender<T> {
+    inner: async_slot::Sender<T>,
+    cancel: Arc<Mutex<Cancel>>,
+    counter: Option<Arc<Counter>>,
+    #[allow(dead_code)]  // guard is never read, only dropped
+    guard: Option<Guard>,
+    /// Receives errors of the subscription, see `report_error`
+    errors: Option<Arc<Sender<Error>>>,
+}
+
+/// Receiving half of the channel
+///
+/// Unlike `async_slot::Receiver` it wakes up the task of the sender when
+/// dropped, so the task feeding a subscription stops as soon as the
+/// subscription is dropped rather than on its next update.
+pub(crate) struct Receiver<T> {
+    inner: async_slot::Receiver<T>,
+    cancel: Arc<Mutex<Cancel>>,
+}
+
+#[derive(Default)]
+struct Cancel {
+    dropped: bool,
+    task: Option<Task>,
+}
+
+/// Create a channel which doesn't count dropped values
+pub(crate) fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = async_slot::channel();
+    let cancel = Arc::new(Mutex::new(Cancel::default()));
+    let tx = Sender {
+        inner: tx,
+        cancel: cancel.clone(),
+        counter: None,
+        guard: None,
+        errors: None,
+    };
+    (tx, Receiver { inner: rx, cancel })
+}
+
+impl Backpressure {
+    pub fn new(metrics: SharedMetrics) -> Backpressure {
+        Backpressure {
+            total: Arc::new(AtomicUsize::new(0)),
+            subscriptions: Mutex::new(Vec::new()),
+            metrics,
+        }
+    }
+    /// Create a channel which is accounted in stats (and in metrics)
+    pub fn channel<T>(&self) -> (Sender<T>, Receiver<T>) {
+        let counter = Arc::new(Counter {
+            dropped: AtomicUsize::new(0),
+            total: self.total.clone(),
+        });
+        let mut subs = self.subscriptions.lock()
+            .expect("backpressure lock is not poisoned");
+        subs.retain(|c| c.upgrade().is_some());
+        subs.push(Arc::downgrade(&counter));
+        let guard = self.metrics.read()
+            .expect("metrics lock is not poisoned")
+            .as_ref().map(Guard::new);
+        let (mut tx, rx) = channel();
+        tx.counter = Some(counter);
+        tx.guard = guard;
+        (tx, rx)
+    }
+    /// Same as `channel` but also returns a receiver of the errors
+    ///
+    /// Errors of the subscription are reported by `Sender::report_error`
+    pub fn channel_with_errors<T>(&self)
+        -> (Sender<T>, Receiver<T>, Receiver<Error>)
+    {
+        let (mut tx, rx) = self.channel();
+        let (etx, erx) = channel();
+        tx.errors = Some(Arc::new(etx));
+        (tx, rx, erx)
+    }
+    pub fn stats(&self) -> BackpressureStats {
+        let mut subs = self.subscriptions.lock()
+            .expect("backpressure lock is not poisoned");
+        subs.retain(|c| c.upgrade().is_some());
+        BackpressureStats {
+            dropped_updates: self.total.load(Ordering::Relaxed) as u64,
+            lagging_subscriptions: subs.iter()
+                .filter_map(|c| c.upgrade())
+                .filter(|c| c.dropped.load(Ordering::Relaxed) > 0)
+                .count(),
+        }
+    }
+}
+
+impl<T> Sender<T> {
+    pub fn swap(&self, value: T) -> Result<Option<T>, SendError<T>> {
+        let result = self.inner.swap(value);
+        if let (Ok(Some(_)), Some(counter)) = (&result, &self.counter) {
+            counter.dropped.fetch_add(1, Ordering::Relaxed);
+            counter.total.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+    pub fn poll_cancel(&mut self) -> Poll<(), ()> {
+        let mut cancel = self.cancel.lock()
+            .expect("cancel lock is not poisoned");
+        if cancel.dropped {
+            return Ok(Async::Ready(()));
+        }
+        cancel.task = Some(task::current());
+        Ok(Async::NotReady)
+    }
+    /// Sends the error to the error receiver if there is one
+    ///
+    /// Only the latest error is kept if receiver is not fast enough.
+    pub fn report_error(&self, error: &Error) {
+        if let Some(ref errors) = self.errors {
+            errors.swap(clone_error(error)).ok();
+        }
+    }
+    /// Makes errors of this sender reported to the receiver of `other`
+    ///
+    /// This is used for internal channels that feed the channel of the
+    /// application (i.e. when address rewriter is applied).
+    pub fn inherit_errors<U>(&mut self, other: &Sender<U>) {
+        self.errors = other.errors.clone();
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sender")
+        .field("counted", &self.counter.is_some())
+        .finish()
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+    type Error = ();  // actually void
+    fn poll(&mut self) -> Poll<Option<T>, ()> {
+        self.inner.poll()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let task = {
+            let mut cancel = self.cancel.lock()
+                .expect("cancel lock is not poisoned");
+            cancel.dropped = true;
+            cancel.task.take()
+        };
+        if let Some(task) = task {
+            task.notify();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Receiver")
+    }
+}
@@ -0,0 +1,131 @@
+//! Declarative config which can be deserialized from a file
+//!
+//! Enabled by `serde` feature.
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use abstract_ns::{HostResolve, Resolve, HostSubscribe, Subscribe};
+use abstract_ns::name::{self, Name};
+use humantime;
+use serde::{Deserialize, Deserializer};
+use serde::de::Error as DeError;
+
+use config::Config;
+
+
+quick_error! {
+    /// Error building config from `ConfigSpec`
+    #[derive(Debug)]
+    pub enum SpecError {
+        /// Name of a static host or service is invalid
+        Name(name: String, err: name::Error) {
+            display("bad name {:?}: {}", name, err)
+            cause(err)
+        }
+        /// Resolver factory returned `None` for the resolver name
+        UnknownResolver(name: String) {
+            display("unknown resolver {:?}", name)
+        }
+    }
+}
+
+/// Static parts of the config that can be stored in a file
+///
+/// Resolvers can't be deserialized, so suffixes and fallthrough refer to
+/// resolvers by name, which is converted into an actual resolver by
+/// `Config::from_spec`. Durations are in humantime format, i.e. `100ms`
+/// or `1m 30s`.
+///
+/// ```yaml
+/// hosts:
+///   localhost: [127.0.0.1, "::1"]
+/// services:
+///   _http._tcp.localhost: [127.0.0.1:80]
+/// suffixes:
+///   consul: consul
+/// fallthrough: dns
+/// restart_delay: 100ms
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigSpec {
+    /// Static hosts, see `Config::add_host`
+    #[serde(default)]
+    pub hosts: HashMap<String, Vec<IpAddr>>,
+    /// Static services, see `Config::add_service`
+    #[serde(default)]
+    pub services: HashMap<String, Vec<SocketAddr>>,
+    /// Mapping of suffix to the resolver name, see `Config::add_suffix`
+    #[serde(default)]
+    pub suffixes: HashMap<String, String>,
+    /// Resolver name for the names matching no suffix
+    #[serde(default)]
+    pub fallthrough: Option<String>,
+    /// See `Config::restart_delay`
+    #[serde(default, deserialize_with="duration")]
+    pub restart_delay: Option<Duration>,
+    /// See `Config::convergence_delay`
+    #[serde(default, deserialize_with="duration")]
+    pub convergence_delay: Option<Duration>,
+}
+
+fn duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => humantime::parse_duration(&value)
+            .map(Some)
+            .map_err(|e| D::Error::custom(
+                format!("bad duration {:?}: {}", value, e))),
+        None => Ok(None),
+    }
+}
+
+fn parse_name(name: &str) -> Result<Name, SpecError> {
+    name.parse().map_err(|e| SpecError::Name(name.to_string(), e))
+}
+
+impl Config {
+    /// Create a config from the spec (i.e. deserialized from a file)
+    ///
+    /// `resolver_factory` is called with the resolver name for every
+    /// suffix and for the fallthrough resolver, and returns `None` for
+    /// unknown names. To use resolvers of different types, wrap them in
+    /// an enum implementing resolver traits.
+    ///
+    /// Settings that aren't part of the spec can be changed on the
+    /// returned config as usual.
+    pub fn from_spec<R, F>(spec: &ConfigSpec, mut resolver_factory: F)
+        -> Result<Config, SpecError>
+        where F: FnMut(&str) -> Option<R>,
+              R: Resolve + HostResolve + Subscribe + HostSubscribe,
+              R: Debug + 'static,
+    {
+        let mut cfg = Config::new();
+        for (name, ips) in &spec.hosts {
+            cfg.add_host(&parse_name(name)?, ips.clone());
+        }
+        for (name, addrs) in &spec.services {
+            cfg.add_service(&parse_name(name)?, addrs[..].into());
+        }
+        for (suffix, resolver) in &spec.suffixes {
+            let resolver = resolver_factory(resolver)
+                .ok_or_else(|| SpecError::UnknownResolver(resolver.clone()))?;
+            cfg.add_suffix(suffix.clone(), resolver);
+        }
+        if let Some(ref resolver) = spec.fallthrough {
+            let resolver = resolver_factory(resolver)
+                .ok_or_else(|| SpecError::UnknownResolver(resolver.clone()))?;
+            cfg.set_fallthrough(resolver);
+        }
+        if let Some(delay) = spec.restart_delay {
+            cfg.restart_delay(delay);
+        }
+        if let Some(delay) = spec.convergence_delay {
+            cfg.convergence_delay(delay);
+        }
+        Ok(cfg)
+    }
+}
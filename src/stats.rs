@@ -0,0 +1,55 @@
+//! Counts of work held by the router
+//!
+//! See `Router::stats`.
+use std::cell::Cell;
+use std::rc::Rc;
+
+use futures::{Future, Poll};
+
+
+/// A snapshot of the work currently held by the router
+///
+/// Fetch it with `Router::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouterStats {
+    /// Number of in-flight one-shot resolutions
+    ///
+    /// This includes internal steps of a resolution, so a single request
+    /// (i.e. to a chain of resolvers) may be counted more than once.
+    pub resolves: usize,
+    /// Number of active subscription tasks
+    ///
+    /// A single subscription may consist of several tasks, for example
+    /// `subscribe_many` has a task for each name in the list. Tasks
+    /// waiting for a restart (after the underlying stream is closed) are
+    /// counted too.
+    pub subscriptions: usize,
+}
+
+/// Keeps the counter incremented while the future is alive
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Counted<F> {
+    future: F,
+    counter: Rc<Cell<usize>>,
+}
+
+impl<F> Counted<F> {
+    pub fn new(future: F, counter: &Rc<Cell<usize>>) -> Counted<F> {
+        counter.set(counter.get() + 1);
+        Counted { future, counter: counter.clone() }
+    }
+}
+
+impl<F: Future> Future for Counted<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        self.future.poll()
+    }
+}
+
+impl<F> Drop for Counted<F> {
+    fn drop(&mut self) {
+        self.counter.set(self.counter.get() - 1);
+    }
+}
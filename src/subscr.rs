@@ -1,22 +1,25 @@
 use std::fmt;
 use std::sync::Arc;
 
-use abstract_ns::{Name, Address, IpList, Error};
-use async_slot as slot;
+use abstract_ns::{Name, Address, IpList, Error, Subscribe};
+use slot;
 use futures::{Future, Stream, Async};
 use futures::sync::oneshot;
 use futures::future::Shared;
 use void::Void;
 
+use eq::{Dedup, SetEq};
 use fuse::Fuse;
+use mapped::Unmap;
 use internal_traits::Resolver;
-use config::Config;
-use coroutine::{ResolverFuture, FutureResult, Continuation, get_suffix};
+use config::{Config, AddressRewriter};
+use coroutine::{ResolverFuture, FutureResult, Continuation, Update};
+use coroutine::{get_suffix, static_host};
 
 
 #[must_use = "futures do nothing unless polled"]
 pub(crate) struct SubscrFuture<F: Task> {
-    pub update_rx: Shared<oneshot::Receiver<()>>,
+    pub update_rx: Shared<oneshot::Receiver<Update>>,
     pub task: Option<F>,
 }
 
@@ -31,6 +34,10 @@ pub(crate) enum TaskResult {
 pub(crate) trait Task {
     fn poll(&mut self) -> TaskResult;
     fn restart(self, res: &mut ResolverFuture, cfg: &Arc<Config>);
+    /// Called before restart when all subscriptions need to be refreshed
+    fn refresh(&mut self) {}
+    /// Name which is used to find per-suffix options
+    fn name(&self) -> Option<&Name> { None }
 }
 
 pub(crate) struct Subscr<S: Stream<Item=Address>> {
@@ -38,6 +45,8 @@ pub(crate) struct Subscr<S: Stream<Item=Address>> {
     pub subscriber: Arc<Resolver>,
     pub source: Fuse<S>,
     pub tx: slot::Sender<Address>,
+    pub dedup: Dedup<Address>,
+    pub unmap: bool,
 }
 
 pub(crate) struct HostSubscr<S: Stream<Item=IpList>> {
@@ -45,16 +54,47 @@ pub(crate) struct HostSubscr<S: Stream<Item=IpList>> {
     pub subscriber: Arc<Resolver>,
     pub source: Fuse<S>,
     pub tx: slot::Sender<IpList>,
+    pub dedup: Dedup<IpList>,
+    pub unmap: bool,
+}
+
+pub(crate) struct PinnedSubscr<R: Subscribe> {
+    pub name: Name,
+    pub resolver: R,
+    pub source: Fuse<R::Stream>,
+    pub tx: slot::Sender<Address>,
+    pub dedup: Dedup<Address>,
+    pub unmap: bool,
+}
+
+pub(crate) struct SubscribeWith<R> {
+    pub name: Name,
+    pub resolver: R,
+    pub tx: slot::Sender<Address>,
 }
 
 pub(crate) struct HostNoOpSubscr {
     pub name: Name,
     pub tx: slot::Sender<IpList>,
+    /// A static value sent to the subscriber if any
+    pub value: Option<IpList>,
 }
 
 pub(crate) struct NoOpSubscr {
     pub name: Name,
     pub tx: slot::Sender<Address>,
+    /// A static value sent to the subscriber if any
+    pub value: Option<Address>,
+}
+
+/// Applies `Config::set_address_rewriter` to the subscription
+pub(crate) struct RewriteSubscr {
+    name: Name,
+    source: slot::Receiver<Address>,
+    tx: slot::Sender<Address>,
+    rewriter: Option<AddressRewriter>,
+    /// Last value before rewriting
+    last: Option<Address>,
 }
 
 pub(crate) struct Wrapper<T: Task>(Option<T>);
@@ -83,6 +123,9 @@ impl<T: Task> Continuation for Wrapper<T> {
         self.0.take().expect("continuation called twice")
             .restart(res, cfg)
     }
+    fn name(&self) -> Option<&Name> {
+        self.0.as_ref().and_then(|task| task.name())
+    }
 }
 
 impl<F: Task + 'static> Future for SubscrFuture<F> {
@@ -90,7 +133,21 @@ impl<F: Task + 'static> Future for SubscrFuture<F> {
     type Error = Void;
     fn poll(&mut self) -> Result<Async<FutureResult>, Void> {
         match self.update_rx.poll() {
-            Ok(Async::Ready(_)) | Err(_) => {
+            Ok(Async::Ready(kind)) => {
+                let mut task = self.task.take().expect("future polled twice");
+                match *kind {
+                    // dropping the task closes the subscription
+                    Update::Shutdown => {
+                        return Ok(Async::Ready(FutureResult::Done));
+                    }
+                    Update::Refresh => task.refresh(),
+                    Update::Config => {}
+                }
+                return Ok(Async::Ready(FutureResult::Restart {
+                    task: Wrapper::wrap(task),
+                }));
+            }
+            Err(_) => {
                 return Ok(Async::Ready(FutureResult::Restart {
                     task: Wrapper::wrap(
                         self.task.take().expect("future polled twice")),
@@ -122,96 +179,213 @@ impl<F: Task + 'static> Future for SubscrFuture<F> {
 impl<S: Stream<Item=Address> + 'static> Task for Subscr<S>
     where S::Error: Into<Error>,
 {
-    fn restart(self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
-        if let Some(value) =  cfg.services.get(&self.name) {
+    fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        if let Some(value) =  cfg.service(&self.name) {
             let ok = self.tx.swap(value.clone()).is_ok();
             if ok {
                 SubscrFuture::spawn_in(res,
-                    NoOpSubscr { name: self.name, tx: self.tx });
+                    NoOpSubscr {
+                        name: self.name, tx: self.tx,
+                        value: Some(value),
+                    });
             }
             return;
         }
-        let nsub = get_suffix(cfg, self.name.as_ref());
+        let nsub = get_suffix(cfg, &self.name);
         if !Arc::ptr_eq(nsub, &self.subscriber) || self.source.is_done() {
             nsub.subscribe(res, nsub, cfg, self.name, self.tx);
         } else {
+            self.dedup.set_mode(cfg.address_eq);
+            self.unmap = cfg.normalize_mapped_v4;
             SubscrFuture::spawn_in(res, self)
         }
     }
     fn poll(&mut self) -> TaskResult {
-        loop {
-            match self.source.poll() {
-                Ok(Async::Ready(Some(x))) => {
-                    if self.tx.swap(x).is_err() {
-                        return TaskResult::Stop;
-                    }
-                }
-                Ok(Async::Ready(None))  => {
-                    error!("End of stream while following {:?}", self.name);
-                    return TaskResult::DelayRestart;
-                }
-                Err(e) => {
-                    error!("Error while following {:?}: {}", self.name,
-                        Into::<Error>::into(e));
-                    return TaskResult::DelayRestart;
-                }
-                Ok(Async::NotReady) => break,
-            }
-        }
-        match self.tx.poll_cancel() {
-            Ok(Async::NotReady) => {}
-            _ => {
-                return TaskResult::Stop;
-            }
-        }
-        TaskResult::Continue
+        poll_source(&self.name, &mut self.source, &mut self.tx,
+            &mut self.dedup, self.unmap)
+    }
+    fn refresh(&mut self) {
+        self.source.close();
+    }
+    fn name(&self) -> Option<&Name> {
+        Some(&self.name)
     }
 }
 
 impl<S: Stream<Item=IpList> + 'static> Task for HostSubscr<S>
     where S::Error: Into<Error>,
 {
-    fn restart(self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+    fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
         if let Some(value) =  cfg.hosts.get(&self.name) {
             let ok = self.tx.swap(value.clone()).is_ok();
             if ok {
                 SubscrFuture::spawn_in(res,
-                    HostNoOpSubscr { name: self.name, tx: self.tx });
+                    HostNoOpSubscr {
+                        name: self.name, tx: self.tx,
+                        value: Some(value.clone()),
+                    });
             }
             return;
         }
-        let ref nsub = get_suffix(cfg, self.name.as_ref());
+        let ref nsub = get_suffix(cfg, &self.name);
         if !Arc::ptr_eq(nsub, &self.subscriber) || self.source.is_done() {
             nsub.host_subscribe(res, nsub, cfg, self.name, self.tx);
         } else {
+            self.dedup.set_mode(cfg.address_eq);
+            self.unmap = cfg.normalize_mapped_v4;
             SubscrFuture::spawn_in(res, self)
         }
     }
+    fn poll(&mut self) -> TaskResult {
+        poll_source(&self.name, &mut self.source, &mut self.tx,
+            &mut self.dedup, self.unmap)
+    }
+    fn refresh(&mut self) {
+        self.source.close();
+    }
+    fn name(&self) -> Option<&Name> {
+        Some(&self.name)
+    }
+}
+
+impl<R> Task for PinnedSubscr<R>
+    where R: Subscribe + 'static,
+{
+    fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        // pinned subscriptions bypass static and suffix dispatch, so
+        // resubscribe only if the source is finished
+        if self.source.is_done() {
+            self.source = Fuse::new(self.resolver.subscribe(&self.name));
+        }
+        self.dedup.set_mode(cfg.address_eq);
+        self.unmap = cfg.normalize_mapped_v4;
+        SubscrFuture::spawn_in(res, self)
+    }
+    fn poll(&mut self) -> TaskResult {
+        poll_source(&self.name, &mut self.source, &mut self.tx,
+            &mut self.dedup, self.unmap)
+    }
+    fn refresh(&mut self) {
+        self.source.close();
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for SubscribeWith<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SubscribeWith")
+        .field("name", &self.name)
+        .field("resolver", &self.resolver)
+        .finish()
+    }
+}
+
+impl<R> Continuation for Option<SubscribeWith<R>>
+    where R: Subscribe + fmt::Debug + 'static,
+{
+    fn restart(&mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        let SubscribeWith { name, resolver, tx } = self.take()
+            .expect("continuation called twice");
+        let source = Fuse::new(resolver.subscribe(&name));
+        SubscrFuture::spawn_in(res, PinnedSubscr {
+            name, resolver, source, tx,
+            dedup: Dedup::new(cfg.address_eq),
+            unmap: cfg.normalize_mapped_v4,
+        });
+    }
+}
+
+fn poll_source<S, T>(name: &Name, source: &mut Fuse<S>,
+    tx: &mut slot::Sender<T>, dedup: &mut Dedup<T>, unmap: bool)
+    -> TaskResult
+    where S: Stream<Item=T>,
+          S::Error: Into<Error>,
+          T: PartialEq + SetEq + Unmap + Clone,
+{
+    loop {
+        match source.poll() {
+            Ok(Async::Ready(Some(x))) => {
+                let x = x.unmap_if(unmap);
+                if !dedup.is_new(&x) {
+                    continue;
+                }
+                if tx.swap(x).is_err() {
+                    return TaskResult::Stop;
+                }
+            }
+            Ok(Async::Ready(None))  => {
+                error!("End of stream while following {:?}", name);
+                return TaskResult::DelayRestart;
+            }
+            Err(e) => {
+                let e = Into::<Error>::into(e);
+                error!("Error while following {:?}: {}", name, e);
+                tx.report_error(&e);
+                return TaskResult::DelayRestart;
+            }
+            Ok(Async::NotReady) => break,
+        }
+    }
+    match tx.poll_cancel() {
+        Ok(Async::NotReady) => {}
+        _ => {
+            return TaskResult::Stop;
+        }
+    }
+    TaskResult::Continue
+}
+
+impl RewriteSubscr {
+    pub fn new(cfg: &Arc<Config>, name: Name,
+        source: slot::Receiver<Address>, tx: slot::Sender<Address>)
+        -> RewriteSubscr
+    {
+        RewriteSubscr {
+            name, source, tx,
+            rewriter: cfg.rewriter.clone(),
+            last: None,
+        }
+    }
+    fn send(&mut self, value: Address) -> bool {
+        let value = match self.rewriter {
+            Some(ref rewriter) => rewriter.rewrite(value),
+            None => value,
+        };
+        self.tx.swap(value).is_ok()
+    }
+}
+
+impl Task for RewriteSubscr {
+    fn restart(mut self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        if !AddressRewriter::same(&self.rewriter, &cfg.rewriter) {
+            self.rewriter = cfg.rewriter.clone();
+            if let Some(value) = self.last.clone() {
+                if !self.send(value) {
+                    return;
+                }
+            }
+        }
+        SubscrFuture::spawn_in(res, self)
+    }
     fn poll(&mut self) -> TaskResult {
         loop {
             match self.source.poll() {
+                Err(()) => unreachable!(), // slot doesn't fail
                 Ok(Async::Ready(Some(x))) => {
-                    if self.tx.swap(x).is_err() {
+                    self.last = Some(x.clone());
+                    if !self.send(x) {
                         return TaskResult::Stop;
                     }
                 }
-                Ok(Async::Ready(None))  => {
-                    error!("End of stream while following {:?}", self.name);
-                    return TaskResult::DelayRestart;
-                }
-                Err(e) => {
-                    error!("Error while following {:?}: {}", self.name,
-                        Into::<Error>::into(e));
-                    return TaskResult::DelayRestart;
+                Ok(Async::Ready(None)) => {
+                    debug!("Subscription for {:?} is closed", self.name);
+                    return TaskResult::Stop;
                 }
                 Ok(Async::NotReady) => break,
             }
         }
         match self.tx.poll_cancel() {
             Ok(Async::NotReady) => {}
-            _ => {
-                return TaskResult::Stop;
-            }
+            _ => return TaskResult::Stop,
         }
         TaskResult::Continue
     }
@@ -219,30 +393,51 @@ impl<S: Stream<Item=IpList> + 'static> Task for HostSubscr<S>
 
 impl Task for HostNoOpSubscr {
     fn restart(self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        if self.value.is_some() &&
+            static_host(cfg, &self.name) == self.value
+        {
+            // static value (or literal IP) is unchanged, don't send
+            // a duplicate
+            SubscrFuture::spawn_in(res, self);
+            return;
+        }
         // it's cheap to just resolve it again
         res.host_subscribe(cfg, self.name, self.tx);
     }
     fn poll(&mut self) -> TaskResult {
-        // do nothing until config changes
-        TaskResult::Continue
+        // do nothing until config changes or subscriber is gone
+        match self.tx.poll_cancel() {
+            Ok(Async::NotReady) => TaskResult::Continue,
+            _ => TaskResult::Stop,
+        }
     }
 }
 
 impl Task for NoOpSubscr {
     fn restart(self, res: &mut ResolverFuture, cfg: &Arc<Config>) {
+        if self.value.is_some() &&
+            cfg.service(&self.name).as_ref() == self.value.as_ref()
+        {
+            // static value is unchanged, don't send a duplicate
+            SubscrFuture::spawn_in(res, self);
+            return;
+        }
         // it's cheap to just resolve it again
         res.subscribe(cfg, self.name, self.tx);
     }
     fn poll(&mut self) -> TaskResult {
-        // do nothing until config changes
-        TaskResult::Continue
+        // do nothing until config changes or subscriber is gone
+        match self.tx.poll_cancel() {
+            Ok(Async::NotReady) => TaskResult::Continue,
+            _ => TaskResult::Stop,
+        }
     }
 }
 
 impl<T: Task + 'static> SubscrFuture<T> {
     pub fn spawn_in(r: &mut ResolverFuture, task: T) {
         let update_rx = r.update_rx();
-        r.spawn(SubscrFuture {
+        r.spawn_subscription(SubscrFuture {
             update_rx,
             task: Some(task),
         });
@@ -1,12 +1,20 @@
 //! An extension trait that turns resolvers into subscribers
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::RandomState;
 use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use abstract_ns::{Resolve, HostResolve, Subscribe, HostSubscribe, Name};
 use abstract_ns::{Address, IpList};
-use futures::{Future, Stream, Async};
-use tokio_core::reactor::{Handle, Timeout};
+use futures::{Future, IntoFuture, Stream, Async};
+use futures::task::{self, Task};
+
+use health::HealthCheckedSubscriber;
+use timer::{Timer, Sleep, Delay};
 
 
 /// A subscriber which polls resolver at a regular interval
@@ -15,19 +23,60 @@ use tokio_core::reactor::{Handle, Timeout};
 #[derive(Debug)]
 pub struct IntervalSubscriber<R>(Rc<Internal<R>>);
 
+/// A subscriber which polls resolver when the last value expires
+///
+/// Create the instance with `SubscribeExt::ttl_subscriber`
+#[derive(Debug)]
+pub struct TtlSubscriber<R>(IntervalSubscriber<R>);
+
+/// A resolver which knows time to live of the values it returns
+///
+/// Implement this for the resolver to use it with
+/// `SubscribeExt::ttl_subscriber`. Both methods return `None` by default,
+/// meaning that TTL is unknown.
+pub trait MinTtl {
+    /// Returns the shortest TTL of records the `addr` is resolved from
+    fn min_ttl(&self, name: &Name, addr: &Address) -> Option<Duration> {
+        let _ = (name, addr);
+        None
+    }
+    /// Returns the shortest TTL of records the `list` is resolved from
+    fn min_host_ttl(&self, name: &Name, list: &IpList) -> Option<Duration> {
+        let _ = (name, list);
+        None
+    }
+}
+
 #[derive(Debug)]
 struct Internal<R> {
     resolver: R,
     interval: Duration,
-    handle: Handle,
+    jitter: Option<Jitter>,
+    ttl: Option<TtlBounds<R>>,
+    timer: Arc<Timer>,
+}
+
+struct TtlBounds<R> {
+    min: Duration,
+    max: Duration,
+    addr: fn(&R, &Name, &Address) -> Option<Duration>,
+    host: fn(&R, &Name, &IpList) -> Option<Duration>,
+}
+
+struct Jitter {
+    fraction: f64,
+    /// Returns a random number in range `[0, 1)`
+    random: RefCell<Box<FnMut() -> f64>>,
 }
 
 enum State<F> {
-    Sleeping(Timeout),
+    Sleeping(Sleep),
     Waiting(F),
+    Stopped,
 }
 
-/// A stream returned by IntervalSubscriber::subscribe
+/// A stream returned by IntervalSubscriber::subscribe and
+/// TtlSubscriber::subscribe
 pub struct IntervalResolver<R: Resolve> {
     internal: Rc<Internal<R>>,
     name: Name,
@@ -35,7 +84,8 @@ pub struct IntervalResolver<R: Resolve> {
     state: State<R::Future>,
 }
 
-/// A stream returned by IntervalSubscriber::subscribe_host
+/// A stream returned by IntervalSubscriber::subscribe_host and
+/// TtlSubscriber::subscribe_host
 pub struct IntervalHostResolver<R: HostResolve> {
     internal: Rc<Internal<R>>,
     name: Name,
@@ -43,29 +93,551 @@ pub struct IntervalHostResolver<R: HostResolve> {
     state: State<R::HostFuture>,
 }
 
+/// A subscriber which signals when values become stale
+///
+/// Create the instance with `SubscribeExt::max_staleness`
+#[derive(Debug)]
+pub struct MaxStaleness<R> {
+    subscriber: R,
+    max: Duration,
+    timer: Arc<Timer>,
+}
+
+/// A stream returned by `MaxStaleness::subscribe` and `subscribe_host`
+#[derive(Debug)]
+pub struct StaleStream<S: Stream> {
+    stream: S,
+    max: Duration,
+    timer: Arc<Timer>,
+    delay: Option<Delay>,
+    empty: fn() -> S::Item,
+}
+
+/// A resolver which dispatches queries across a pool of resolvers
+///
+/// Create the instance with `SubscribeExt::pooled`
+#[derive(Debug)]
+pub struct PooledResolver<R>(Rc<Pool<R>>);
+
+#[derive(Debug)]
+struct Pool<R> {
+    members: Vec<Member<R>>,
+    max_in_flight: Cell<usize>,
+    waiters: RefCell<Vec<Task>>,
+}
+
+#[derive(Debug)]
+struct Member<R> {
+    resolver: R,
+    in_flight: Cell<usize>,
+}
+
+enum PoolState<F> {
+    Queued,
+    Running(usize, F),
+    Done,
+}
+
+/// A future returned by `PooledResolver::resolve` and `resolve_host`
+pub struct PooledFuture<R, F> {
+    pool: Rc<Pool<R>>,
+    name: Name,
+    start: fn(&R, &Name) -> F,
+    state: PoolState<F>,
+}
+
 /// An extension trait for resolver
 ///
 pub trait SubscribeExt {
     /// Return a subscriber that uses `resolve` or `resolve_host` at a regular
     /// interval
-    fn interval_subscriber(self, interval: Duration, handle: &Handle)
+    fn interval_subscriber<C>(self, interval: Duration, timer: &C)
+        -> IntervalSubscriber<Self>
+        where Self: Sized, C: Timer + Clone;
+
+    /// Return a subscriber that uses `resolve` or `resolve_host` at a
+    /// randomized interval
+    ///
+    /// Each sleep between polls is picked randomly within
+    /// `interval ± interval * jitter_fraction`, so subscriptions created
+    /// at the same time don't query the resolver at the same time. Use
+    /// `IntervalSubscriber::random_source` to replace the random number
+    /// generator (i.e. for deterministic tests).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `jitter_fraction` is not within `[0, 1]`.
+    fn interval_subscriber_jitter<C>(self, interval: Duration,
+        jitter_fraction: f64, timer: &C)
         -> IntervalSubscriber<Self>
-        where Self: Sized;
+        where Self: Sized, C: Timer + Clone;
+
+    /// Return a subscriber that uses `resolve` or `resolve_host` when the
+    /// last value expires
+    ///
+    /// Next poll is scheduled after the TTL reported by `MinTtl` for the
+    /// last value, clamped to `[min, max]`. If TTL is unknown, resolver is
+    /// polled at `default` interval.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min` is larger than `max`.
+    fn ttl_subscriber<C>(self, default: Duration,
+        min: Duration, max: Duration, timer: &C)
+        -> TtlSubscriber<Self>
+        where Self: MinTtl + Sized, C: Timer + Clone;
+
+    /// Return a subscriber that enforces maximum age of a value
+    ///
+    /// If underlying subscription doesn't yield a new value in `max`
+    /// after the previous one, an empty address (or empty ip list) is
+    /// yielded to signal that the value is stale. Real values are yielded
+    /// as usual when they arrive.
+    ///
+    /// Note: the stream returned by subscriber isn't deduplicated, so
+    /// unchanged values refresh the value too.
+    fn max_staleness<C>(self, max: Duration, timer: &C)
+        -> MaxStaleness<Self>
+        where Self: Sized, C: Timer + Clone;
+
+    /// Return a subscriber that filters out addresses failing a health
+    /// check
+    ///
+    /// The `check` is called for every address of a subscription, and the
+    /// address is removed from the value if the returned future resolves
+    /// to `false` (or fails). Addresses are checked again every
+    /// `interval`. The first value is yielded when every address is
+    /// checked once, and after that the value is yielded only when the set
+    /// of healthy addresses changes.
+    ///
+    /// Use `HealthCheckedSubscriber::debounce` to tune how fast flapping
+    /// addresses are removed and added back, and
+    /// `HealthCheckedSubscriber::host_port` to check host subscriptions.
+    fn health_checked<F, R, C>(self, check: F, interval: Duration,
+        timer: &C)
+        -> HealthCheckedSubscriber<Self, F>
+        where F: Fn(SocketAddr) -> R,
+              R: IntoFuture<Item=bool>,
+              Self: Sized, C: Timer + Clone;
+
+    /// Return a resolver that dispatches queries across `size` resolvers
+    ///
+    /// Resolvers are created by calling `factory`. This is useful when a
+    /// single resolver instance (i.e. a connection to a DNS-over-HTTPS
+    /// server) is a bottleneck. Each query is sent to the resolver with the
+    /// least number of queries in flight. Use
+    /// `PooledResolver::max_in_flight` to limit the number of concurrent
+    /// queries per resolver, by default it's unlimited.
+    ///
+    /// Subscriptions are dispatched to the least loaded resolver too, but
+    /// they aren't counted as queries in flight.
+    fn pooled<F>(factory: F, size: usize) -> PooledResolver<Self>
+        where F: FnMut() -> Self,
+              Self: Sized;
 }
 
 impl<T: Resolve + HostResolve> SubscribeExt for T {
-    fn interval_subscriber(self, interval: Duration, handle: &Handle)
+    fn interval_subscriber<C>(self, interval: Duration, timer: &C)
+        -> IntervalSubscriber<Self>
+        where Self: Sized, C: Timer + Clone
+    {
+        IntervalSubscriber(Rc::new(Internal {
+            resolver: self,
+            interval,
+            jitter: None,
+            ttl: None,
+            timer: Arc::new(timer.clone()),
+        }))
+    }
+    fn ttl_subscriber<C>(self, default: Duration,
+        min: Duration, max: Duration, timer: &C)
+        -> TtlSubscriber<Self>
+        where Self: MinTtl + Sized, C: Timer + Clone
+    {
+        assert!(min <= max, "min TTL must not be larger than max TTL");
+        TtlSubscriber(IntervalSubscriber(Rc::new(Internal {
+            resolver: self,
+            interval: default,
+            jitter: None,
+            ttl: Some(TtlBounds {
+                min, max,
+                addr: Self::min_ttl,
+                host: Self::min_host_ttl,
+            }),
+            timer: Arc::new(timer.clone()),
+        })))
+    }
+    fn interval_subscriber_jitter<C>(self, interval: Duration,
+        jitter_fraction: f64, timer: &C)
         -> IntervalSubscriber<Self>
-        where Self: Sized
+        where Self: Sized, C: Timer + Clone
     {
+        assert!((0. ..=1.).contains(&jitter_fraction),
+            "jitter fraction must be within [0, 1]");
         IntervalSubscriber(Rc::new(Internal {
             resolver: self,
             interval,
-            handle: handle.clone(),
+            jitter: Some(Jitter {
+                fraction: jitter_fraction,
+                random: RefCell::new(xorshift()),
+            }),
+            ttl: None,
+            timer: Arc::new(timer.clone()),
+        }))
+    }
+    fn max_staleness<C>(self, max: Duration, timer: &C)
+        -> MaxStaleness<Self>
+        where Self: Sized, C: Timer + Clone
+    {
+        MaxStaleness {
+            subscriber: self,
+            max,
+            timer: Arc::new(timer.clone()),
+        }
+    }
+    fn health_checked<F, R, C>(self, check: F, interval: Duration,
+        timer: &C)
+        -> HealthCheckedSubscriber<Self, F>
+        where F: Fn(SocketAddr) -> R,
+              R: IntoFuture<Item=bool>,
+              Self: Sized, C: Timer + Clone
+    {
+        HealthCheckedSubscriber::new(self, check, interval,
+            Arc::new(timer.clone()))
+    }
+    fn pooled<F>(mut factory: F, size: usize) -> PooledResolver<Self>
+        where F: FnMut() -> Self,
+              Self: Sized
+    {
+        assert!(size > 0, "pool must contain at least one resolver");
+        PooledResolver(Rc::new(Pool {
+            members: (0..size).map(|_| Member {
+                resolver: factory(),
+                in_flight: Cell::new(0),
+            }).collect(),
+            max_in_flight: Cell::new(usize::MAX),
+            waiters: RefCell::new(Vec::new()),
         }))
     }
 }
 
+/// Returns a random number generator seeded from the hasher keys
+fn xorshift() -> Box<FnMut() -> f64> {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    // zero is a fixed point of xorshift
+    let mut state = hasher.finish() | 1;
+    Box::new(move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state >> 11) as f64 / (1u64 << 53) as f64
+    })
+}
+
+impl fmt::Debug for Jitter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Jitter")
+        .field("fraction", &self.fraction)
+        .finish()
+    }
+}
+
+impl<R> fmt::Debug for TtlBounds<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TtlBounds")
+        .field("min", &self.min)
+        .field("max", &self.max)
+        .finish()
+    }
+}
+
+impl<R> IntervalSubscriber<R> {
+    /// Replaces the random number generator used for jitter
+    ///
+    /// The function must return numbers in range `[0, 1)`. It has no
+    /// effect if the subscriber is created without jitter.
+    pub fn random_source<F>(self, f: F) -> Self
+        where F: FnMut() -> f64 + 'static,
+    {
+        if let Some(ref jitter) = self.0.jitter {
+            *jitter.random.borrow_mut() = Box::new(f);
+        }
+        self
+    }
+}
+
+impl<R> Internal<R> {
+    fn sleep_time(&self, ttl: Option<Duration>) -> Duration {
+        if let Some(ref bounds) = self.ttl {
+            let ttl = ttl.unwrap_or(self.interval);
+            return ttl.max(bounds.min).min(bounds.max);
+        }
+        let jitter = match self.jitter {
+            Some(ref jitter) => jitter,
+            None => return self.interval,
+        };
+        let random = (*jitter.random.borrow_mut())();
+        let factor = 1. + jitter.fraction * (2. * random - 1.);
+        let secs = (self.interval.as_secs() as f64 +
+            self.interval.subsec_nanos() as f64 * 1e-9) * factor;
+        Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+    }
+}
+
+fn empty_address() -> Address {
+    Address::from(&[][..])
+}
+
+fn empty_ip_list() -> IpList {
+    Vec::<IpAddr>::new().into()
+}
+
+impl<T: Resolve> Resolve for MaxStaleness<T> {
+    type Future = T::Future;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        self.subscriber.resolve(name)
+    }
+}
+
+impl<T: HostResolve> HostResolve for MaxStaleness<T> {
+    type HostFuture = T::HostFuture;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        self.subscriber.resolve_host(name)
+    }
+}
+
+impl<T: Subscribe> Subscribe for MaxStaleness<T> {
+    type Error = T::Error;
+    type Stream = StaleStream<T::Stream>;
+    fn subscribe(&self, name: &Name) -> Self::Stream {
+        StaleStream::new(self.subscriber.subscribe(name),
+            self.max, &self.timer, empty_address)
+    }
+}
+
+impl<T: HostSubscribe> HostSubscribe for MaxStaleness<T> {
+    type HostError = T::HostError;
+    type HostStream = StaleStream<T::HostStream>;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        StaleStream::new(self.subscriber.subscribe_host(name),
+            self.max, &self.timer, empty_ip_list)
+    }
+}
+
+impl<S: Stream> StaleStream<S> {
+    fn new(stream: S, max: Duration, timer: &Arc<Timer>,
+        empty: fn() -> S::Item)
+        -> StaleStream<S>
+    {
+        StaleStream {
+            stream, max, empty,
+            timer: timer.clone(),
+            delay: None,
+        }
+    }
+}
+
+impl<S: Stream> Stream for StaleStream<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> Result<Async<Option<S::Item>>, S::Error> {
+        match self.stream.poll()? {
+            Async::Ready(Some(value)) => {
+                self.delay = Some(Delay::new(&*self.timer, self.max));
+                return Ok(Async::Ready(Some(value)));
+            }
+            Async::Ready(None) => return Ok(Async::Ready(None)),
+            Async::NotReady => {}
+        }
+        let expired = match self.delay.as_mut().map(|t| t.poll()) {
+            Some(Ok(Async::Ready(()))) => true,
+            Some(Err(e)) => {
+                error!("Staleness timer failed: {}", e);
+                self.delay = None;
+                false
+            }
+            Some(Ok(Async::NotReady)) | None => false,
+        };
+        if expired {
+            self.delay = None;
+            return Ok(Async::Ready(Some((self.empty)())));
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<R> PooledResolver<R> {
+    /// Sets maximum number of queries in flight for each resolver
+    ///
+    /// When all resolvers of the pool have this number of queries in
+    /// flight, new queries wait until one of them is finished.
+    pub fn max_in_flight(self, limit: usize) -> Self {
+        assert!(limit > 0, "limit must be positive");
+        self.0.max_in_flight.set(limit);
+        self
+    }
+    fn least_loaded(&self) -> &R {
+        &self.0.members.iter()
+            .min_by_key(|m| m.in_flight.get())
+            .expect("pool is never empty")
+            .resolver
+    }
+    fn start<F>(&self, name: &Name, start: fn(&R, &Name) -> F)
+        -> PooledFuture<R, F>
+    {
+        let mut future = PooledFuture {
+            pool: self.0.clone(),
+            name: name.clone(),
+            start,
+            state: PoolState::Queued,
+        };
+        // start eagerly, so that queries created together are spread
+        // across the pool
+        future.try_start();
+        future
+    }
+}
+
+impl<R> Pool<R> {
+    fn acquire(&self) -> Option<usize> {
+        let limit = self.max_in_flight.get();
+        let (idx, member) = self.members.iter().enumerate()
+            .min_by_key(|&(_, m)| m.in_flight.get())
+            .expect("pool is never empty");
+        if member.in_flight.get() >= limit {
+            return None;
+        }
+        member.in_flight.set(member.in_flight.get() + 1);
+        Some(idx)
+    }
+    fn release(&self, idx: usize) {
+        let member = &self.members[idx];
+        member.in_flight.set(member.in_flight.get() - 1);
+        for waiter in self.waiters.borrow_mut().drain(..) {
+            waiter.notify();
+        }
+    }
+}
+
+impl<R, F> PooledFuture<R, F> {
+    fn try_start(&mut self) -> bool {
+        match self.pool.acquire() {
+            Some(idx) => {
+                let future = (self.start)(
+                    &self.pool.members[idx].resolver, &self.name);
+                self.state = PoolState::Running(idx, future);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<R, F: Future> Future for PooledFuture<R, F> {
+    type Item = F::Item;
+    type Error = F::Error;
+    fn poll(&mut self) -> Result<Async<F::Item>, F::Error> {
+        if let PoolState::Queued = self.state {
+            if !self.try_start() {
+                self.pool.waiters.borrow_mut().push(task::current());
+                return Ok(Async::NotReady);
+            }
+        }
+        let (idx, result) = match self.state {
+            PoolState::Running(idx, ref mut future) => match future.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                result => (idx, result),
+            },
+            PoolState::Queued => unreachable!(),
+            PoolState::Done => panic!("future polled after completion"),
+        };
+        self.state = PoolState::Done;
+        self.pool.release(idx);
+        result
+    }
+}
+
+impl<R, F> Drop for PooledFuture<R, F> {
+    fn drop(&mut self) {
+        if let PoolState::Running(idx, _) = self.state {
+            self.pool.release(idx);
+        }
+    }
+}
+
+impl<R, F> fmt::Debug for PooledFuture<R, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PooledFuture")
+        .field("name", &self.name)
+        .finish()
+    }
+}
+
+impl<T: Resolve> Resolve for PooledResolver<T> {
+    type Future = PooledFuture<T, T::Future>;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        self.start(name, T::resolve)
+    }
+}
+
+impl<T: HostResolve> HostResolve for PooledResolver<T> {
+    type HostFuture = PooledFuture<T, T::HostFuture>;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        self.start(name, T::resolve_host)
+    }
+}
+
+impl<T: Subscribe> Subscribe for PooledResolver<T> {
+    type Error = T::Error;
+    type Stream = T::Stream;
+    fn subscribe(&self, name: &Name) -> Self::Stream {
+        self.least_loaded().subscribe(name)
+    }
+}
+
+impl<T: HostSubscribe> HostSubscribe for PooledResolver<T> {
+    type HostError = T::HostError;
+    type HostStream = T::HostStream;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        self.least_loaded().subscribe_host(name)
+    }
+}
+
+fn sleep<R, F>(internal: &Internal<R>, ttl: Option<Duration>) -> State<F> {
+    State::Sleeping(internal.timer.sleep(internal.sleep_time(ttl)))
+}
+
+impl<T: Resolve> Resolve for TtlSubscriber<T> {
+    type Future = T::Future;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        self.0.resolve(name)
+    }
+}
+
+impl<T: HostResolve> HostResolve for TtlSubscriber<T> {
+    type HostFuture = T::HostFuture;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        self.0.resolve_host(name)
+    }
+}
+
+impl<T: Resolve> Subscribe for TtlSubscriber<T> {
+    type Error = <T::Future as Future>::Error;
+    type Stream = IntervalResolver<T>;
+    fn subscribe(&self, name: &Name) -> Self::Stream {
+        self.0.subscribe(name)
+    }
+}
+
+impl<T: HostResolve> HostSubscribe for TtlSubscriber<T> {
+    type HostError = <T::HostFuture as Future>::Error;
+    type HostStream = IntervalHostResolver<T>;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        self.0.subscribe_host(name)
+    }
+}
+
 impl<T: Resolve> Resolve for IntervalSubscriber<T> {
     type Future = T::Future;
     fn resolve(&self, name: &Name) -> Self::Future {
@@ -114,17 +686,30 @@ impl<R: HostResolve> Stream for IntervalHostResolver<R> {
         use self::State::*;
         loop {
             let mut updated = false;
+            let mut failed = false;
+            let mut ttl = None;
             match self.state {
                 Sleeping(ref mut timer) => {
-                    match timer.poll().expect("timer never fails") {
-                        Async::NotReady => return Ok(Async::NotReady),
-                        Async::Ready(()) => {}
+                    match timer.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(())) => {}
+                        Err(e) => {
+                            // this usually means reactor is shutting down
+                            error!("Timer for {:?} failed: {}, \
+                                stopping stream", self.name, e);
+                            failed = true;
+                        }
                     }
                 }
+                Stopped => return Ok(Async::Ready(None)),
                 Waiting(ref mut future) => {
                     match future.poll()? {
                         Async::NotReady => return Ok(Async::NotReady),
                         Async::Ready(a) => {
+                            ttl = self.internal.ttl.as_ref().and_then(|t| {
+                                (t.host)(&self.internal.resolver,
+                                         &self.name, &a)
+                            });
                             if self.last_value.as_ref() != Some(&a) {
                                 self.last_value = Some(a);
                                 updated = true;
@@ -134,15 +719,18 @@ impl<R: HostResolve> Stream for IntervalHostResolver<R> {
                 }
             }
             match &mut self.state {
+                state @ &mut Sleeping(..) if failed => {
+                    *state = Stopped;
+                    return Ok(Async::Ready(None));
+                }
                 state @ &mut Sleeping(..) => {
                     *state = Waiting(self.internal.resolver
                         .resolve_host(&self.name));
                 }
                 state @ &mut Waiting(..) => {
-                    *state = Sleeping(Timeout::new(
-                        self.internal.interval, &self.internal.handle)
-                        .expect("timeout never fails"));
+                    *state = sleep(&self.internal, ttl);
                 }
+                &mut Stopped => unreachable!(),
             }
             if updated {
                 return Ok(Async::Ready(self.last_value.clone()));
@@ -158,17 +746,30 @@ impl<R: Resolve> Stream for IntervalResolver<R> {
         use self::State::*;
         loop {
             let mut updated = false;
+            let mut failed = false;
+            let mut ttl = None;
             match self.state {
                 Sleeping(ref mut timer) => {
-                    match timer.poll().expect("timer never fails") {
-                        Async::NotReady => return Ok(Async::NotReady),
-                        Async::Ready(()) => {}
+                    match timer.poll() {
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Ok(Async::Ready(())) => {}
+                        Err(e) => {
+                            // this usually means reactor is shutting down
+                            error!("Timer for {:?} failed: {}, \
+                                stopping stream", self.name, e);
+                            failed = true;
+                        }
                     }
                 }
+                Stopped => return Ok(Async::Ready(None)),
                 Waiting(ref mut future) => {
                     match future.poll()? {
                         Async::NotReady => return Ok(Async::NotReady),
                         Async::Ready(a) => {
+                            ttl = self.internal.ttl.as_ref().and_then(|t| {
+                                (t.addr)(&self.internal.resolver,
+                                         &self.name, &a)
+                            });
                             if self.last_value.as_ref() != Some(&a) {
                                 self.last_value = Some(a);
                                 updated = true;
@@ -178,15 +779,18 @@ impl<R: Resolve> Stream for IntervalResolver<R> {
                 }
             }
             match &mut self.state {
+                state @ &mut Sleeping(..) if failed => {
+                    *state = Stopped;
+                    return Ok(Async::Ready(None));
+                }
                 state @ &mut Sleeping(..) => {
                     *state = Waiting(self.internal.resolver
                         .resolve(&self.name));
                 }
                 state @ &mut Waiting(..) => {
-                    *state = Sleeping(Timeout::new(
-                        self.internal.interval, &self.internal.handle)
-                        .expect("timeout never fails"));
+                    *state = sleep(&self.internal, ttl);
                 }
+                &mut Stopped => unreachable!(),
             }
             if updated {
                 return Ok(Async::Ready(self.last_value.clone()));
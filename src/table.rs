@@ -0,0 +1,81 @@
+//! Exported routing table
+//!
+//! See `Router::routing_table`.
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+
+use config::Config;
+
+
+/// A description of how names are routed by the current config
+///
+/// This is plain data, intended for documentation generation and audits.
+/// All maps are sorted by name, so output is stable between runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingTable {
+    /// Static hosts added by `Config::add_host`
+    pub hosts: BTreeMap<String, Vec<IpAddr>>,
+    /// Static services added by `Config::add_service`
+    ///
+    /// Each service is a list of address sets ordered by priority, weights
+    /// are not exported.
+    pub services: BTreeMap<String, Vec<Vec<SocketAddr>>>,
+    /// Suffixes and the `Debug` representation of their resolvers
+    pub suffixes: BTreeMap<String, SuffixEntry>,
+    /// Prefixes and the `Debug` representation of their resolvers
+    pub prefixes: BTreeMap<String, String>,
+    /// Names added by `Config::add_name` and their resolvers
+    pub names: BTreeMap<String, String>,
+    /// The `Debug` representation of the fallthrough resolver if any
+    pub fallthrough: Option<String>,
+}
+
+/// A suffix in the `RoutingTable`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuffixEntry {
+    /// The `Debug` representation of the resolver
+    pub resolver: String,
+    /// Whether suffix is disabled by `Router::disable_suffix`
+    pub disabled: bool,
+}
+
+impl RoutingTable {
+    pub(crate) fn from_config(cfg: &Config) -> RoutingTable {
+        RoutingTable {
+            hosts: cfg.hosts.iter()
+                .map(|(name, list)| {
+                    (name.as_ref().to_string(), list.iter().cloned().collect())
+                })
+                .collect(),
+            services: cfg.services.iter()
+                .map(|(name, addr)| {
+                    (name.as_ref().to_string(),
+                     addr.iter().map(|set| set.addresses().collect())
+                        .collect())
+                })
+                .collect(),
+            suffixes: cfg.suffixes.iter()
+                .map(|(suffix, suf)| {
+                    (suffix.clone(), SuffixEntry {
+                        resolver: suf.resolver.describe()
+                            .unwrap_or_else(|| String::from("none")),
+                        disabled: cfg.disabled_suffixes.contains(suffix),
+                    })
+                })
+                .collect(),
+            prefixes: cfg.prefixes.iter()
+                .map(|(prefix, pre)| {
+                    (prefix.clone(), pre.resolver.describe()
+                        .unwrap_or_else(|| String::from("none")))
+                })
+                .collect(),
+            names: cfg.names.iter()
+                .map(|(name, exact)| {
+                    (name.as_ref().to_string(), exact.resolver.describe()
+                        .unwrap_or_else(|| String::from("none")))
+                })
+                .collect(),
+            fallthrough: cfg.root.describe(),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Timers used by the router for delays and timeouts
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{Future, Async};
+use futures::future::err;
+use futures::task::{self, Task};
+use tokio_core::reactor::{Handle, Timeout};
+
+
+/// A boxed future returned by `Timer::sleep`
+pub type Sleep = Box<Future<Item=(), Error=io::Error> + Send>;
+
+/// A source of timers for the router
+///
+/// Timer is set with `Config::timer`. It's used for restart delays,
+/// convergence delays and seed grace periods. By default timers of the
+/// runtime that runs the router are used.
+///
+/// It's implemented for `tokio_core::reactor::Handle`, so a handle can be
+/// passed wherever a timer is expected.
+pub trait Timer: fmt::Debug + 'static {
+    /// Returns a future which resolves after `duration`
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// A timer which is advanced manually by `ManualTimer::advance`
+///
+/// This is useful for deterministic tests of delays. Clones of the timer
+/// share the same clock.
+#[derive(Debug, Clone, Default)]
+pub struct ManualTimer(Arc<Mutex<Clock>>);
+
+#[derive(Debug, Default)]
+struct Clock {
+    now: Duration,
+    waiters: Vec<Task>,
+}
+
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+struct ManualSleep {
+    deadline: Duration,
+    clock: Arc<Mutex<Clock>>,
+}
+
+/// A `Sleep` future which can be stored in types deriving `Debug`
+#[must_use = "futures do nothing unless polled"]
+pub(crate) struct Delay(Sleep);
+
+impl Timer for Handle {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        match Timeout::new(duration, self) {
+            Ok(timer) => Box::new(timer),
+            // error is reported on the first poll
+            Err(e) => Box::new(err(e)),
+        }
+    }
+}
+
+impl Delay {
+    pub fn new<T: Timer + ?Sized>(timer: &T, duration: Duration) -> Delay {
+        Delay(timer.sleep(duration))
+    }
+}
+
+impl Future for Delay {
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> Result<Async<()>, io::Error> {
+        self.0.poll()
+    }
+}
+
+impl fmt::Debug for Delay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Delay")
+    }
+}
+
+impl ManualTimer {
+    /// Creates a timer with clock at zero
+    pub fn new() -> ManualTimer {
+        ManualTimer::default()
+    }
+    /// Moves the clock forward, waking up futures that are expired
+    ///
+    /// Futures are woken up, but they are processed on the next
+    /// iteration of the event loop (e.g. `Core::turn`).
+    pub fn advance(&self, duration: Duration) {
+        let mut clock = self.0.lock().expect("clock lock is not poisoned");
+        clock.now += duration;
+        for task in clock.waiters.drain(..) {
+            task.notify();
+        }
+    }
+    /// Returns the total time the clock is advanced by
+    pub fn elapsed(&self) -> Duration {
+        self.0.lock().expect("clock lock is not poisoned").now
+    }
+}
+
+impl Timer for ManualTimer {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::new(ManualSleep {
+            deadline: self.elapsed() + duration,
+            clock: self.0.clone(),
+        })
+    }
+}
+
+impl Future for ManualSleep {
+    type Item = ();
+    type Error = io::Error;
+    fn poll(&mut self) -> Result<Async<()>, io::Error> {
+        let mut clock = self.clock.lock()
+            .expect("clock lock is not poisoned");
+        if clock.now >= self.deadline {
+            return Ok(Async::Ready(()));
+        }
+        clock.waiters.push(task::current());
+        Ok(Async::NotReady)
+    }
+}
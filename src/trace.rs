@@ -0,0 +1,26 @@
+/// The kind of the routing rule which is used to resolve a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Name is a static host or service added to the config
+    Static,
+    /// Name is routed by `Config::add_name`
+    Name,
+    /// Name is routed by `Config::add_prefix` with this prefix
+    Prefix(String),
+    /// Name is routed by `Config::add_suffix` with this suffix
+    Suffix(String),
+    /// No rule matches, name is resolved by the fallthrough resolver
+    Fallthrough,
+}
+
+/// Describes how the name was resolved by `Router::resolve_traced`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    /// The routing rule which matched the name
+    pub route: Route,
+    /// `Debug` representation of the resolver that produced the answer
+    ///
+    /// This is `None` for static names and when no fallthrough resolver
+    /// is configured.
+    pub resolver: Option<String>,
+}
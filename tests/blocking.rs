@@ -0,0 +1,44 @@
+#![cfg(feature="blocking")]
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use futures::future::empty;
+use ns_router::{Config, Router};
+use ns_router::blocking::resolve_fn;
+
+
+fn router_in_thread() -> Router {
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut core = tokio_core::reactor::Core::new().unwrap();
+        let cfg = Config::new()
+            .add_host(&"localhost".parse().unwrap(),
+                      vec!["127.0.0.1".parse().unwrap()])
+            .done();
+        tx.send(Router::from_config(&cfg, &core.handle())).unwrap();
+        core.run(empty::<(), ()>()).unwrap();
+    });
+    rx.recv().unwrap()
+}
+
+#[test]
+fn test_resolve_fn() {
+    let router = router_in_thread();
+    let resolve = resolve_fn(&router, 80);
+
+    assert_eq!(resolve("localhost").unwrap(),
+        vec!["127.0.0.1:80".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(resolve("localhost:8080").unwrap(),
+        vec!["127.0.0.1:8080".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(resolve("127.0.0.2:1234").unwrap(),
+        vec!["127.0.0.2:1234".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(resolve("example.org").unwrap_err().kind(),
+        io::ErrorKind::NotFound);
+}
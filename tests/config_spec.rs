@@ -0,0 +1,121 @@
+#![cfg(feature="serde")]
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate serde_yaml;
+extern crate tokio_core;
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::future::{FutureResult, ok};
+use futures::stream::{once, Once};
+use abstract_ns::{HostResolve, Resolve, HostSubscribe, Subscribe};
+use abstract_ns::{Name, Address, IpList, Error};
+use ns_router::{Config, ConfigSpec, SpecError, Router};
+
+
+#[derive(Debug)]
+struct Mock(IpAddr);
+
+impl HostResolve for Mock {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec![self.0].into())
+    }
+}
+
+impl Resolve for Mock {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok([SocketAddr::new(self.0, 443)][..].into())
+    }
+}
+
+impl HostSubscribe for Mock {
+    type HostStream = Once<IpList, Error>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        once(Ok(vec![self.0].into()))
+    }
+}
+
+impl Subscribe for Mock {
+    type Stream = Once<Address, Error>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        once(Ok([SocketAddr::new(self.0, 443)][..].into()))
+    }
+}
+
+fn addr(addr: &str) -> Address {
+    [addr.parse::<SocketAddr>().unwrap()][..].into()
+}
+
+fn factory(name: &str) -> Option<Mock> {
+    match name {
+        "consul" => Some(Mock("127.0.0.2".parse().unwrap())),
+        "dns" => Some(Mock("127.0.0.3".parse().unwrap())),
+        _ => None,
+    }
+}
+
+const SPEC: &str = r#"
+hosts:
+  localhost: [127.0.0.1]
+services:
+  _http._tcp.localhost: [127.0.0.1:80, 127.0.0.1:8080]
+suffixes:
+  consul: consul
+fallthrough: dns
+restart_delay: 150ms
+convergence_delay: 1s
+"#;
+
+#[test]
+fn deserialize() {
+    let spec: ConfigSpec = serde_yaml::from_str(SPEC).unwrap();
+    assert_eq!(spec.hosts["localhost"],
+        vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    assert_eq!(spec.services["_http._tcp.localhost"].len(), 2);
+    assert_eq!(spec.suffixes["consul"], "consul");
+    assert_eq!(spec.fallthrough, Some("dns".to_string()));
+    assert_eq!(spec.restart_delay, Some(Duration::from_millis(150)));
+    assert_eq!(spec.convergence_delay, Some(Duration::from_secs(1)));
+}
+
+#[test]
+fn bad_duration() {
+    assert!(serde_yaml::from_str::<ConfigSpec>("restart_delay: soon")
+        .is_err());
+}
+
+#[test]
+fn unknown_resolver() {
+    let spec: ConfigSpec = serde_yaml::from_str("fallthrough: etcd")
+        .unwrap();
+    match Config::from_spec(&spec, factory) {
+        Err(SpecError::UnknownResolver(ref name)) if name == "etcd" => {}
+        res => panic!("unexpected result {:?}", res.map(|_| ())),
+    }
+}
+
+#[test]
+fn router_from_spec() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let spec: ConfigSpec = serde_yaml::from_str(SPEC).unwrap();
+    let cfg = Config::from_spec(&spec, factory).unwrap().done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_auto("localhost", 80)).unwrap();
+    assert_eq!(res, addr("127.0.0.1:80"));
+    let res = core.run(router.resolve_auto("_http._tcp.localhost", 80))
+        .unwrap();
+    assert_eq!(res, Address::parse_list(
+        &["127.0.0.1:80", "127.0.0.1:8080"]).unwrap());
+    let res = core.run(router.resolve_auto("service.consul", 80)).unwrap();
+    assert_eq!(res, addr("127.0.0.2:80"));
+    let res = core.run(router.resolve_auto("example.org", 80)).unwrap();
+    assert_eq!(res, addr("127.0.0.3:80"));
+}
@@ -0,0 +1,154 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::cell::Cell;
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::time::Duration;
+
+use abstract_ns::{HostSubscribe, Subscribe, Name, Address, IpList, Error};
+use abstract_ns::{Resolve, HostResolve};
+use futures::{Future, Stream, Async, lazy};
+use futures::future::{FutureResult, Empty, IntoStream, ok, empty};
+use futures::stream::{once, Chain, Once};
+use ns_router::{Config, Router, SubscribeExt, ManualTimer};
+
+
+#[derive(Debug)]
+struct Mock;
+
+fn addresses() -> Address {
+    Address::parse_list(&["127.0.0.1:80", "127.0.0.2:80", "127.0.0.3:80"])
+    .unwrap()
+}
+
+fn ips() -> IpList {
+    IpList::parse_list(&["127.0.0.1", "127.0.0.2", "127.0.0.3"]).unwrap()
+}
+
+impl Resolve for Mock {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(addresses())
+    }
+}
+
+impl HostResolve for Mock {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(ips())
+    }
+}
+
+impl Subscribe for Mock {
+    type Error = Error;
+    type Stream = Chain<Once<Address, Error>,
+                        IntoStream<Empty<Address, Error>>>;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        once(Ok(addresses()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl HostSubscribe for Mock {
+    type HostError = Error;
+    type HostStream = Chain<Once<IpList, Error>,
+                            IntoStream<Empty<IpList, Error>>>;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        once(Ok(ips()))
+            .chain(empty().into_stream())
+    }
+}
+
+fn is_not_third(addr: SocketAddr) -> FutureResult<bool, ()> {
+    ok(addr.ip() != "127.0.0.3".parse::<IpAddr>().unwrap())
+}
+
+#[test]
+fn test_filter_service() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .set_fallthrough(Mock.health_checked(is_not_third,
+            Duration::from_secs(3600), &handle))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&"localhost".parse().unwrap()).into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0, Some(Address::parse_list(&[
+        "127.0.0.1:80", "127.0.0.2:80",
+    ]).unwrap()));
+}
+
+#[test]
+fn test_filter_host() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let sub = Mock.health_checked(is_not_third,
+        Duration::from_secs(3600), &handle);
+    let name = "localhost".parse().unwrap();
+
+    // host subscriptions aren't checked without a port
+    let res = core.run(sub.subscribe_host(&name).into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(res.0.unwrap().iter().count(), 3);
+
+    let sub = sub.host_port(80);
+    let res = core.run(sub.subscribe_host(&name).into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(IpList::parse_list(&["127.0.0.1", "127.0.0.2"]).unwrap()));
+}
+
+#[test]
+fn test_debounce() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let timer = ManualTimer::new();
+    let healthy = Rc::new(Cell::new(true));
+    let sub = Mock.health_checked({
+        let healthy = healthy.clone();
+        move |addr: SocketAddr| {
+            if healthy.get() { ok(true) } else { is_not_third(addr) }
+        }
+    }, Duration::from_secs(10), &timer);
+    let mut stream = sub.subscribe(&"localhost".parse().unwrap());
+    let mut poll = || {
+        match core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap() {
+            Ok(Async::Ready(Some(value))) => Some(value.at(0).addresses()
+                .map(|a| a.ip().to_string()).collect::<Vec<_>>()),
+            Ok(Async::NotReady) => None,
+            other => panic!("unexpected poll result {:?}", other),
+        }
+    };
+
+    assert_eq!(poll().unwrap(), ["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+    assert_eq!(poll(), None);
+
+    // a single failure is ignored
+    healthy.set(false);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll(), None);
+    healthy.set(true);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll(), None);
+
+    // two failures in a row remove the address
+    healthy.set(false);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll(), None);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll().unwrap(), ["127.0.0.1", "127.0.0.2"]);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll(), None);
+
+    healthy.set(true);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll(), None);
+    timer.advance(Duration::from_secs(10));
+    assert_eq!(poll().unwrap(), ["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+}
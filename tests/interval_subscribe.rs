@@ -4,14 +4,15 @@ extern crate ns_router;
 extern crate tokio_core;
 
 use std::net::{IpAddr};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use futures::{Stream, lazy};
-use futures::future::{FutureResult, ok};
+use futures::{Future, Stream, lazy};
+use futures::future::{FutureResult, Empty, IntoStream, ok, empty};
+use futures::stream::{self, Chain};
 use abstract_ns::{HostSubscribe, Subscribe, Name, Address, IpList, Error};
 use abstract_ns::{Resolve, HostResolve};
-use ns_router::{Config, Router, SubscribeExt};
+use ns_router::{Config, Router, SubscribeExt, MinTtl};
 
 #[derive(Debug)]
 struct IncrMock(AtomicUsize);
@@ -77,3 +78,202 @@ fn test_service() {
     assert_eq!(res.0,
         Some(Address::parse_list(&["127.0.0.2:443"]).unwrap()));
 }
+
+#[test]
+fn test_reactor_gone() {
+    let core = tokio_core::reactor::Core::new().unwrap();
+    let sub = IncrMock(AtomicUsize::new(1))
+        .interval_subscriber(Duration::from_millis(100), &core.handle());
+    let stream = sub.subscribe_host(&"localhost".parse().unwrap());
+    drop(core);
+
+    // timer doesn't work without reactor, so the stream is finished
+    // instead of panicking
+    let values = stream.wait().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(values,
+        vec![vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()]);
+}
+
+#[test]
+fn test_network_change() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .set_fallthrough(IncrMock(AtomicUsize::new(1))
+            .interval_subscriber(Duration::from_secs(3600), &handle))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res1 = core.run(lazy(|| {
+        router.subscribe_host(&"a.localhost".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res1.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+    let res2 = core.run(lazy(|| {
+        router.subscribe(&"_http._tcp.b.localhost".parse().unwrap())
+        .into_future()
+    })).unwrap();
+    assert_eq!(res2.0,
+        Some(Address::parse_list(&["127.0.0.2:443"]).unwrap()));
+
+    // interval is an hour, so new values are only caused by refresh
+    router.on_network_change();
+
+    let (val1, val2) = core.run(
+        res1.1.into_future().map_err(|(e, _)| e)
+        .join(res2.1.into_future().map_err(|(e, _)| e))
+    ).unwrap();
+    let ip1 = *val1.0.unwrap().iter().next().unwrap();
+    let addr2 = val2.0.unwrap();
+    let mut seen = vec![ip1, addr2.at(0).addresses().next().unwrap().ip()];
+    seen.sort();
+    assert_eq!(seen, vec![
+        "127.0.0.3".parse::<IpAddr>().unwrap(),
+        "127.0.0.4".parse::<IpAddr>().unwrap(),
+    ]);
+}
+
+#[derive(Debug)]
+struct Once;
+
+impl HostResolve for Once {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec!["127.0.0.1".parse().unwrap()].into())
+    }
+}
+
+impl HostSubscribe for Once {
+    type HostStream = Chain<stream::Once<IpList, Error>,
+                           IntoStream<Empty<IpList, Error>>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        stream::once(Ok(vec!["127.0.0.1".parse().unwrap()].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl Resolve for Once {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["127.0.0.1:443".parse().unwrap()][..].into())
+    }
+}
+
+#[test]
+fn test_max_staleness() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let sub = Once.max_staleness(Duration::from_millis(100), &handle);
+    let stream = sub.subscribe_host(&"localhost".parse().unwrap());
+
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+
+    // backend never updates, so the value is reported as stale
+    let start = Instant::now();
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(res.0.map(|x| x.iter().count()), Some(0));
+}
+
+#[test]
+fn test_jitter() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut values = vec![0.0, 0.999].into_iter().cycle();
+    let sub = IncrMock(AtomicUsize::new(1))
+        .interval_subscriber_jitter(Duration::from_millis(100), 0.5, &handle)
+        .random_source(move || values.next().unwrap());
+    let stream = sub.subscribe_host(&"localhost".parse().unwrap());
+
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+
+    let start = Instant::now();
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    let short = start.elapsed();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into()));
+
+    let start = Instant::now();
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    let long = start.elapsed();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.3".parse::<IpAddr>().unwrap()].into()));
+
+    assert!(short >= Duration::from_millis(45));
+    assert!(short < Duration::from_millis(100));
+    assert!(long >= Duration::from_millis(140));
+    assert!(long < Duration::from_millis(200));
+}
+
+#[derive(Debug)]
+struct TtlMock(AtomicUsize, Duration);
+
+impl HostResolve for TtlMock {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        let n = self.0.fetch_add(1, Ordering::SeqCst);
+        ok(vec![format!("127.0.0.{}", n).parse().unwrap()].into())
+    }
+}
+
+impl Resolve for TtlMock {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        let n = self.0.fetch_add(1, Ordering::SeqCst);
+        ok([format!("127.0.0.{}:443", n).parse().unwrap()][..].into())
+    }
+}
+
+impl MinTtl for TtlMock {
+    fn min_ttl(&self, _name: &Name, _addr: &Address) -> Option<Duration> {
+        Some(self.1)
+    }
+}
+
+#[test]
+fn test_ttl() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let sub = TtlMock(AtomicUsize::new(1), Duration::from_millis(300))
+        .ttl_subscriber(Duration::from_millis(10),
+            Duration::from_millis(10), Duration::from_secs(3600), &handle);
+    let stream = sub.subscribe(&"localhost".parse().unwrap());
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.1:443"]).unwrap()));
+
+    let start = Instant::now();
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.2:443"]).unwrap()));
+    assert!(start.elapsed() >= Duration::from_millis(290));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // TTL of 5 seconds is clamped to the maximum
+    let sub = TtlMock(AtomicUsize::new(1), Duration::from_secs(5))
+        .ttl_subscriber(Duration::from_millis(10),
+            Duration::from_millis(10), Duration::from_millis(100), &handle);
+    let stream = sub.subscribe(&"localhost".parse().unwrap());
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    let start = Instant::now();
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.2:443"]).unwrap()));
+    assert!(start.elapsed() >= Duration::from_millis(90));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // host TTL is unknown, so default interval is used
+    let stream = sub.subscribe_host(&"localhost".parse().unwrap());
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    let start = Instant::now();
+    core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    assert!(start.elapsed() < Duration::from_millis(90));
+}
@@ -0,0 +1,54 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate log;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{Log, LogRecord, LogMetadata, LogLevelFilter};
+use ns_router::{Config, Router};
+
+
+struct Capture(Arc<Mutex<Vec<String>>>);
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &LogMetadata) -> bool {
+        true
+    }
+    fn log(&self, record: &LogRecord) {
+        self.0.lock().unwrap().push(format!("{}", record.args()));
+    }
+}
+
+#[test]
+fn test_two_labels() {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let logger_lines = lines.clone();
+    log::set_logger(move |max_level| {
+        max_level.set(LogLevelFilter::Debug);
+        Box::new(Capture(logger_lines))
+    }).unwrap();
+
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let (_router_a, up_a) = Router::updating_config(
+        &Config::new().set_label("tenant-a").done(), &handle);
+    let (_router_b, up_b) = Router::updating_config(
+        &Config::new().set_label("tenant-b").done(), &handle);
+    core.turn(Some(Duration::new(0, 0)));
+
+    drop(up_a);
+    drop(up_b);
+    core.turn(Some(Duration::new(0, 0)));
+
+    let lines = lines.lock().unwrap();
+    assert!(lines.contains(&"tenant-a: config updated".to_string()));
+    assert!(lines.contains(&"tenant-b: config updated".to_string()));
+    assert!(lines.contains(
+        &"tenant-a: config stream closed, shutting down".to_string()));
+    assert!(lines.contains(
+        &"tenant-b: config stream closed, shutting down".to_string()));
+}
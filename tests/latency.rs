@@ -0,0 +1,82 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::time::Duration;
+
+use futures::{Future, lazy};
+use abstract_ns::{HostResolve, Resolve, Name, Address, IpList, Error};
+use ns_router::{Config, Router};
+use tokio_core::reactor::{Handle, Timeout};
+
+
+#[derive(Debug)]
+struct Slow(Handle);
+
+impl Slow {
+    fn sleep(&self) -> Box<Future<Item=(), Error=Error>> {
+        Box::new(Timeout::new(Duration::from_millis(150), &self.0).unwrap()
+            .map_err(|e| Error::TemporaryError(Box::new(e))))
+    }
+}
+
+impl HostResolve for Slow {
+    type HostFuture = Box<Future<Item=IpList, Error=Error>>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        Box::new(self.sleep()
+            .map(|()| vec!["127.0.0.1".parse().unwrap()].into()))
+    }
+}
+
+impl Resolve for Slow {
+    type Future = Box<Future<Item=Address, Error=Error>>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        Box::new(self.sleep()
+            .map(|()| ["127.0.0.1:443".parse().unwrap()][..].into()))
+    }
+}
+
+#[test]
+fn test_latency_bucket() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .set_fallthrough(Slow(handle.clone()).frozen_subscriber())
+        .track_latency(true)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    core.run(lazy(|| {
+        router.resolve_host(&"localhost".parse().unwrap())
+    })).unwrap();
+    core.run(lazy(|| {
+        router.resolve_auto("localhost", 80)
+    })).unwrap();
+
+    let stats = router.latency_stats();
+    assert_eq!(stats.host.count(), 1);
+    assert_eq!(stats.host.percentile(0.5), Some(Duration::from_millis(200)));
+    assert_eq!(stats.host_port.count(), 1);
+    assert_eq!(stats.host_port.percentile(0.99),
+               Some(Duration::from_millis(200)));
+    assert_eq!(stats.service.count(), 0);
+}
+
+#[test]
+fn test_latency_disabled() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    core.run(lazy(|| {
+        router.resolve_host(&"localhost".parse().unwrap())
+    })).unwrap();
+    assert_eq!(router.latency_stats().host.count(), 0);
+}
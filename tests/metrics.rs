@@ -0,0 +1,141 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use futures::{Stream, lazy};
+use abstract_ns::HostResolve;
+use ns_router::{Config, Router, Metrics};
+
+
+#[derive(Debug, Default)]
+struct Counting {
+    started: AtomicUsize,
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+    subscriptions: AtomicUsize,
+    configs: AtomicUsize,
+}
+
+impl Metrics for Counting {
+    fn on_resolve_start(&self, _label: &str) {
+        self.started.fetch_add(1, Ordering::SeqCst);
+    }
+    fn on_resolve_done(&self, _label: &str, success: bool) {
+        if success {
+            self.succeeded.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.failed.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    fn on_subscribe(&self, _label: &str) {
+        self.subscriptions.fetch_add(1, Ordering::SeqCst);
+    }
+    fn on_unsubscribe(&self, _label: &str) {
+        self.subscriptions.fetch_sub(1, Ordering::SeqCst);
+    }
+    fn on_config_update(&self, _label: &str) {
+        self.configs.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Debug, Default)]
+struct PerLabel(Mutex<HashMap<String, (usize, usize)>>);
+
+impl PerLabel {
+    fn get(&self, label: &str) -> (usize, usize) {
+        self.0.lock().unwrap().get(label).cloned().unwrap_or((0, 0))
+    }
+}
+
+impl Metrics for PerLabel {
+    fn on_resolve_start(&self, label: &str) {
+        self.0.lock().unwrap().entry(label.to_string()).or_default().0 += 1;
+    }
+    fn on_subscribe(&self, label: &str) {
+        self.0.lock().unwrap().entry(label.to_string()).or_default().1 += 1;
+    }
+    fn on_unsubscribe(&self, label: &str) {
+        self.0.lock().unwrap().entry(label.to_string()).or_default().1 -= 1;
+    }
+}
+
+#[test]
+fn test_counts() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let metrics = Arc::new(Counting::default());
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse::<IpAddr>().unwrap()])
+        .set_metrics(metrics.clone())
+        .done();
+    let (router, up) = Router::updating_config(&cfg, &handle);
+
+    core.run(lazy(|| {
+        router.resolve_host(&"localhost".parse().unwrap())
+    })).unwrap();
+    core.run(lazy(|| {
+        router.resolve_auto("localhost", 80)
+    })).unwrap();
+    core.run(lazy(|| {
+        router.resolve_host(&"missing.localhost".parse().unwrap())
+    })).unwrap_err();
+    assert_eq!(metrics.started.load(Ordering::SeqCst), 3);
+    assert_eq!(metrics.succeeded.load(Ordering::SeqCst), 2);
+    assert_eq!(metrics.failed.load(Ordering::SeqCst), 1);
+    assert_eq!(metrics.configs.load(Ordering::SeqCst), 1);
+
+    let stream = router.subscribe_many(&["localhost"], 80);
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+    assert_eq!(metrics.subscriptions.load(Ordering::SeqCst), 1);
+
+    up.update(&cfg);
+    core.turn(Some(Duration::new(0, 0)));
+    assert_eq!(metrics.configs.load(Ordering::SeqCst), 2);
+
+    drop(res);
+    core.turn(Some(Duration::new(0, 0)));
+    assert_eq!(metrics.subscriptions.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn test_shared_by_routers() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let metrics = Arc::new(PerLabel::default());
+    let mut cfg = Config::new();
+    cfg.add_host(&"localhost".parse().unwrap(),
+                 vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    cfg.set_metrics(metrics.clone());
+    let first = Router::from_config(&cfg.set_label("first").done(), &handle);
+    let second = Router::from_config(
+        &cfg.set_label("second").done(), &handle);
+
+    core.run(lazy(|| {
+        first.resolve_host(&"localhost".parse().unwrap())
+    })).unwrap();
+    core.run(lazy(|| {
+        second.resolve_host(&"localhost".parse().unwrap())
+    })).unwrap();
+    core.run(lazy(|| {
+        second.resolve_host(&"localhost".parse().unwrap())
+    })).unwrap();
+    let stream = first.subscribe_many(&["localhost"], 80);
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert!(res.0.is_some());
+
+    assert_eq!(metrics.get("first"), (1, 1));
+    assert_eq!(metrics.get("second"), (2, 0));
+    assert_eq!(metrics.get("router"), (0, 0));
+}
@@ -0,0 +1,50 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use abstract_ns::{Name, Resolve};
+use futures::Future;
+use ns_router::{Config, Router, ResolveMiddleware};
+use ns_router::middleware::AddressFuture;
+
+
+#[derive(Debug)]
+struct Sidecar;
+
+impl ResolveMiddleware for Sidecar {
+    fn around_resolve(&self, _name: &Name, next: AddressFuture)
+        -> AddressFuture
+    {
+        Box::new(next.map(|_| {
+            ["127.0.0.1:15001".parse::<SocketAddr>().unwrap()][..].into()
+        }))
+    }
+}
+
+#[test]
+fn test_rewrite() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_service(&"_http._tcp.localhost".parse().unwrap(),
+                  ["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into())
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .add_middleware(Arc::new(Sidecar))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(
+        router.resolve(&"_http._tcp.localhost".parse().unwrap()));
+    assert_eq!(res.unwrap(),
+        ["127.0.0.1:15001".parse::<SocketAddr>().unwrap()][..].into());
+
+    let res = core.run(router.resolve_auto("localhost:8080", 80));
+    assert_eq!(res.unwrap(),
+        ["127.0.0.1:15001".parse::<SocketAddr>().unwrap()][..].into());
+}
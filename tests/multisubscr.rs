@@ -4,6 +4,7 @@ extern crate ns_router;
 extern crate tokio_core;
 
 use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
 
 use futures::{lazy};
 use futures::future::{Future, Empty, IntoStream, empty};
@@ -11,7 +12,10 @@ use futures::future::{FutureResult, ok};
 use futures::stream::{once, Stream, Chain, Once};
 use abstract_ns::{HostSubscribe, Subscribe, Name, Address, IpList, Error};
 use abstract_ns::{Resolve, HostResolve};
-use ns_router::{Config, Router};
+use abstract_ns::addr::Builder;
+use ns_router::{Config, Router, ManualTimer, AutoName};
+use ns_router::future::union_streams;
+use tokio_core::reactor::Timeout;
 
 
 #[derive(Debug)]
@@ -87,3 +91,441 @@ fn host_and_service() {
             "127.0.0.2:8080".parse::<SocketAddr>().unwrap(),
         ][..].into()));
 }
+
+#[test]
+fn checked_names() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"example.org".parse().unwrap(),
+                 vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let (stream, errors) = router.subscribe_many_checked(&[
+        "example.org",
+        "127.0.0.1:80",
+        "example.org:99999",
+    ], 8080);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, 2);
+    match errors[0].1 {
+        Error::InvalidName(ref name, _) => {
+            assert_eq!(name, "example.org:99999");
+        }
+        ref e => panic!("unexpected error {:?}", e),
+    }
+
+    let res = core.run(stream.into_future()).unwrap();
+    assert_eq!(res.0,
+        Some([
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.2:8080".parse::<SocketAddr>().unwrap(),
+        ][..].into()));
+}
+
+
+#[test]
+fn try_subscribe_names() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"example.org".parse().unwrap(),
+                 vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let errors = router.try_subscribe_many(&[
+        "example.org",
+        "example.org:99999",
+    ], 8080).err().expect("name is rejected");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, "example.org:99999");
+    match errors[0].1 {
+        Error::InvalidName(ref name, _) => {
+            assert_eq!(name, "example.org:99999");
+        }
+        ref e => panic!("unexpected error {:?}", e),
+    }
+
+    let stream = router.try_subscribe_many(&["example.org"], 8080)
+        .ok().expect("names are valid");
+    let res = core.run(stream.into_future()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.2:8080".parse::<SocketAddr>().unwrap()][..].into()));
+}
+
+#[test]
+fn shared_address() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"a.example.org".parse().unwrap(), vec![
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        "127.0.0.2".parse::<IpAddr>().unwrap(),
+    ]);
+    cfg.add_host(&"b.example.org".parse().unwrap(), vec![
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        "127.0.0.3".parse::<IpAddr>().unwrap(),
+    ]);
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&[
+            "a.example.org",
+            "b.example.org",
+        ], 80).into_future()
+    })).unwrap();
+    let addr = res.0.unwrap();
+    assert_eq!(addr.at(0).addresses().count(), 3);
+    assert_eq!(addr,
+        [
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.2:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.3:80".parse::<SocketAddr>().unwrap(),
+        ][..].into());
+}
+
+#[test]
+fn per_name_ports() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"a.example.org".parse().unwrap(),
+                 vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    cfg.add_host(&"b.example.org".parse().unwrap(),
+                 vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_many_ports(vec![
+            ("a.example.org", 80),
+            ("b.example.org", 8080),
+            ("a.example.org:81", 8080),
+        ]).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some([
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.2:8080".parse::<SocketAddr>().unwrap(),
+            "127.0.0.1:81".parse::<SocketAddr>().unwrap(),
+        ][..].into()));
+}
+
+fn weighted(sets: &[&[(&str, u64)]]) -> Address {
+    let mut builder = Builder::new();
+    for set in sets {
+        let items = set.iter()
+            .map(|&(a, w)| (w, a.parse::<SocketAddr>().unwrap()))
+            .collect::<Vec<_>>();
+        builder.add_addresses(&items);
+    }
+    builder.into_address()
+}
+
+#[test]
+fn priorities_preserved() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&"_a._tcp.example.org".parse().unwrap(), weighted(&[
+        &[("127.0.0.1:80", 10)],
+        &[("127.0.0.2:80", 5)],
+    ]));
+    cfg.add_service(&"_b._tcp.example.org".parse().unwrap(), weighted(&[
+        &[("127.0.0.3:80", 20)],
+    ]));
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&[
+            "_a._tcp.example.org",
+            "_b._tcp.example.org",
+        ], 80).into_future()
+    })).unwrap();
+    // weights of resolved addresses are not accessible, so they are reset
+    assert_eq!(res.0, Some(weighted(&[
+        &[("127.0.0.1:80", 0), ("127.0.0.3:80", 0)],
+        &[("127.0.0.2:80", 0)],
+    ])));
+}
+
+#[test]
+fn weighted_addr() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&"_a._tcp.example.org".parse().unwrap(), weighted(&[
+        &[("127.0.0.1:80", 10)],
+    ]));
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_many(vec![
+            AutoName::WeightedAddr("10.0.0.1:80".parse().unwrap(), 100),
+            AutoName::SocketAddr("10.0.0.2:80".parse().unwrap()),
+            AutoName::Auto("_a._tcp.example.org"),
+        ], 80).into_future()
+    })).unwrap();
+    // weight of the resolved service address is not accessible
+    assert_eq!(res.0, Some(weighted(&[
+        &[("10.0.0.1:80", 100), ("10.0.0.2:80", 1), ("127.0.0.1:80", 1)],
+    ])));
+
+    let res = core.run(router.resolve_auto(
+        AutoName::WeightedAddr("10.0.0.1:80".parse().unwrap(), 100), 80));
+    assert_eq!(res.unwrap(), weighted(&[&[("10.0.0.1:80", 100)]]));
+}
+
+#[test]
+fn empty_list() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .emit_empty_for_empty_list(true)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let start = Instant::now();
+    let res = core.run(lazy(|| {
+        router.subscribe_many(Vec::<&str>::new(), 80).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    let addr = res.0.unwrap();
+    assert!(addr.iter().all(|set| set.addresses().next().is_none()));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn empty_list_by_default() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let start = Instant::now();
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&[] as &[&str], 80).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    let addr = res.0.unwrap();
+    assert!(addr.iter().all(|set| set.addresses().next().is_none()));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // and nothing else is sent after the empty address
+    let mut stream = res.1;
+    core.turn(Some(Duration::from_millis(10)));
+    let poll = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert!(poll.unwrap().is_not_ready());
+}
+
+/// Resolves `hN.example.org` to an unique address derived from `N`
+#[derive(Debug)]
+struct Numbered;
+
+fn numbered_ip(name: &Name) -> IpAddr {
+    let num: u16 = name.as_ref().split('.').next().unwrap()[1..]
+        .parse().unwrap();
+    format!("10.0.{}.{}", num >> 8, num & 0xFF).parse().unwrap()
+}
+
+impl HostResolve for Numbered {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        ok(vec![numbered_ip(name)].into())
+    }
+}
+
+impl Resolve for Numbered {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        ok([SocketAddr::new(numbered_ip(name), 80)][..].into())
+    }
+}
+
+impl HostSubscribe for Numbered {
+    type HostStream = Chain<Once<IpList, Error>,
+                            IntoStream<Empty<IpList, Error>>>;
+    type HostError = Error;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        once(Ok(vec![numbered_ip(name)].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl Subscribe for Numbered {
+    type Stream = Chain<Once<Address, Error>,
+                            IntoStream<Empty<Address, Error>>>;
+    type Error = Error;
+    fn subscribe(&self, name: &Name) -> Self::Stream {
+        once(Ok([SocketAddr::new(numbered_ip(name), 80)][..].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+#[test]
+fn large_list() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_suffix("example.org", Numbered);
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let names = (0..2000).map(|i| format!("h{}.example.org", i))
+        .collect::<Vec<_>>();
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&names, 80).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    let addr = res.0.unwrap();
+    assert_eq!(addr.at(0).addresses().count(), 2000);
+
+    // single item changed in a large list
+    cfg.add_host(&"h1999.example.org".parse().unwrap(),
+                 vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    let addr = res.0.unwrap();
+    let addrs = addr.at(0).addresses().collect::<Vec<_>>();
+    assert_eq!(addrs.len(), 2000);
+    assert!(addrs.contains(&"127.0.0.1:80".parse().unwrap()));
+    assert!(!addrs.contains(&"10.0.7.207:80".parse().unwrap()));
+}
+
+/// Never resolves anything
+#[derive(Debug)]
+struct Never;
+
+impl HostResolve for Never {
+    type HostFuture = Empty<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        empty()
+    }
+}
+
+impl Resolve for Never {
+    type Future = Empty<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        empty()
+    }
+}
+
+impl HostSubscribe for Never {
+    type HostStream = IntoStream<Empty<IpList, Error>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        empty().into_stream()
+    }
+}
+
+impl Subscribe for Never {
+    type Stream = IntoStream<Empty<Address, Error>>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        empty().into_stream()
+    }
+}
+
+#[test]
+fn convergence_override() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .add_suffix("slow.example", Never)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let names = ["127.0.0.1:80", "x.slow.example"];
+
+    let mut default = router.subscribe_many(&names, 80);
+    let start = Instant::now();
+    let res = core.run(lazy(|| {
+        router.subscribe_many_with(&names, 80, Duration::from_millis(10))
+            .into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // subscription with default delay still waits for all names
+    core.run(Timeout::new(Duration::from_millis(100), &handle).unwrap())
+        .unwrap();
+    let poll = core.run(lazy(|| Ok::<_, ()>(default.poll()))).unwrap();
+    assert!(poll.unwrap().is_not_ready());
+}
+
+#[test]
+fn convergence_manual_timer() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let timer = ManualTimer::new();
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .timer(timer.clone())
+        .add_suffix("slow.example", Never)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let mut stream = router.subscribe_many(
+        &["127.0.0.1:80", "x.slow.example"], 80);
+    for _ in 0..10 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    let poll = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert!(poll.unwrap().is_not_ready());
+
+    timer.advance(Duration::from_secs(9));
+    core.turn(Some(Duration::new(0, 0)));
+    let poll = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert!(poll.unwrap().is_not_ready());
+
+    timer.advance(Duration::from_secs(1));
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+}
+
+#[test]
+fn union_of_streams() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"a.example.org".parse().unwrap(),
+                 vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    cfg.add_host(&"b.example.org".parse().unwrap(),
+                 vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    let router1 = Router::from_config(&cfg.done(), &handle);
+    let router2 = Router::from_config(&cfg.done(), &handle);
+
+    let stream = union_streams(vec![
+        router1.subscribe_many(&["a.example.org"], 80),
+        router2.subscribe_many(&["b.example.org"], 8080),
+    ]);
+    // let both subscriptions deliver the value
+    for _ in 0..3 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    let (value, stream) = core.run(stream.into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(value,
+        Some([
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.2:8080".parse::<SocketAddr>().unwrap(),
+        ][..].into()));
+
+    // finished stream is removed from the union
+    router2.shutdown();
+    let (value, stream) = core.run(stream.into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(value,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+
+    router1.shutdown();
+    let (value, _) = core.run(stream.into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(value, None);
+}
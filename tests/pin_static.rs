@@ -0,0 +1,96 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::{lazy};
+use futures::future::{FutureResult, ok};
+use abstract_ns::{HostResolve, Resolve, Name, Address, IpList, Error};
+use ns_router::{Config, Router};
+
+
+#[derive(Debug)]
+struct IncrMock(AtomicUsize);
+
+impl HostResolve for IncrMock {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        let n = self.0.fetch_add(1, Ordering::SeqCst);
+        ok(vec![format!("127.0.0.{}", n).parse().unwrap()].into())
+    }
+}
+
+impl Resolve for IncrMock {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        let n = self.0.fetch_add(1, Ordering::SeqCst);
+        ok([format!("127.0.0.{}:443", n).parse().unwrap()][..].into())
+    }
+}
+
+#[test]
+fn test_pin_host() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .set_fallthrough(IncrMock(AtomicUsize::new(1)).frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    core.run(lazy(|| {
+        router.pin_static_host(&"localhost".parse().unwrap())
+    })).unwrap();
+
+    for _ in 0..3 {
+        let res = core.run(lazy(|| {
+            router.resolve_host(&"localhost".parse().unwrap())
+        })).unwrap();
+        assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+    }
+
+    // other names still go to the resolver
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"example.org".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_pin_service() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .set_fallthrough(IncrMock(AtomicUsize::new(1)).frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    core.run(lazy(|| {
+        router.pin_static(&"_http._tcp.localhost".parse().unwrap())
+    })).unwrap();
+
+    for _ in 0..3 {
+        let res = core.run(lazy(|| {
+            router.resolve(&"_http._tcp.localhost".parse().unwrap())
+        })).unwrap();
+        assert_eq!(res,
+            ["127.0.0.1:443".parse::<SocketAddr>().unwrap()][..].into());
+    }
+}
+
+#[test]
+fn test_pin_error() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let router = Router::from_config(&Config::new().done(), &handle);
+
+    match core.run(router.pin_static_host(&"localhost".parse().unwrap())) {
+        Err(Error::NameNotFound) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+}
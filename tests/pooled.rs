@@ -0,0 +1,76 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use futures::future::{FutureResult, join_all, ok};
+use abstract_ns::{Resolve, HostResolve, Name, Address, IpList, Error};
+use ns_router::SubscribeExt;
+
+
+#[derive(Debug)]
+struct Counting(Arc<AtomicUsize>);
+
+impl HostResolve for Counting {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ok(vec!["127.0.0.1".parse().unwrap()].into())
+    }
+}
+
+impl Resolve for Counting {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ok(["127.0.0.1:443".parse().unwrap()][..].into())
+    }
+}
+
+fn counters(size: usize) -> Vec<Arc<AtomicUsize>> {
+    (0..size).map(|_| Arc::new(AtomicUsize::new(0))).collect()
+}
+
+fn counts(counters: &[Arc<AtomicUsize>]) -> Vec<usize> {
+    counters.iter().map(|c| c.load(Ordering::SeqCst)).collect()
+}
+
+#[test]
+fn test_spread() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let counters = counters(3);
+    let mut iter = counters.clone().into_iter();
+    let pool = Counting::pooled(|| Counting(iter.next().unwrap()), 3);
+    let name = "example.org".parse().unwrap();
+
+    let futures = (0..6).map(|_| pool.resolve_host(&name))
+        .collect::<Vec<_>>();
+    assert_eq!(counts(&counters), vec![2, 2, 2]);
+    let res = core.run(join_all(futures)).unwrap();
+    assert_eq!(res.len(), 6);
+
+    // finished queries don't occupy the pool
+    core.run(pool.resolve(&name)).unwrap();
+    assert_eq!(counts(&counters), vec![3, 2, 2]);
+}
+
+#[test]
+fn test_max_in_flight() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let counters = counters(2);
+    let mut iter = counters.clone().into_iter();
+    let pool = Counting::pooled(|| Counting(iter.next().unwrap()), 2)
+        .max_in_flight(1);
+    let name = "example.org".parse().unwrap();
+
+    let futures = (0..3).map(|_| pool.resolve_host(&name))
+        .collect::<Vec<_>>();
+    // third query waits for a free resolver
+    assert_eq!(counts(&counters), vec![1, 1]);
+    let res = core.run(join_all(futures)).unwrap();
+    assert_eq!(res.len(), 3);
+    assert_eq!(counts(&counters).iter().sum::<usize>(), 3);
+}
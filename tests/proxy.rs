@@ -0,0 +1,37 @@
+#![cfg(feature="proxy")]
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::net::SocketAddr;
+
+use futures::{lazy};
+use ns_router::{Config, Router};
+use ns_router::proxy::{ProxyResolver, Target};
+
+
+#[test]
+fn test_proxy_suffix() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let mut resolver = ProxyResolver::new(&router);
+    resolver.proxy_suffix("internal");
+
+    let res = core.run(lazy(|| {
+        resolver.resolve_auto("db.internal:5432", 80)
+    })).unwrap();
+    assert_eq!(res, Target::Proxy("db.internal".parse().unwrap(), 5432));
+
+    let res = core.run(lazy(|| {
+        resolver.resolve_auto("localhost", 80)
+    })).unwrap();
+    assert_eq!(res, Target::Resolved(
+        ["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+}
@@ -3,11 +3,15 @@ extern crate futures;
 extern crate ns_router;
 extern crate tokio_core;
 
-use std::net::{SocketAddr};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use abstract_ns::{HostResolve, Resolve, Address, IpList};
-use ns_router::{Config, Router};
+use abstract_ns::{HostResolve, Resolve, Subscribe, Address, IpList};
+use futures::Stream;
+use ns_router::{Config, Router, AutoName};
+use ns_router::ResolvedMode::{HostPort, Service, Addr};
 
 
 
@@ -69,6 +73,42 @@ fn test_auto() {
         ["127.0.0.1:8439".parse::<SocketAddr>().unwrap()][..].into());
 }
 
+#[test]
+fn test_auto_explain() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .add_service(&"_xmpp._tcp.localhost".parse().unwrap(),
+                  ["127.0.0.1:5269".parse::<SocketAddr>().unwrap()][..].into())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let ip = "127.0.0.1".parse::<IpAddr>().unwrap();
+    let sa = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    let cases = vec![
+        (AutoName::Auto("localhost"), Some(HostPort)),
+        (AutoName::Auto("localhost:1234"), Some(HostPort)),
+        (AutoName::Auto("_xmpp._tcp.localhost"), Some(Service)),
+        (AutoName::Auto("127.0.0.1"), Some(Addr)),
+        (AutoName::Auto("127.0.0.1:8080"), Some(Addr)),
+        (AutoName::Auto("_xmpp._tcp.localhost:1234"), None),
+        (AutoName::HostPort("localhost", 1234), Some(HostPort)),
+        (AutoName::HostDefaultPort("localhost"), Some(HostPort)),
+        (AutoName::Service("_xmpp._tcp.localhost"), Some(Service)),
+        (AutoName::IpAddr(ip), Some(Addr)),
+        (AutoName::SocketAddr(sa), Some(Addr)),
+    ];
+    for (name, expected) in cases {
+        let descr = format!("{:?}", name);
+        let (future, mode) = router.resolve_auto_explain(name, 80);
+        assert_eq!(mode, expected, "{}", descr);
+        assert_eq!(core.run(future).is_ok(), expected.is_some(), "{}", descr);
+    }
+}
+
 #[test]
 fn test_straw_addresses() {
     let mut core = tokio_core::reactor::Core::new().unwrap();
@@ -94,3 +134,290 @@ fn test_straw_addresses() {
         core.run(router.resolve_auto("2001:db8::2:1", 80)).unwrap(),
         ["[2001:db8::2:1]:80".parse::<SocketAddr>().unwrap()][..].into());
 }
+
+#[test]
+fn test_host_ports() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap(),
+                       "127.0.0.2".parse().unwrap()])
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_host_ports(
+        &"localhost".parse().unwrap(), &[80, 9100]));
+    assert_eq!(res.unwrap(), Address::parse_list(&[
+        "127.0.0.1:80", "127.0.0.2:80",
+        "127.0.0.1:9100", "127.0.0.2:9100",
+    ]).unwrap());
+}
+
+#[test]
+fn test_least_loaded() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_service(&"_http._tcp.localhost".parse().unwrap(),
+            Address::parse_list(&[
+                "127.0.0.1:80", "127.0.0.2:80", "127.0.0.3:80",
+            ]).unwrap())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let mut loads = HashMap::new();
+    loads.insert("127.0.0.1:80".parse::<SocketAddr>().unwrap(), 10);
+    loads.insert("127.0.0.2:80".parse::<SocketAddr>().unwrap(), 3);
+    loads.insert("127.0.0.3:80".parse::<SocketAddr>().unwrap(), 7);
+    let res = core.run(router.resolve_one_with_load(
+        &"_http._tcp.localhost".parse().unwrap(), &loads));
+    assert_eq!(res.unwrap(), "127.0.0.2:80".parse::<SocketAddr>().unwrap());
+
+    // absent address is treated as zero load
+    loads.remove(&"127.0.0.3:80".parse::<SocketAddr>().unwrap());
+    let res = core.run(router.resolve_one_with_load(
+        &"_http._tcp.localhost".parse().unwrap(), &loads));
+    assert_eq!(res.unwrap(), "127.0.0.3:80".parse::<SocketAddr>().unwrap());
+}
+
+#[test]
+fn test_host_and_service() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_host(&name, vec!["127.0.0.1".parse().unwrap()])
+        .add_service(&name,
+            ["127.0.0.2:8080".parse::<SocketAddr>().unwrap()][..].into())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_host(&name)).unwrap();
+    assert_eq!(res, IpList::parse_list(&["127.0.0.1"]).unwrap());
+    let res = core.run(router.resolve_auto("localhost", 80)).unwrap();
+    assert_eq!(res, Address::parse_list(&["127.0.0.1:80"]).unwrap());
+    let res = core.run(router.resolve(&name)).unwrap();
+    assert_eq!(res, Address::parse_list(&["127.0.0.2:8080"]).unwrap());
+}
+
+#[test]
+fn test_resolve_by_port() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_multi._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_service(&name, [
+            "127.0.0.1:80".parse::<SocketAddr>().unwrap(),
+            "127.0.0.1:9100".parse().unwrap(),
+            "127.0.0.2:80".parse().unwrap(),
+        ][..].into())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_by_port(&name)).unwrap();
+    assert_eq!(res.len(), 2);
+    let mut http = res[&80].clone();
+    http.sort();
+    assert_eq!(http, vec![
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        "127.0.0.2".parse().unwrap(),
+    ]);
+    assert_eq!(res[&9100], vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+}
+
+#[test]
+fn test_on_apply() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let applied = Arc::new(Mutex::new(Vec::new()));
+    let name = "localhost".parse().unwrap();
+
+    let applied2 = applied.clone();
+    let mut cfg = Config::new();
+    cfg.on_apply(Arc::new(move |cfg: &Arc<Config>| {
+        applied2.lock().unwrap().push(&**cfg as *const Config as usize);
+    }));
+    cfg.add_host(&name, vec!["127.0.0.1".parse().unwrap()]);
+    let cfg1 = cfg.done();
+    let (router, up) = Router::updating_config(&cfg1, &handle);
+    core.run(router.resolve_host(&name)).unwrap();
+    assert_eq!(*applied.lock().unwrap(),
+        vec![&*cfg1 as *const Config as usize]);
+
+    cfg.add_host(&name, vec!["127.0.0.2".parse().unwrap()]);
+    let cfg2 = cfg.done();
+    up.update(&cfg2);
+    core.turn(Some(Duration::new(0, 0)));
+    let res = core.run(router.resolve_host(&name)).unwrap();
+    assert_eq!(res, IpList::parse_list(&["127.0.0.2"]).unwrap());
+    assert_eq!(*applied.lock().unwrap(), vec![
+        &*cfg1 as *const Config as usize,
+        &*cfg2 as *const Config as usize,
+    ]);
+}
+
+#[test]
+fn test_current_config() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&name, vec!["127.0.0.1".parse().unwrap()]);
+    let first = cfg.done();
+    let (_router, up) = Router::updating_config(&first, &handle);
+    // router hasn't started yet
+    assert!(up.current_config().is_none());
+    core.turn(Some(Duration::new(0, 0)));
+    assert!(Arc::ptr_eq(&up.current_config().unwrap(), &first));
+
+    cfg.add_host(&name, vec!["127.0.0.2".parse().unwrap()]);
+    up.update(&cfg.done());
+    cfg.add_host(&name, vec!["127.0.0.3".parse().unwrap()]);
+    let third = cfg.done();
+    up.update(&third);
+    core.turn(Some(Duration::new(0, 0)));
+    assert!(Arc::ptr_eq(&up.current_config().unwrap(), &third));
+    assert_eq!(up.current_config().unwrap().hosts().next().unwrap().1,
+        &vec!["127.0.0.3".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_update_with() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "localhost".parse().unwrap();
+    let other = "example.org".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_host(&name, vec!["127.0.0.1".parse().unwrap()])
+        .done();
+    let (router, up) = Router::updating_config(&cfg, &handle);
+    // nothing to update until router applies the config
+    assert!(!up.update_with(|_| panic!("must not be called")));
+    core.turn(Some(Duration::new(0, 0)));
+
+    assert!(up.update_with(|cfg| {
+        cfg.add_host(&other, vec!["127.0.0.2".parse().unwrap()]);
+    }));
+    core.turn(Some(Duration::new(0, 0)));
+    let res = core.run(router.resolve_host(&other));
+    assert_eq!(res.unwrap(), IpList::parse_list(&["127.0.0.2"]).unwrap());
+    // the rest of the config is kept
+    let res = core.run(router.resolve_host(&name));
+    assert_eq!(res.unwrap(), IpList::parse_list(&["127.0.0.1"]).unwrap());
+}
+
+#[test]
+fn test_address_weight() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let name = "_http._tcp.localhost".parse().unwrap();
+    let cfg = Config::new()
+        .set_address_weight(&name,
+            "127.0.0.1:80".parse().unwrap(), 0)
+        .add_service(&name, Address::parse_list(&[
+                "127.0.0.1:80", "127.0.0.2:80",
+            ]).unwrap())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve(&name)).unwrap();
+    // zero-weight address is kept, but only at the lowest priority
+    assert_eq!(res.iter().count(), 2);
+    assert_eq!(res.addresses_at(1).collect::<Vec<_>>(),
+        vec!["127.0.0.1:80".parse::<SocketAddr>().unwrap()]);
+    assert_eq!(res.pick_one(),
+        Some("127.0.0.2:80".parse::<SocketAddr>().unwrap()));
+
+    let res = core.run(router.resolve_one_with_load(&name, &HashMap::new()));
+    assert_eq!(res.unwrap(), "127.0.0.2:80".parse::<SocketAddr>().unwrap());
+}
+
+#[test]
+fn test_auto_port_names() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .register_port("admin", 8081)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    assert_eq!(
+        core.run(router.resolve_auto("localhost:admin", 80)).unwrap(),
+        ["127.0.0.1:8081".parse::<SocketAddr>().unwrap()][..].into());
+    assert_eq!(
+        core.run(router.resolve_auto("localhost:https", 80)).unwrap(),
+        ["127.0.0.1:443".parse::<SocketAddr>().unwrap()][..].into());
+    assert!(core.run(router.resolve_auto("localhost:nope", 80)).is_err());
+}
+
+#[test]
+fn test_fixed() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let addr = Address::parse_list(&["127.0.0.1:80", "127.0.0.2:80"])
+        .unwrap();
+    let router = Router::fixed(addr.clone(), &handle);
+
+    let name = "_http._tcp.anything.example.org".parse().unwrap();
+    assert_eq!(core.run(router.resolve(&name)).unwrap(), addr);
+    assert_eq!(
+        core.run(router.resolve_host(&"example.com".parse().unwrap()))
+            .unwrap(),
+        IpList::parse_list(&["127.0.0.1", "127.0.0.2"]).unwrap());
+    assert_eq!(
+        core.run(router.resolve_auto("example.com:8080", 80)).unwrap(),
+        Address::parse_list(&["127.0.0.1:8080", "127.0.0.2:8080"])
+            .unwrap());
+    let (value, _) = core.run(router.subscribe(&name).into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(value, Some(addr));
+}
+
+#[test]
+fn test_host_auto() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let localhost = IpList::parse_list(&["127.0.0.1"]).unwrap();
+
+    assert_eq!(core.run(router.resolve_host_auto("localhost:1234", 80))
+        .unwrap(), localhost);
+    assert_eq!(core.run(router.resolve_host_auto(
+        AutoName::HostPort("localhost", 1234), 80)).unwrap(), localhost);
+    assert_eq!(core.run(router.resolve_host_auto(
+        AutoName::HostDefaultPort("localhost"), 80)).unwrap(), localhost);
+    assert_eq!(core.run(router.resolve_host_auto("127.0.0.2", 80))
+        .unwrap(), IpList::parse_list(&["127.0.0.2"]).unwrap());
+    assert_eq!(core.run(router.resolve_host_auto(
+        AutoName::IpAddr("127.0.0.3".parse().unwrap()), 80)).unwrap(),
+        IpList::parse_list(&["127.0.0.3"]).unwrap());
+    assert_eq!(core.run(router.resolve_host_auto(
+        AutoName::SocketAddr("127.0.0.4:8080".parse().unwrap()), 80))
+        .unwrap(), IpList::parse_list(&["127.0.0.4"]).unwrap());
+    for name in vec![
+        AutoName::Auto("_http._tcp.localhost"),
+        AutoName::Service("localhost"),
+    ] {
+        match core.run(router.resolve_host_auto(name, 80)) {
+            Err(abstract_ns::Error::InvalidName(..)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
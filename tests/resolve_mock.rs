@@ -4,12 +4,16 @@ extern crate ns_router;
 extern crate tokio_core;
 
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
-use futures::{lazy};
-use futures::future::{FutureResult, ok};
+use futures::{Stream, lazy};
+use futures::future::{FutureResult, Empty, ok, err, empty, join_all};
 use abstract_ns::{HostResolve, Resolve, Name, Address, IpList, Error};
-use ns_router::{Config, Router};
+use abstract_ns::{HostSubscribe, Subscribe};
+use ns_router::{Config, Router, RecordType, Record, Route};
+use tokio_core::reactor::Timeout;
 
 
 #[derive(Debug)]
@@ -18,6 +22,38 @@ struct Mock;
 #[derive(Debug)]
 struct Mock2;
 
+#[derive(Debug)]
+struct TempFail;
+
+/// Counts host resolutions
+#[derive(Debug)]
+struct Counting(Arc<AtomicUsize>);
+
+/// Has both IPv4 and IPv6 addresses, except `v4only.*` names
+#[derive(Debug)]
+struct DualStack;
+
+#[derive(Debug)]
+struct NotFound;
+
+/// Counts resolutions of names which don't exist
+#[derive(Debug)]
+struct CountingNotFound(Arc<AtomicUsize>);
+
+/// Returns IPv4 address mapped into IPv6 space
+#[derive(Debug)]
+struct Mapped;
+
+/// Fails with a temporary error the first `failures` resolutions
+#[derive(Debug)]
+struct Wedged;
+
+#[derive(Debug)]
+struct Flaky {
+    calls: Arc<AtomicUsize>,
+    failures: usize,
+}
+
 
 impl HostResolve for Mock {
     type HostFuture = FutureResult<IpList, Error>;
@@ -40,6 +76,135 @@ impl Resolve for Mock2 {
     }
 }
 
+impl HostResolve for TempFail {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        err(Error::TemporaryError("backend is down".into()))
+    }
+}
+
+impl HostResolve for NotFound {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        err(Error::NameNotFound)
+    }
+}
+
+impl Resolve for NotFound {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        err(Error::NameNotFound)
+    }
+}
+
+impl HostResolve for CountingNotFound {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        err(Error::NameNotFound)
+    }
+}
+
+impl Resolve for CountingNotFound {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        err(Error::NameNotFound)
+    }
+}
+
+impl Resolve for TempFail {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        err(Error::TemporaryError("backend is down".into()))
+    }
+}
+
+impl HostResolve for Wedged {
+    type HostFuture = Empty<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        empty()
+    }
+}
+
+impl Resolve for Wedged {
+    type Future = Empty<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        empty()
+    }
+}
+
+impl HostResolve for DualStack {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        if name.as_ref().starts_with("v4only.") {
+            ok(vec!["127.0.0.1".parse().unwrap()].into())
+        } else {
+            ok(vec![
+                "127.0.0.1".parse().unwrap(),
+                "::1".parse().unwrap(),
+            ].into())
+        }
+    }
+}
+
+impl Resolve for DualStack {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["127.0.0.3:5432".parse().unwrap()][..].into())
+    }
+}
+
+impl HostResolve for Counting {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ok(vec!["127.0.0.4".parse().unwrap()].into())
+    }
+}
+
+impl Resolve for Counting {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["127.0.0.4:80".parse().unwrap()][..].into())
+    }
+}
+
+impl HostResolve for Flaky {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec!["127.0.0.5".parse().unwrap()].into())
+    }
+}
+
+impl Resolve for Flaky {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+            err(Error::TemporaryError("backend is down".into()))
+        } else {
+            ok(["127.0.0.5:80".parse().unwrap()][..].into())
+        }
+    }
+}
+
+impl HostResolve for Mapped {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec![
+            "::ffff:127.0.0.1".parse().unwrap(),
+            "::1".parse().unwrap(),
+        ].into())
+    }
+}
+
+impl Resolve for Mapped {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["[::ffff:127.0.0.1]:443".parse().unwrap()][..].into())
+    }
+}
+
 impl Resolve for Mock {
     type Future = FutureResult<Address, Error>;
     fn resolve(&self, _name: &Name) -> Self::Future {
@@ -112,6 +277,27 @@ fn test_host_suffix() {
     assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
 }
 
+#[test]
+fn test_suffix_case_insensitive() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("CONSUL", Mock2.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"x.consul".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+
+    let suffix = core.run(router.matched_suffix(
+        &"x.consul".parse().unwrap())).unwrap();
+    assert_eq!(suffix, Some("consul".to_string()));
+}
+
 #[test]
 fn test_suffix() {
     let mut core = tokio_core::reactor::Core::new().unwrap();
@@ -138,3 +324,769 @@ fn test_suffix() {
     assert_eq!(res,
         ["127.0.0.1:443".parse::<SocketAddr>().unwrap()][..].into());
 }
+
+#[test]
+fn test_temporary_error() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("consul", TempFail.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    // Read first config from a stream
+    core.turn(Some(Duration::new(0, 0)));
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"x.consul".parse().unwrap())
+    }));
+    match res {
+        Err(Error::TemporaryError(..)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_temporary_error_fallthrough() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("consul", TempFail.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .retry_fallthrough_on_temporary(true)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    // Read first config from a stream
+    core.turn(Some(Duration::new(0, 0)));
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"x.consul".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+
+    let res = core.run(lazy(|| {
+        router.resolve_auto("x.consul", 80)
+    })).unwrap();
+    assert_eq!(res,
+        ["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into());
+
+    let res = core.run(lazy(|| {
+        router.resolve(&"_xmpp-server._tcp.consul".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res,
+        ["127.0.0.1:443".parse::<SocketAddr>().unwrap()][..].into());
+}
+
+#[test]
+fn test_bypass_static() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let name = "_xmpp-server._tcp.localhost".parse().unwrap();
+    let cfg = Config::new()
+        .add_service(&name,
+            ["127.0.0.9:443".parse().unwrap()][..].into())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| router.resolve(&name))).unwrap();
+    assert_eq!(res,
+        ["127.0.0.9:443".parse::<SocketAddr>().unwrap()][..].into());
+
+    let res = core.run(lazy(|| router.resolve_bypass_static(&name)))
+        .unwrap();
+    assert_eq!(res,
+        ["127.0.0.1:443".parse::<SocketAddr>().unwrap()][..].into());
+}
+
+#[test]
+fn test_new_with_root() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new_with_root(Mock.frozen_subscriber()).done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"anything.example.org".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_matched_suffix() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("consul", Mock2.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.matched_suffix(&"db.consul".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, Some("consul".to_string()));
+
+    let res = core.run(lazy(|| {
+        router.matched_suffix(&"localhost".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, None);
+}
+
+#[test]
+fn test_disable_suffix() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("consul", Mock2.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let name = "x.consul".parse().unwrap();
+
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+
+    router.disable_suffix("consul");
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+
+    router.enable_suffix("consul");
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_longest_suffix_wins() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("com", Mock.frozen_subscriber())
+        .add_suffix("b.example.com", Mock.frozen_subscriber())
+        .add_suffix("example.com", Mock2.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let mut matched = |name: &str| {
+        core.run(router.matched_suffix(&name.parse().unwrap())).unwrap()
+    };
+    assert_eq!(matched("a.b.example.com").as_ref().map(|x| &x[..]),
+        Some("b.example.com"));
+    assert_eq!(matched("b.example.com").as_ref().map(|x| &x[..]),
+        Some("b.example.com"));
+    assert_eq!(matched("x.example.com").as_ref().map(|x| &x[..]),
+        Some("example.com"));
+    assert_eq!(matched("a.bb.example.com").as_ref().map(|x| &x[..]),
+        Some("example.com"));
+    assert_eq!(matched("example.com.org"), None);
+    assert_eq!(matched("x.com").as_ref().map(|x| &x[..]), Some("com"));
+
+    // next longest suffix is used while the longest one is disabled
+    router.disable_suffix("b.example.com");
+    assert_eq!(matched("a.b.example.com").as_ref().map(|x| &x[..]),
+        Some("example.com"));
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"a.b.example.com".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_routing_table() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()])
+        .add_host(&"example.org".parse().unwrap(),
+            vec!["127.0.0.2".parse::<IpAddr>().unwrap()])
+        .add_suffix("consul", Mock2.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let table = core.run(lazy(|| router.routing_table())).unwrap();
+    assert_eq!(table.hosts.keys().collect::<Vec<_>>(),
+        vec!["example.org", "localhost"]);
+    assert_eq!(table.hosts["localhost"],
+        vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    assert!(table.services.is_empty());
+    assert_eq!(table.suffixes.len(), 1);
+    assert!(table.suffixes["consul"].resolver.contains("Mock2"));
+    assert!(!table.suffixes["consul"].disabled);
+    assert_eq!(table.fallthrough, None);
+
+    router.disable_suffix("consul");
+    let table = core.run(lazy(|| router.routing_table())).unwrap();
+    assert!(table.suffixes["consul"].disabled);
+}
+
+#[test]
+fn test_resolve_record() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .set_fallthrough(DualStack.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let name = "db.example.org".parse().unwrap();
+
+    let res = core.run(lazy(|| {
+        router.resolve_record(&name, RecordType::A)
+    })).unwrap();
+    assert_eq!(res,
+        Record::Ip(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+
+    let res = core.run(lazy(|| {
+        router.resolve_record(&name, RecordType::AAAA)
+    })).unwrap();
+    assert_eq!(res,
+        Record::Ip(vec!["::1".parse::<IpAddr>().unwrap()].into()));
+
+    let res = core.run(lazy(|| {
+        router.resolve_record(&name, RecordType::SRV)
+    })).unwrap();
+    assert_eq!(res, Record::Srv(
+        ["127.0.0.3:5432".parse::<SocketAddr>().unwrap()][..].into()));
+
+    let res = core.run(lazy(|| {
+        router.resolve_record(&"v4only.example.org".parse().unwrap(),
+            RecordType::AAAA)
+    }));
+    match res {
+        Err(Error::NameNotFound) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_warmup_on_start() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let cfg = Config::new()
+        .add_suffix("consul", Counting(counter.clone()).frozen_subscriber())
+        .warmup_on_start(true)
+        .done();
+    let _router = Router::from_config(&cfg, &handle);
+    core.run(Timeout::new(Duration::from_millis(10), &handle).unwrap())
+        .unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    let cfg = Config::new()
+        .add_suffix("consul", Counting(counter.clone()).frozen_subscriber())
+        .done();
+    let _router = Router::from_config(&cfg, &handle);
+    core.run(Timeout::new(Duration::from_millis(10), &handle).unwrap())
+        .unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_prefix() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("internal", Mock.frozen_subscriber())
+        .add_prefix("db-", Mock2.frozen_subscriber())
+        .add_prefix("db-replica", DualStack.frozen_subscriber())
+        .add_prefix("", Mock2.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"db-primary.internal".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"db-replica.internal".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec![
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        "::1".parse().unwrap(),
+    ].into());
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"web.internal".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_exact_name() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let auth = "auth.example.com".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_suffix("example.com", Mock.frozen_subscriber())
+        .add_name(&auth, Mock2.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| router.resolve_host(&auth))).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&auth).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into()));
+
+    let res = core.run(lazy(|| {
+        router.resolve_host(&"www.example.com".parse().unwrap())
+    })).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_normalize_mapped_v4() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "mapped.example.org".parse().unwrap();
+
+    let cfg = Config::new()
+        .set_fallthrough(Mapped.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec![
+        "::ffff:127.0.0.1".parse::<IpAddr>().unwrap(),
+        "::1".parse().unwrap(),
+    ].into());
+
+    let cfg = Config::new()
+        .set_fallthrough(Mapped.frozen_subscriber())
+        .normalize_mapped_v4(true)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec![
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        "::1".parse().unwrap(),
+    ].into());
+
+    let res = core.run(lazy(|| router.resolve(&name))).unwrap();
+    assert_eq!(res,
+        ["127.0.0.1:443".parse::<SocketAddr>().unwrap()][..].into());
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&name).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0, Some(vec![
+        "127.0.0.1".parse::<IpAddr>().unwrap(),
+        "::1".parse().unwrap(),
+    ].into()));
+}
+
+#[test]
+fn test_fallthrough_chain() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "example.org".parse().unwrap();
+
+    let cfg = Config::new()
+        .set_fallthrough(NotFound.frozen_subscriber())
+        .push_fallthrough(Mock2.frozen_subscriber())
+        .push_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+
+    let res = core.run(lazy(|| router.resolve(&name))).unwrap();
+    assert_eq!(res,
+        ["127.0.0.2:443".parse::<SocketAddr>().unwrap()][..].into());
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&name).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into()));
+
+    // errors other than NameNotFound aren't masked
+    let cfg = Config::new()
+        .push_fallthrough(TempFail.frozen_subscriber())
+        .push_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    match core.run(lazy(|| router.resolve_host(&name))) {
+        Err(Error::TemporaryError(..)) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+
+    // last resolver's error is returned
+    let cfg = Config::new()
+        .push_fallthrough(NotFound.frozen_subscriber())
+        .push_fallthrough(NotFound.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    match core.run(lazy(|| router.resolve_host(&name))) {
+        Err(Error::NameNotFound) => {}
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn test_remove_host() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(Mock.frozen_subscriber());
+    cfg.add_host(&name, vec!["127.0.0.2".parse().unwrap()]);
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.2".parse::<IpAddr>().unwrap()].into());
+
+    cfg.remove_host(&name);
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+
+    cfg.add_host(&name, vec!["127.0.0.2".parse().unwrap()]);
+    cfg.add_suffix("localhost", Mock2.frozen_subscriber());
+    cfg.clear();
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+    let res = core.run(lazy(|| router.resolve_host(&name))).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+}
+
+#[test]
+fn test_resolve_traced() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let svc = "_http._tcp.static".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_suffix("consul", Mock2.frozen_subscriber())
+        .add_service(&svc, ["127.0.0.5:80".parse().unwrap()][..].into())
+        .set_fallthrough(DualStack.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let (addr, trace) = core.run(lazy(|| {
+        router.resolve_traced(&"_db._tcp.db.consul".parse().unwrap())
+    })).unwrap();
+    assert_eq!(addr,
+        ["127.0.0.2:443".parse::<SocketAddr>().unwrap()][..].into());
+    assert_eq!(trace.route, Route::Suffix("consul".into()));
+    assert!(trace.resolver.unwrap().contains("Mock2"));
+
+    let (_, trace) = core.run(lazy(|| {
+        router.resolve_traced(&"_db._tcp.example.org".parse().unwrap())
+    })).unwrap();
+    assert_eq!(trace.route, Route::Fallthrough);
+    assert!(trace.resolver.unwrap().contains("DualStack"));
+
+    let (addr, trace) = core.run(lazy(|| {
+        router.resolve_traced(&svc)
+    })).unwrap();
+    assert_eq!(addr,
+        ["127.0.0.5:80".parse::<SocketAddr>().unwrap()][..].into());
+    assert_eq!(trace.route, Route::Static);
+    assert_eq!(trace.resolver, None);
+}
+
+#[test]
+fn test_trailing_dot_suffix() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("consul", Mock2.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    for name in &["foo.consul", "foo.consul."] {
+        let res = core.run(router.resolve_auto(*name, 80)).unwrap();
+        assert_eq!(res,
+            ["127.0.0.2:80".parse::<SocketAddr>().unwrap()][..].into());
+    }
+    for name in &["_http._tcp.foo.consul", "_http._tcp.foo.consul."] {
+        let res = core.run(router.resolve_auto(*name, 80)).unwrap();
+        assert_eq!(res,
+            ["127.0.0.2:443".parse::<SocketAddr>().unwrap()][..].into());
+    }
+}
+
+#[test]
+fn test_resolve_retry() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let cfg = Config::new()
+        .set_fallthrough(Flaky { calls: calls.clone(), failures: 2 }
+            .frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_retry(
+        &"_http._tcp.example.org".parse().unwrap(),
+        3, Duration::from_millis(10), &handle));
+    assert_eq!(res.unwrap(),
+        ["127.0.0.5:80".parse::<SocketAddr>().unwrap()][..].into());
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_resolve_retry_exhausted() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let cfg = Config::new()
+        .set_fallthrough(Flaky { calls: calls.clone(), failures: 100 }
+            .frozen_subscriber())
+        .add_suffix("missing", NotFound.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_retry(
+        &"_http._tcp.example.org".parse().unwrap(),
+        3, Duration::from_millis(10), &handle));
+    match res {
+        Err(Error::TemporaryError(_)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // name not found is not retried
+    let res = core.run(router.resolve_retry(
+        &"_http._tcp.x.missing".parse().unwrap(),
+        3, Duration::from_secs(100), &handle));
+    match res {
+        Err(Error::NameNotFound) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_resolve_many() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.2".parse().unwrap()])
+        .add_suffix("missing", NotFound.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_many(
+        vec!["localhost:8080", "_bad._name:80", "x.missing", "example.org"],
+        80)).unwrap();
+    assert_eq!(res.len(), 4);
+    assert_eq!(res[0].as_ref().unwrap(),
+        &["127.0.0.2:8080".parse::<SocketAddr>().unwrap()][..].into());
+    match res[1] {
+        Err(Error::InvalidName(..)) => {}
+        ref other => panic!("unexpected result {:?}", other),
+    }
+    match res[2] {
+        Err(Error::NameNotFound) => {}
+        ref other => panic!("unexpected result {:?}", other),
+    }
+    assert_eq!(res[3].as_ref().unwrap(),
+        &["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into());
+}
+
+#[test]
+fn test_resolve_hosts_many() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_host(&"a.localhost".parse().unwrap(),
+                  vec!["127.0.0.2".parse().unwrap()])
+        .add_suffix("missing", NotFound.frozen_subscriber())
+        .add_suffix("down", TempFail.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let names = ["a.localhost", "x.missing", "example.org", "example.net"]
+        .iter().map(|n| n.parse::<Name>().unwrap()).collect::<Vec<_>>();
+    let res = core.run(router.resolve_hosts_many(&names)).unwrap();
+    assert_eq!(res,
+        IpList::parse_list(&["127.0.0.1", "127.0.0.2"]).unwrap());
+
+    let names = vec!["example.org".parse::<Name>().unwrap(),
+                     "x.down".parse().unwrap()];
+    match core.run(router.resolve_hosts_many(names)) {
+        Err(Error::TemporaryError(..)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_resolution_loop() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let (router, up) = Router::updating_config(&Config::new().done(),
+                                               &handle);
+    // names of the suffix are routed back to the same router
+    up.update(&Config::new()
+        .add_suffix("loop", router.clone())
+        .max_resolution_depth(4)
+        .done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    let name = "x.loop".parse::<Name>().unwrap();
+    match core.run(router.resolve_host(&name)) {
+        Err(Error::TemporaryError(ref e)) => {
+            assert_eq!(e.to_string(), "resolution loop");
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    match core.run(router.resolve(&name)) {
+        Err(Error::TemporaryError(..)) => {}
+        other => panic!("unexpected result {:?}", other),
+    }
+    // subscription never gets a value, but it doesn't spin either
+    let mut stream = router.subscribe(&name);
+    for _ in 0..10 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    let poll = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert!(poll.unwrap().is_not_ready());
+}
+
+#[test]
+fn test_resolve_timeout() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("wedged", Wedged.frozen_subscriber())
+        .set_fallthrough(Mock.frozen_subscriber())
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(router.resolve_auto("example.wedged", 80)
+        .timeout(Duration::from_millis(10), &handle));
+    match res {
+        Err(Error::TemporaryError(..)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+    let res = core.run(
+        router.resolve_host(&"example.wedged".parse().unwrap())
+        .timeout(Duration::from_millis(10), &handle));
+    match res {
+        Err(Error::TemporaryError(..)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let res = core.run(router.resolve_auto("example.org", 80)
+        .timeout(Duration::from_millis(10), &handle));
+    assert_eq!(res.unwrap(),
+        ["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into());
+}
+
+#[test]
+fn test_negative_cache() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(CountingNotFound(counter.clone())
+            .frozen_subscriber())
+        .negative_cache_ttl(Duration::from_millis(100));
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    for _ in 0..3 {
+        match core.run(router.resolve_auto("missing.org", 80)) {
+            Err(Error::NameNotFound) => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // service lookup isn't cached by the host one
+    let res = core.run(router.resolve(&"missing.org".parse().unwrap()));
+    assert!(res.is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+    // new config clears the cache
+    cfg.restart_delay(Duration::from_millis(200));
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+    let res = core.run(router.resolve_auto("missing.org", 80));
+    assert!(res.is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+
+    // entry expires
+    core.run(Timeout::new(Duration::from_millis(150), &handle).unwrap())
+        .unwrap();
+    let res = core.run(router.resolve_auto("missing.org", 80));
+    assert!(res.is_err());
+    assert_eq!(counter.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn test_positive_cache() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let counter = Arc::new(AtomicUsize::new(0));
+    let addr: Address = ["127.0.0.4:80".parse().unwrap()][..].into();
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(Counting(counter.clone()).frozen_subscriber())
+        .positive_cache_ttl(Duration::from_secs(3600));
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(join_all((0..10)
+        .map(|_| router.resolve_auto("example.org", 80))
+        .collect::<Vec<_>>())).unwrap();
+    assert_eq!(res, vec![addr.clone(); 10]);
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    let res = core.run(router.resolve_auto("example.org", 80)).unwrap();
+    assert_eq!(res, addr);
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // config update clears the cache
+    cfg.restart_delay(Duration::from_millis(200));
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    // config update in the middle of the request, waiters are replied
+    // but the value isn't cached
+    let pending = (0..3)
+        .map(|_| router.resolve_auto("example.org", 80))
+        .collect::<Vec<_>>();
+    cfg.restart_delay(Duration::from_millis(300));
+    up.update(&cfg.done());
+    let res = core.run(join_all(pending)).unwrap();
+    assert_eq!(res, vec![addr.clone(); 3]);
+    assert_eq!(counter.load(Ordering::SeqCst), 2);
+
+    let res = core.run(router.resolve_auto("example.org", 80)).unwrap();
+    assert_eq!(res, addr);
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+    let res = core.run(router.resolve_auto("example.org", 80)).unwrap();
+    assert_eq!(res, addr);
+    assert_eq!(counter.load(Ordering::SeqCst), 3);
+}
@@ -0,0 +1,120 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::cell::Cell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use abstract_ns::{IpList, Name, Error};
+use abstract_ns::{HostResolve, Resolve, HostSubscribe, Subscribe};
+use futures::{Future, Stream};
+use futures::future::{lazy, empty, Empty};
+use ns_router::{Config, Router, ManualTimer, Spawn, Timer};
+use ns_router::timer::Sleep;
+use tokio_core::reactor::Handle;
+
+
+/// Spawns on the tokio-core reactor, but uses a manual timer
+#[derive(Debug, Clone)]
+struct TestRuntime {
+    handle: Handle,
+    timer: ManualTimer,
+    spawned: Rc<Cell<usize>>,
+}
+
+#[derive(Debug)]
+struct Never;
+
+impl Spawn for TestRuntime {
+    fn spawn(&self, future: Box<Future<Item=(), Error=()>>) {
+        self.spawned.set(self.spawned.get() + 1);
+        self.handle.spawn(future);
+    }
+}
+
+impl Timer for TestRuntime {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        self.timer.sleep(duration)
+    }
+}
+
+impl Resolve for Never {
+    type Future = Empty<abstract_ns::Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        empty()
+    }
+}
+
+impl HostResolve for Never {
+    type HostFuture = Empty<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        empty()
+    }
+}
+
+impl Subscribe for Never {
+    type Error = Error;
+    type Stream = futures::stream::Empty<abstract_ns::Address, Error>;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        futures::stream::empty()
+    }
+}
+
+impl HostSubscribe for Never {
+    type HostError = Error;
+    type HostStream = futures::stream::Empty<IpList, Error>;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        futures::stream::empty()
+    }
+}
+
+fn runtime(handle: &Handle) -> TestRuntime {
+    TestRuntime {
+        handle: handle.clone(),
+        timer: ManualTimer::new(),
+        spawned: Rc::new(Cell::new(0)),
+    }
+}
+
+#[test]
+fn custom_runtime() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let runtime = runtime(&core.handle());
+
+    let cfg = Config::new()
+        .add_host(&"localhost".parse().unwrap(),
+                  vec!["127.0.0.1".parse().unwrap()])
+        .done();
+    let router = Router::from_config(&cfg, &runtime);
+    assert_eq!(runtime.spawned.get(), 1);
+
+    let res = core.run(router.resolve_host(&"localhost".parse().unwrap()));
+    assert_eq!(res.unwrap(), IpList::parse_list(&["127.0.0.1"]).unwrap());
+}
+
+#[test]
+fn runtime_timer() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let runtime = runtime(&core.handle());
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .add_suffix("slow.example", Never)
+        .done();
+    let router = Router::from_config(&cfg, &runtime);
+
+    let mut stream = router.subscribe_many(
+        &["127.0.0.1:80", "x.slow.example"], 80);
+    for _ in 0..10 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    let poll = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert!(poll.unwrap().is_not_ready());
+
+    runtime.timer.advance(Duration::from_secs(10));
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+}
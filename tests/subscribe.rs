@@ -3,16 +3,22 @@ extern crate futures;
 extern crate ns_router;
 extern crate tokio_core;
 
+use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 use futures::{lazy};
 use futures::future::{Future, Empty, IntoStream, empty};
-use futures::future::{FutureResult, ok};
+use futures::future::{FutureResult, ok, err};
 use futures::stream::{once, Stream, Chain, Once};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver};
 use abstract_ns::{HostSubscribe, Subscribe, Name, Address, IpList, Error};
 use abstract_ns::{Resolve, HostResolve};
-use ns_router::{Config, Router};
+use abstract_ns::addr::Builder;
+use ns_router::{Config, Router, AddressEqMode, AutoName, BoxedSubscriber};
+use ns_router::future::WeightedPicker;
 
 
 #[derive(Debug)]
@@ -52,6 +58,50 @@ impl Subscribe for Mock {
     }
 }
 
+/// Counts polls of the subscription stream
+#[derive(Debug)]
+struct PollCount(Arc<AtomicUsize>);
+
+struct CountedStream<S>(S, Arc<AtomicUsize>);
+
+impl<S: Stream> Stream for CountedStream<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+    fn poll(&mut self) -> futures::Poll<Option<S::Item>, S::Error> {
+        self.1.fetch_add(1, Ordering::SeqCst);
+        self.0.poll()
+    }
+}
+
+impl HostResolve for PollCount {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        Mock.resolve_host(name)
+    }
+}
+
+impl Resolve for PollCount {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        Mock.resolve(name)
+    }
+}
+
+impl HostSubscribe for PollCount {
+    type HostStream = <Mock as HostSubscribe>::HostStream;
+    type HostError = Error;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        Mock.subscribe_host(name)
+    }
+}
+
+impl Subscribe for PollCount {
+    type Stream = CountedStream<<Mock as Subscribe>::Stream>;
+    type Error = Error;
+    fn subscribe(&self, name: &Name) -> Self::Stream {
+        CountedStream(Mock.subscribe(name), self.0.clone())
+    }
+}
 
 
 #[test]
@@ -198,3 +248,1071 @@ fn test_add_and_override_fallback_service() {
     assert_eq!(res.0,
         Some(["127.0.0.2:80".parse::<SocketAddr>().unwrap()][..].into()));
 }
+
+#[test]
+fn test_subscribe_with() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&"_http._tcp.localhost".parse().unwrap(),
+              ["127.0.0.2:80".parse::<SocketAddr>().unwrap()][..].into());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_with(
+            &"_http._tcp.localhost".parse().unwrap(), Mock).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:1234".parse::<SocketAddr>().unwrap()][..].into()));
+
+    // config update doesn't reroute the subscription
+    cfg.set_fallthrough(Mock);
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    let mut stream = res.1;
+    let res = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert_eq!(res, Ok(futures::Async::NotReady));
+}
+
+#[derive(Debug, Clone)]
+struct Counter(Arc<AtomicUsize>);
+
+impl HostResolve for Counter {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec!["127.0.0.1".parse().unwrap()].into())
+    }
+}
+
+impl Resolve for Counter {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["127.0.0.1:443".parse().unwrap()][..].into())
+    }
+}
+
+impl HostSubscribe for Counter {
+    type HostStream = Chain<Once<IpList, Error>,
+                            IntoStream<Empty<IpList, Error>>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        once(Ok(vec!["127.0.0.1".parse().unwrap()].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl Subscribe for Counter {
+    type Stream = Chain<Once<Address, Error>,
+                            IntoStream<Empty<Address, Error>>>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        once(Ok(vec!["127.0.0.1:1234".parse().unwrap()][..].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+#[test]
+fn test_unrelated_update_keeps_subscription() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(Counter(counter.clone()));
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&"localhost".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    cfg.add_host(&"example.org".parse().unwrap(),
+            vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    cfg.add_host(&"localhost".parse().unwrap(),
+            vec!["127.0.0.3".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+    let res = core.run(res.1.into_future()).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.3".parse::<IpAddr>().unwrap()].into()));
+}
+
+#[test]
+fn test_unrelated_update_no_static_duplicate() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"localhost".parse().unwrap(),
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&"localhost".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+
+    cfg.add_host(&"example.org".parse().unwrap(),
+            vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    let mut stream = res.1;
+    let res = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert_eq!(res, Ok(futures::Async::NotReady));
+}
+
+#[test]
+fn test_primary_changes() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.1:80", "127.0.0.2:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&name).primary_changes().into_future()
+    })).unwrap();
+    assert_eq!(res.0, Some("127.0.0.1:80".parse().unwrap()));
+
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.1:80", "127.0.0.3:80"]).unwrap());
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    let mut stream = res.1;
+    let res = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert_eq!(res, Ok(futures::Async::NotReady));
+
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.4:80"]).unwrap());
+    up.update(&cfg.done());
+    let res = core.run(stream.into_future()).unwrap();
+    assert_eq!(res.0, Some("127.0.0.4:80".parse().unwrap()));
+}
+
+#[test]
+fn test_round_robin() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name, Address::parse_list(
+        &["127.0.0.1:80", "127.0.0.2:80", "127.0.0.3:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let mut rr = router.subscribe(&name).round_robin();
+    let mut next = |core: &mut tokio_core::reactor::Core| {
+        for _ in 0..10 {
+            core.turn(Some(Duration::new(0, 0)));
+        }
+        core.run(lazy(|| Ok::<_, ()>(rr.pick()))).unwrap()
+            .map(|a| a.to_string())
+    };
+    assert_eq!(next(&mut core).unwrap(), "127.0.0.1:80");
+    assert_eq!(next(&mut core).unwrap(), "127.0.0.2:80");
+
+    // new set is shorter than the current position
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.4:80", "127.0.0.5:80"]).unwrap());
+    up.update(&cfg.done());
+    assert_eq!(next(&mut core).unwrap(), "127.0.0.4:80");
+    assert_eq!(next(&mut core).unwrap(), "127.0.0.5:80");
+    assert_eq!(next(&mut core).unwrap(), "127.0.0.4:80");
+}
+
+fn count_picks(core: &mut tokio_core::reactor::Core,
+    picker: &mut WeightedPicker, n: usize)
+    -> HashMap<String, usize>
+{
+    for _ in 0..10 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    core.run(lazy(|| {
+        let mut counts = HashMap::new();
+        for _ in 0..n {
+            let addr = picker.pick().map(|a| a.to_string())
+                .unwrap_or("none".to_string());
+            *counts.entry(addr).or_insert(0) += 1;
+        }
+        Ok::<_, ()>(counts)
+    })).unwrap()
+}
+
+#[test]
+fn test_weighted_picker() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut addr = Builder::new();
+    addr.add_addresses(&[
+        (1, "127.0.0.1:80".parse().unwrap()),
+        (3, "127.0.0.2:80".parse().unwrap()),
+        (0, "127.0.0.4:80".parse().unwrap()),
+    ]);
+    addr.add_addresses(&[(100, "127.0.0.3:80".parse().unwrap())]);
+    let cfg = Config::new().add_service(&name, addr.into_address()).done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let mut picker = router.subscribe(&name).weighted_picker();
+    let counts = count_picks(&mut core, &mut picker, 1000);
+    // neither fallback priority nor zero weight is picked
+    assert_eq!(counts.len(), 2);
+    assert!(counts["127.0.0.2:80"] > counts["127.0.0.1:80"]);
+}
+
+#[test]
+fn test_weighted_picker_zero_weights() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name, Address::parse_list(&[
+        "127.0.0.1:80", "127.0.0.2:80",
+    ]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let mut picker = router.subscribe(&name).weighted_picker();
+    let counts = count_picks(&mut core, &mut picker, 1000);
+    assert_eq!(counts.len(), 2);
+
+    cfg.add_service(&name, Address::from(&[][..]));
+    up.update(&cfg.done());
+    let counts = count_picks(&mut core, &mut picker, 10);
+    assert_eq!(counts["none"], 10);
+}
+
+#[test]
+fn test_subscribe_seeded() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_service(&name,
+            Address::parse_list(&["127.0.0.2:80"]).unwrap())
+        .seed_grace_period(Duration::from_millis(200))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let seed = Address::parse_list(&["127.0.0.1:80"]).unwrap();
+
+    let res = core.run(lazy(|| {
+        router.subscribe_seeded(&name, seed).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.1:80"]).unwrap()));
+
+    let res = core.run(res.1.into_future()).unwrap();
+    assert_eq!(res.0, Some(Address::parse_list(
+        &["127.0.0.1:80", "127.0.0.2:80"]).unwrap()));
+
+    let res = core.run(res.1.into_future()).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.2:80"]).unwrap()));
+}
+
+#[test]
+fn test_fallible_shutdown() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_service(&name, Address::parse_list(&["127.0.0.1:80"]).unwrap())
+        .done();
+    let (router, up) = Router::updating_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_fallible(&name).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.1:80"]).unwrap()));
+
+    drop(up);
+    match core.run(res.1.into_future()) {
+        Err((Error::TemporaryError(_), stream)) => {
+            let res = core.run(stream.into_future())
+                .map_err(|(e, _)| e).unwrap();
+            assert!(res.0.is_none());
+        }
+        Err((e, _)) => panic!("unexpected error {}", e),
+        Ok((value, _)) => panic!("unexpected value {:?}", value),
+    }
+}
+
+#[test]
+fn test_fallible_closed_without_shutdown() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_service(&name, Address::parse_list(&["127.0.0.1:80"]).unwrap())
+        .done();
+    let (router, _up) = Router::updating_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_fallible(&name).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(Address::parse_list(&["127.0.0.1:80"]).unwrap()));
+
+    // event loop is dropped while config stream is still open
+    drop(core);
+    let mut rest = res.1.wait();
+    assert!(rest.next().is_none());
+}
+
+#[derive(Debug)]
+struct Feed(Mutex<Option<UnboundedReceiver<IpList>>>);
+
+impl HostResolve for Feed {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec!["127.0.0.1".parse().unwrap()].into())
+    }
+}
+
+impl Resolve for Feed {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["127.0.0.1:443".parse().unwrap()][..].into())
+    }
+}
+
+impl HostSubscribe for Feed {
+    type HostStream = Box<Stream<Item=IpList, Error=Error>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        let rx = self.0.lock().unwrap().take().expect("subscribed once");
+        Box::new(rx.map_err(|()| unreachable!()))
+    }
+}
+
+impl Subscribe for Feed {
+    type Stream = Chain<Once<Address, Error>,
+                            IntoStream<Empty<Address, Error>>>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        once(Ok(vec!["127.0.0.1:1234".parse().unwrap()][..].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+#[test]
+fn test_set_equality() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let (tx, rx) = unbounded();
+    let ip = |x: &str| x.parse::<IpAddr>().unwrap();
+
+    let cfg = Config::new()
+        .set_fallthrough(Feed(Mutex::new(Some(rx))))
+        .address_eq(AddressEqMode::SetEquality)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    tx.unbounded_send(vec![ip("127.0.0.1"), ip("127.0.0.2")].into())
+        .unwrap();
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&"localhost".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res.0, Some(vec![ip("127.0.0.1"), ip("127.0.0.2")].into()));
+
+    // same addresses in different order
+    tx.unbounded_send(vec![ip("127.0.0.2"), ip("127.0.0.1")].into())
+        .unwrap();
+    core.turn(Some(Duration::new(0, 0)));
+    let mut stream = res.1;
+    let res = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert_eq!(res, Ok(futures::Async::NotReady));
+
+    tx.unbounded_send(vec![ip("127.0.0.3")].into()).unwrap();
+    let res = core.run(stream.into_future()).unwrap();
+    assert_eq!(res.0, Some(vec![ip("127.0.0.3")].into()));
+}
+
+#[derive(Debug)]
+struct SubscribeOnly;
+
+impl HostResolve for SubscribeOnly {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        err(Error::NameNotFound)
+    }
+}
+
+impl Resolve for SubscribeOnly {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        err(Error::NameNotFound)
+    }
+}
+
+impl HostSubscribe for SubscribeOnly {
+    type HostStream = Chain<Once<IpList, Error>,
+                            IntoStream<Empty<IpList, Error>>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        once(Ok(vec!["127.0.0.5".parse().unwrap()].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl Subscribe for SubscribeOnly {
+    type Stream = Chain<Once<Address, Error>,
+                            IntoStream<Empty<Address, Error>>>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        once(Ok(vec!["127.0.0.5:1234".parse().unwrap()][..].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+#[test]
+fn test_first_value() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .set_fallthrough(SubscribeOnly)
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| router.first_value(&name))).unwrap();
+    assert_eq!(res,
+        ["127.0.0.5:1234".parse::<SocketAddr>().unwrap()][..].into());
+}
+
+#[test]
+fn test_canonical() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.2:80", "127.0.0.1:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&name).canonical().into_future()
+    })).unwrap();
+    assert_eq!(res.0, Some(vec![
+        "127.0.0.1:80".parse().unwrap(),
+        "127.0.0.2:80".parse().unwrap(),
+    ]));
+
+    // reordered and duplicated
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.1:80", "127.0.0.2:80", "127.0.0.1:80"])
+        .unwrap());
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    let mut stream = res.1;
+    let res = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert_eq!(res, Ok(futures::Async::NotReady));
+
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.3:80", "127.0.0.1:80"]).unwrap());
+    up.update(&cfg.done());
+    let res = core.run(stream.into_future()).unwrap();
+    assert_eq!(res.0, Some(vec![
+        "127.0.0.1:80".parse().unwrap(),
+        "127.0.0.3:80".parse().unwrap(),
+    ]));
+}
+
+#[test]
+fn test_address_rewriter() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_service(&name,
+            Address::parse_list(&["10.0.0.1:80", "10.0.0.2:80"]).unwrap())
+        .set_address_rewriter(Arc::new(|_| {
+            Address::parse_list(&["127.0.0.1:15001"]).unwrap()
+        }))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+    let sidecar = Address::parse_list(&["127.0.0.1:15001"]).unwrap();
+
+    let res = core.run(router.resolve(&name)).unwrap();
+    assert_eq!(res, sidecar);
+    let res = core.run(router.resolve_auto("_http._tcp.localhost", 80))
+        .unwrap();
+    assert_eq!(res, sidecar);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&name).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0, Some(sidecar.clone()));
+
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&["_http._tcp.localhost"], 80).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0, Some(sidecar.clone()));
+}
+
+#[test]
+fn test_scan_state() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.1:80", "127.0.0.2:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let stream = router.subscribe(&name)
+        .scan_state(Vec::<SocketAddr>::new(), |seen, addr| {
+            for set in addr.iter() {
+                for sa in set.addresses() {
+                    if !seen.contains(&sa) {
+                        seen.push(sa);
+                    }
+                }
+            }
+        })
+        .map(|seen| seen.len());
+    let res = core.run(stream.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0, Some(2));
+
+    cfg.add_service(&name,
+        Address::parse_list(&["127.0.0.2:80", "127.0.0.3:80"]).unwrap());
+    up.update(&cfg.done());
+    let res = core.run(res.1.into_future()).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0, Some(3));
+}
+
+#[test]
+fn test_backpressure_stats() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name, Address::parse_list(&["127.0.0.1:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    // consumer never polls the stream
+    let _stream = router.subscribe(&name);
+    core.turn(Some(Duration::new(0, 0)));
+    assert_eq!(router.backpressure_stats().dropped_updates, 0);
+
+    for i in 2..5 {
+        cfg.add_service(&name,
+            Address::parse_list(&[format!("127.0.0.{}:80", i)]).unwrap());
+        up.update(&cfg.done());
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    let stats = router.backpressure_stats();
+    assert_eq!(stats.dropped_updates, 3);
+    assert_eq!(stats.lagging_subscriptions, 1);
+}
+
+#[test]
+fn test_skip_equal_updates() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let polls = Arc::new(AtomicUsize::new(0));
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(PollCount(polls.clone()));
+    cfg.skip_equal_updates(true);
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&"localhost".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:1234".parse::<SocketAddr>().unwrap()][..].into()));
+    core.turn(Some(Duration::new(0, 0)));
+    let before = polls.load(Ordering::SeqCst);
+
+    // subscription isn't restarted, so the stream isn't polled again
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+    core.turn(Some(Duration::new(0, 0)));
+    assert_eq!(polls.load(Ordering::SeqCst), before);
+
+    cfg.add_host(&"example.org".parse().unwrap(),
+        vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+    core.turn(Some(Duration::new(0, 0)));
+    assert!(polls.load(Ordering::SeqCst) > before);
+}
+
+#[test]
+fn test_poll_fairness() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+    let host = "localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.poll_fairness(1);
+    cfg.add_host(&host, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    cfg.add_service(&name, Address::parse_list(&["127.0.0.1:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let mut streams = (0..20).map(|_| router.subscribe(&name))
+        .collect::<Vec<_>>();
+    core.turn(Some(Duration::new(0, 0)));
+    for s in &mut streams {
+        let res = core.run(lazy(|| Ok::<_, ()>(s.poll()))).unwrap();
+        assert!(matches_ready(res));
+    }
+
+    cfg.add_service(&name, Address::parse_list(&["127.0.0.2:80"]).unwrap());
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    // request is served while subscriptions are still being restarted
+    let res = core.run(lazy(|| router.resolve_host(&host))).unwrap();
+    assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into());
+    let updated = core.run(lazy(|| {
+        Ok::<_, ()>(streams.iter_mut()
+            .map(|s| matches_ready(s.poll()))
+            .collect::<Vec<_>>())
+    })).unwrap();
+    assert!(updated.iter().any(|x| !x));
+
+    // but eventually all of them are updated
+    for _ in 0..40 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    for (s, _) in streams.iter_mut().zip(updated).filter(|&(_, u)| !u) {
+        let res = core.run(lazy(|| Ok::<_, ()>(s.poll()))).unwrap();
+        assert!(matches_ready(res));
+    }
+}
+
+fn matches_ready<T, E>(res: Result<futures::Async<Option<T>>, E>) -> bool {
+    match res {
+        Ok(futures::Async::Ready(Some(_))) => true,
+        _ => false,
+    }
+}
+
+/// Yields addresses sent to the channel, only the first subscription is
+/// connected to the channel
+#[derive(Debug)]
+struct Channel(Mutex<Option<UnboundedReceiver<Address>>>);
+
+impl Subscribe for Channel {
+    type Stream = Box<Stream<Item=Address, Error=Error> + Send>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        match self.0.lock().unwrap().take() {
+            Some(rx) => Box::new(rx.map_err(|()| unreachable!())),
+            None => Box::new(empty().into_stream()),
+        }
+    }
+}
+
+#[test]
+fn test_subscribe_redundant() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let router = Router::from_config(&Config::new().done(), &handle);
+    let (tx1, rx1) = unbounded();
+    let (tx2, rx2) = unbounded();
+    tx1.unbounded_send(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..]
+        .into()).unwrap();
+    tx2.unbounded_send(["127.0.0.2:80".parse::<SocketAddr>().unwrap()][..]
+        .into()).unwrap();
+
+    let (value, stream) = core.run(lazy(|| {
+        router.subscribe_redundant(&"_http._tcp.localhost".parse().unwrap(),
+            vec![Box::new(Channel(Mutex::new(Some(rx1)))) as BoxedSubscriber,
+                 Box::new(Channel(Mutex::new(Some(rx2))))])
+        .into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(value, Some(Address::parse_list(&[
+        "127.0.0.1:80", "127.0.0.2:80"]).unwrap()));
+
+    // second resolver is gone, its addresses are removed
+    drop(tx2);
+    let (value, _stream) = core.run(stream.into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(value, Some(Address::parse_list(&["127.0.0.1:80"]).unwrap()));
+    drop(tx1);
+}
+
+#[test]
+fn test_subscribe_auto() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_host(&"localhost".parse().unwrap(),
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    cfg.add_service(&"_http._tcp.localhost".parse().unwrap(),
+              ["127.0.0.3:80".parse::<SocketAddr>().unwrap()][..].into());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_auto("localhost:8080", 80).into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:8080".parse::<SocketAddr>().unwrap()][..].into()));
+
+    cfg.add_host(&"localhost".parse().unwrap(),
+            vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+
+    let res = core.run(res.1.into_future()).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.2:8080".parse::<SocketAddr>().unwrap()][..].into()));
+
+    let res = core.run(lazy(|| {
+        router.subscribe_auto("_http._tcp.localhost", 80).into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.3:80".parse::<SocketAddr>().unwrap()][..].into()));
+
+    let res = core.run(lazy(|| {
+        router.subscribe_auto("127.0.0.4", 80).into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.4:80".parse::<SocketAddr>().unwrap()][..].into()));
+}
+
+#[test]
+fn test_subscribe_literal_address() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    // no static entries and no resolvers
+    let router = Router::from_config(&Config::new().done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&"127.0.0.1".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+
+    let res = core.run(lazy(|| {
+        router.subscribe_auto(AutoName::HostPort("127.0.0.2", 8080), 80)
+        .into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.2:8080".parse::<SocketAddr>().unwrap()][..].into()));
+
+    // the way addresses come from a config file
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&["127.0.0.3:8080"], 80).into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.3:8080".parse::<SocketAddr>().unwrap()][..].into()));
+}
+
+#[test]
+fn test_literal_address_no_duplicate_on_update() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let mut cfg = Config::new();
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_host(&"127.0.0.1".parse().unwrap()).into_future()
+    })).unwrap();
+    assert_eq!(res.0,
+        Some(vec!["127.0.0.1".parse::<IpAddr>().unwrap()].into()));
+
+    cfg.add_host(&"example.org".parse().unwrap(),
+            vec!["127.0.0.2".parse::<IpAddr>().unwrap()]);
+    up.update(&cfg.done());
+    core.turn(Some(Duration::new(0, 0)));
+
+    let mut stream = res.1;
+    let res = core.run(lazy(|| Ok::<_, ()>(stream.poll()))).unwrap();
+    assert_eq!(res, Ok(futures::Async::NotReady));
+}
+
+#[test]
+fn test_shutdown() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&"_http._tcp.localhost".parse().unwrap(),
+              ["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into());
+    let (router, _up) = Router::updating_config(&cfg.done(), &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&"_http._tcp.localhost".parse().unwrap())
+        .into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+
+    router.shutdown();
+    let res = core.run(res.1.into_future()).map_err(|_| ()).unwrap();
+    assert_eq!(res.0, None);
+
+    let res = core.run(
+        router.resolve(&"_http._tcp.localhost".parse().unwrap()));
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_fallible_router_shutdown() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let cfg = Config::new()
+        .add_service(&name, Address::parse_list(&["127.0.0.1:80"]).unwrap())
+        .done();
+    let (router, _up) = Router::updating_config(&cfg, &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe_fallible(&name).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert!(res.0.is_some());
+
+    router.shutdown();
+    match core.run(res.1.into_future()) {
+        Err((Error::TemporaryError(_), _)) => {}
+        Err((e, _)) => panic!("unexpected error {}", e),
+        Ok((value, _)) => panic!("unexpected value {:?}", value),
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&"_http._tcp.localhost".parse().unwrap(),
+              ["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into());
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let stats = core.run(router.stats()).unwrap();
+    assert_eq!(stats.resolves, 0);
+    assert_eq!(stats.subscriptions, 0);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&"_http._tcp.localhost".parse().unwrap())
+        .into_future()
+    })).map_err(|_| ()).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+    let stats = core.run(router.stats()).unwrap();
+    // subscription may consist of several tasks
+    assert!(stats.subscriptions > 0);
+
+    drop(res);
+    core.turn(Some(Duration::new(0, 0)));
+    let stats = core.run(router.stats()).unwrap();
+    assert_eq!(stats.resolves, 0);
+    assert_eq!(stats.subscriptions, 0);
+}
+
+#[test]
+fn test_rapid_updates() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.add_service(&name, Address::parse_list(&["127.0.0.1:80"]).unwrap());
+    let (router, up) = Router::updating_config(&cfg.done(), &handle);
+
+    let mut streams = (0..100).map(|_| router.subscribe(&name))
+        .collect::<Vec<_>>();
+    core.turn(Some(Duration::new(0, 0)));
+    for s in &mut streams {
+        let res = core.run(lazy(|| Ok::<_, ()>(s.poll()))).unwrap();
+        assert!(matches_ready(res));
+    }
+
+    for i in 2..12 {
+        let addr = format!("127.0.0.{}:80", i);
+        cfg.add_service(&name, Address::parse_list(&[addr]).unwrap());
+        up.update(&cfg.done());
+    }
+    // all subscriptions are restarted and deliver the last value
+    // within a single iteration of the event loop
+    core.turn(Some(Duration::new(0, 0)));
+    let expected = Address::parse_list(&["127.0.0.11:80"]).unwrap();
+    for s in &mut streams {
+        match core.run(lazy(|| Ok::<_, ()>(s.poll()))).unwrap() {
+            Ok(futures::Async::Ready(Some(ref x))) if *x == expected => {}
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+}
+
+/// The first subscription fails, the next ones yield an address
+#[derive(Debug)]
+struct FailsOnce(AtomicUsize);
+
+impl HostResolve for FailsOnce {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        err(Error::NameNotFound)
+    }
+}
+
+impl Resolve for FailsOnce {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        err(Error::NameNotFound)
+    }
+}
+
+impl HostSubscribe for FailsOnce {
+    type HostStream = IntoStream<Empty<IpList, Error>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        empty().into_stream()
+    }
+}
+
+impl Subscribe for FailsOnce {
+    type Stream = Box<Stream<Item=Address, Error=Error> + Send>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+            let e = Error::TemporaryError("backend is down".into());
+            Box::new(once(Err(e)))
+        } else {
+            Box::new(once(Ok(["127.0.0.1:1234".parse().unwrap()][..].into()))
+                .chain(empty().into_stream()))
+        }
+    }
+}
+
+#[test]
+fn test_subscribe_with_errors() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let name = "_http._tcp.localhost".parse().unwrap();
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(FailsOnce(AtomicUsize::new(0)));
+    cfg.restart_delay(Duration::from_millis(10));
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let (addrs, errors) = router.subscribe_with_errors(&name);
+    let (error, errors) = core.run(errors.into_future())
+        .map_err(|_| ()).unwrap();
+    match error {
+        Some(Error::TemporaryError(..)) => {}
+        e => panic!("unexpected error {:?}", e),
+    }
+
+    let (addr, addrs) = core.run(addrs.into_future())
+        .map_err(|_| ()).unwrap();
+    assert_eq!(addr,
+        Some(["127.0.0.1:1234".parse::<SocketAddr>().unwrap()][..].into()));
+
+    drop(addrs);
+    let (error, _) = core.run(errors.into_future())
+        .map_err(|_| ()).unwrap();
+    assert!(error.is_none());
+}
+
+/// Subscription stream is never ready, but asks to be polled again
+#[derive(Debug)]
+struct Busy(Arc<AtomicUsize>);
+
+struct BusyStream(Arc<AtomicUsize>);
+
+impl Stream for BusyStream {
+    type Item = Address;
+    type Error = Error;
+    fn poll(&mut self) -> futures::Poll<Option<Address>, Error> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        futures::task::current().notify();
+        Ok(futures::Async::NotReady)
+    }
+}
+
+impl HostResolve for Busy {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, name: &Name) -> Self::HostFuture {
+        Mock.resolve_host(name)
+    }
+}
+
+impl Resolve for Busy {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, name: &Name) -> Self::Future {
+        Mock.resolve(name)
+    }
+}
+
+impl HostSubscribe for Busy {
+    type HostStream = <Mock as HostSubscribe>::HostStream;
+    type HostError = Error;
+    fn subscribe_host(&self, name: &Name) -> Self::HostStream {
+        Mock.subscribe_host(name)
+    }
+}
+
+impl Subscribe for Busy {
+    type Stream = BusyStream;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        BusyStream(self.0.clone())
+    }
+}
+
+#[test]
+fn test_subscribe_cancelable() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let polls = Arc::new(AtomicUsize::new(0));
+
+    let mut cfg = Config::new();
+    cfg.set_fallthrough(Busy(polls.clone()));
+    let router = Router::from_config(&cfg.done(), &handle);
+
+    let (stream, subscription) = router.subscribe_cancelable(
+        &"_http._tcp.example.org".parse().unwrap());
+    for _ in 0..3 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    assert!(polls.load(Ordering::SeqCst) > 0);
+
+    subscription.cancel();
+    core.turn(Some(Duration::new(0, 0)));
+    let after_cancel = polls.load(Ordering::SeqCst);
+    for _ in 0..3 {
+        core.turn(Some(Duration::new(0, 0)));
+    }
+    assert_eq!(polls.load(Ordering::SeqCst), after_cancel);
+
+    let (value, _) = core.run(stream.into_future())
+        .map_err(|_| ()).unwrap();
+    assert!(value.is_none());
+    let stats = core.run(router.stats()).unwrap();
+    assert_eq!(stats.subscriptions, 0);
+}
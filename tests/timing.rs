@@ -0,0 +1,267 @@
+extern crate abstract_ns;
+extern crate futures;
+extern crate ns_router;
+extern crate tokio_core;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream, lazy};
+use futures::future::{FutureResult, Empty, IntoStream, ok, err, empty};
+use futures::stream::{once, Chain, Once};
+use futures::sync::mpsc::unbounded;
+use abstract_ns::{HostSubscribe, Subscribe, Name, Address, IpList, Error};
+use abstract_ns::{Resolve, HostResolve};
+use ns_router::{Config, Router, Timing, SuffixOpts};
+use tokio_core::reactor::Timeout;
+
+
+/// Counts subscriptions, and never yields anything after the first value
+#[derive(Debug)]
+struct Counting(Arc<AtomicUsize>);
+
+/// Never resolves anything
+#[derive(Debug)]
+struct Never;
+
+/// Counts subscriptions, every subscription fails immediately
+#[derive(Debug)]
+struct Failing(Arc<AtomicUsize>);
+
+impl HostResolve for Counting {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        ok(vec!["127.0.0.2".parse().unwrap()].into())
+    }
+}
+
+impl Resolve for Counting {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        ok(["127.0.0.2:443".parse().unwrap()][..].into())
+    }
+}
+
+impl HostSubscribe for Counting {
+    type HostStream = Chain<Once<IpList, Error>,
+                            IntoStream<Empty<IpList, Error>>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        once(Ok(vec!["127.0.0.2".parse().unwrap()].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl Subscribe for Counting {
+    type Stream = Chain<Once<Address, Error>,
+                        IntoStream<Empty<Address, Error>>>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        once(Ok(["127.0.0.2:443".parse().unwrap()][..].into()))
+            .chain(empty().into_stream())
+    }
+}
+
+impl HostResolve for Never {
+    type HostFuture = Empty<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        empty()
+    }
+}
+
+impl Resolve for Never {
+    type Future = Empty<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        empty()
+    }
+}
+
+impl HostSubscribe for Never {
+    type HostStream = IntoStream<Empty<IpList, Error>>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        empty().into_stream()
+    }
+}
+
+impl Subscribe for Never {
+    type Stream = IntoStream<Empty<Address, Error>>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        empty().into_stream()
+    }
+}
+
+impl HostResolve for Failing {
+    type HostFuture = FutureResult<IpList, Error>;
+    fn resolve_host(&self, _name: &Name) -> Self::HostFuture {
+        err(Error::TemporaryError("backend is down".into()))
+    }
+}
+
+impl Resolve for Failing {
+    type Future = FutureResult<Address, Error>;
+    fn resolve(&self, _name: &Name) -> Self::Future {
+        err(Error::TemporaryError("backend is down".into()))
+    }
+}
+
+impl HostSubscribe for Failing {
+    type HostStream = Once<IpList, Error>;
+    type HostError = Error;
+    fn subscribe_host(&self, _name: &Name) -> Self::HostStream {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        once(Err(Error::TemporaryError("backend is down".into())))
+    }
+}
+
+impl Subscribe for Failing {
+    type Stream = Once<Address, Error>;
+    type Error = Error;
+    fn subscribe(&self, _name: &Name) -> Self::Stream {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        once(Err(Error::TemporaryError("backend is down".into())))
+    }
+}
+
+#[test]
+fn timing_update() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let counter = Arc::new(AtomicUsize::new(0));
+
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .add_suffix("counted.example", Counting(counter.clone()))
+        .add_suffix("slow.example", Never)
+        .done();
+    let (timing_tx, timing_rx) = unbounded();
+    let router = Router::from_config_and_timing(
+        once(Ok(cfg)).chain(empty().into_stream()),
+        timing_rx.map_err(|()| unreachable!()),
+        &handle);
+
+    let res = core.run(lazy(|| {
+        router.subscribe(&"x.counted.example".parse().unwrap())
+            .into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.2:443".parse::<SocketAddr>().unwrap()][..].into()));
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    timing_tx.unbounded_send(Timing {
+        convergence_delay: Duration::from_millis(10),
+        .. Timing::default()
+    }).unwrap();
+    // let the router pick up the timing
+    core.run(Timeout::new(Duration::from_millis(50), &handle).unwrap())
+        .unwrap();
+
+    let start = Instant::now();
+    let many = core.run(lazy(|| {
+        router.subscribe_many(&["127.0.0.1:80", "x.slow.example"], 80)
+            .into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(many.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // existing subscription is not restarted
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn suffix_restart_delay() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+    let fast = Arc::new(AtomicUsize::new(0));
+    let slow = Arc::new(AtomicUsize::new(0));
+
+    let cfg = Config::new()
+        .restart_delay(Duration::from_secs(10))
+        .add_suffix_with_opts("fast.example", Failing(fast.clone()),
+            SuffixOpts {
+                restart_delay: Some(Duration::from_millis(10)),
+                .. SuffixOpts::default()
+            })
+        .set_fallthrough(Failing(slow.clone()))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let _fast = router.subscribe(&"x.fast.example".parse().unwrap());
+    let _slow = router.subscribe(&"x.slow.example".parse().unwrap());
+    core.run(Timeout::new(Duration::from_millis(200), &handle).unwrap())
+        .unwrap();
+    assert!(fast.load(Ordering::SeqCst) >= 3);
+    assert_eq!(slow.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn suffix_convergence_delay() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .convergence_delay(Duration::from_secs(10))
+        .add_suffix_with_opts("slow.example", Never, SuffixOpts {
+            convergence_delay: Some(Duration::from_millis(10)),
+            .. SuffixOpts::default()
+        })
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let start = Instant::now();
+    let res = core.run(lazy(|| {
+        router.subscribe_many(&["127.0.0.1:80", "x.slow.example"], 80)
+            .into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.1:80".parse::<SocketAddr>().unwrap()][..].into()));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_subscribe_with_init_timeout() {
+    let mut core = tokio_core::reactor::Core::new().unwrap();
+    let handle = core.handle();
+
+    let cfg = Config::new()
+        .add_suffix("never", Never)
+        .add_suffix("counting", Counting(Arc::new(AtomicUsize::new(0))))
+        .done();
+    let router = Router::from_config(&cfg, &handle);
+
+    let start = Instant::now();
+    let res = core.run(lazy(|| {
+        router.subscribe_with_init_timeout(&"a.never".parse().unwrap(),
+            Duration::from_millis(50), &handle).into_future()
+    }));
+    let elapsed = start.elapsed();
+    match res {
+        Err((Error::TemporaryError(ref e), _))
+            if e.to_string() == "initial resolution timed out" => {}
+        Err((e, _)) => panic!("unexpected error {:?}", e),
+        Ok((v, _)) => panic!("unexpected value {:?}", v),
+    }
+    assert!(elapsed >= Duration::from_millis(50));
+    assert!(elapsed < Duration::from_millis(500));
+
+    let res = core.run(lazy(|| {
+        router.subscribe_with_init_timeout(&"a.counting".parse().unwrap(),
+            Duration::from_millis(50), &handle).into_future()
+    })).map_err(|(e, _)| e).unwrap();
+    assert_eq!(res.0,
+        Some(["127.0.0.2:443".parse::<SocketAddr>().unwrap()][..].into()));
+
+    // no error after the first value
+    let timeout = Timeout::new(Duration::from_millis(100), &handle).unwrap();
+    let res = core.run(res.1.into_future().select2(timeout));
+    match res {
+        Ok(futures::future::Either::B(..)) => {}
+        _ => panic!("stream must not yield"),
+    }
+}